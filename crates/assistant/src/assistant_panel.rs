@@ -954,6 +954,7 @@ impl AssistantPanel {
             line_height: relative(1.3).into(),
             background_color: None,
             underline: None,
+            strikethrough: None,
             white_space: WhiteSpace::Normal,
         };
         EditorElement::new(
@@ -3146,6 +3147,7 @@ impl InlineAssistant {
             line_height: relative(1.3).into(),
             background_color: None,
             underline: None,
+            strikethrough: None,
             white_space: WhiteSpace::Normal,
         };
         EditorElement::new(
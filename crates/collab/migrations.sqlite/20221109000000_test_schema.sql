@@ -338,7 +338,12 @@ CREATE TABLE "notifications" (
     "entity_id" INTEGER,
     "content" TEXT,
     "is_read" BOOLEAN NOT NULL DEFAULT FALSE,
-    "response" BOOLEAN
+    "response" BOOLEAN,
+    "read_at" TIMESTAMP,
+    "actor_id" INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    "idempotency_key" BLOB,
+    "deleted_at" TIMESTAMP,
+    "updated_at" TIMESTAMP NOT NULL default CURRENT_TIMESTAMP
 );
 
 CREATE INDEX
@@ -346,6 +351,22 @@ CREATE INDEX
     ON "notifications"
     ("recipient_id", "is_read", "kind", "entity_id");
 
+CREATE UNIQUE INDEX
+    "index_notifications_on_recipient_id_idempotency_key"
+    ON "notifications"
+    ("recipient_id", "idempotency_key");
+
+CREATE INDEX
+    "index_notifications_on_recipient_id_actor_id_created_at"
+    ON "notifications"
+    ("recipient_id", "actor_id", "created_at");
+
+CREATE TABLE "notification_mutes" (
+    "recipient_id" INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    "kind" INTEGER NOT NULL REFERENCES notification_kinds (id),
+    PRIMARY KEY (recipient_id, kind)
+);
+
 CREATE TABLE contributors (
     user_id INTEGER REFERENCES users(id),
     signed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
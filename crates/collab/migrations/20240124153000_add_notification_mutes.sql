@@ -0,0 +1,5 @@
+CREATE TABLE "notification_mutes" (
+    "recipient_id" INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    "kind" INTEGER NOT NULL REFERENCES notification_kinds (id),
+    PRIMARY KEY (recipient_id, kind)
+);
@@ -0,0 +1 @@
+ALTER TABLE "notifications" ADD COLUMN "read_at" TIMESTAMP;
@@ -0,0 +1,31 @@
+ALTER TABLE "notifications" ADD COLUMN "actor_id" INTEGER REFERENCES users (id) ON DELETE SET NULL;
+
+-- Reads an integer field from a notification's content, returning NULL rather than
+-- failing when the content isn't valid JSON or the field isn't an integer.
+CREATE FUNCTION pg_temp.notification_content_integer(content TEXT, field TEXT) RETURNS INTEGER AS $$
+BEGIN
+    RETURN (content::json->>field)::integer;
+EXCEPTION WHEN others THEN
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+WITH "actors" AS (
+    SELECT
+        "notifications"."id",
+        CASE "notification_kinds"."name"
+            WHEN 'ContactRequest' THEN "notifications"."entity_id"
+            WHEN 'ContactRequestAccepted' THEN "notifications"."entity_id"
+            WHEN 'ChannelInvitation' THEN pg_temp.notification_content_integer("notifications"."content", 'inviter_id')
+            WHEN 'ChannelMessageMention' THEN pg_temp.notification_content_integer("notifications"."content", 'sender_id')
+        END AS "actor_id"
+    FROM "notifications"
+    JOIN "notification_kinds" ON "notification_kinds"."id" = "notifications"."kind"
+)
+UPDATE "notifications"
+SET "actor_id" = "actors"."actor_id"
+FROM "actors"
+JOIN "users" ON "users"."id" = "actors"."actor_id"
+WHERE "notifications"."id" = "actors"."id";
+
+DROP FUNCTION pg_temp.notification_content_integer(TEXT, TEXT);
@@ -0,0 +1,6 @@
+ALTER TABLE "notifications" ADD COLUMN "idempotency_key" UUID;
+
+CREATE UNIQUE INDEX
+    "index_notifications_on_recipient_id_idempotency_key"
+    ON "notifications"
+    ("recipient_id", "idempotency_key");
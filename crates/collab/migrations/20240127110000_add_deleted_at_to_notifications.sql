@@ -0,0 +1,3 @@
+-- Removed notifications are kept until they're pruned, with their removal time, so that
+-- clients that still have them can be told to remove them too.
+ALTER TABLE "notifications" ADD "deleted_at" TIMESTAMP;
@@ -0,0 +1,5 @@
+-- Covers counting the notifications an actor has recently sent a recipient, for rate limiting.
+CREATE INDEX
+    "index_notifications_on_recipient_id_actor_id_created_at"
+    ON "notifications"
+    ("recipient_id", "actor_id", "created_at");
@@ -0,0 +1,6 @@
+-- When each notification was last changed, so that clients that already have it can be
+-- told about changes such as another notification being coalesced into it.
+ALTER TABLE "notifications" ADD "updated_at" TIMESTAMP;
+UPDATE "notifications" SET "updated_at" = GREATEST("created_at", "read_at", "deleted_at");
+ALTER TABLE "notifications" ALTER COLUMN "updated_at" SET DEFAULT now();
+ALTER TABLE "notifications" ALTER COLUMN "updated_at" SET NOT NULL;
@@ -3,7 +3,7 @@ use collab::{
     env::load_dotenv,
     executor::Executor,
 };
-use db::{ConnectOptions, Database};
+use db::{ConnectOptions, Database, NotificationOptions};
 use serde::{de::DeserializeOwned, Deserialize};
 use std::{fmt::Write, fs};
 
@@ -25,9 +25,13 @@ async fn main() {
     }
 
     let database_url = std::env::var("DATABASE_URL").expect("missing DATABASE_URL env var");
-    let db = Database::new(ConnectOptions::new(database_url), Executor::Production)
-        .await
-        .expect("failed to connect to postgres database");
+    let db = Database::new(
+        ConnectOptions::new(database_url),
+        NotificationOptions::default(),
+        Executor::Production,
+    )
+    .await
+    .expect("failed to connect to postgres database");
     let client = reqwest::Client::new();
 
     // Create admin users for all of the users in `.admins.toml` or `.admins.default.toml`.
@@ -12,7 +12,7 @@ use crate::{executor::Executor, Error, Result};
 use anyhow::anyhow;
 use collections::{BTreeMap, HashMap, HashSet};
 use dashmap::DashMap;
-use futures::StreamExt;
+use futures::{channel::mpsc, StreamExt};
 use rand::{prelude::StdRng, Rng, SeedableRng};
 use rpc::{
     proto::{self},
@@ -34,6 +34,7 @@ use std::{
     fmt::Write as _,
     future::Future,
     marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut},
     path::Path,
     rc::Rc,
@@ -45,6 +46,11 @@ use tokio::sync::{Mutex, OwnedMutexGuard};
 
 pub use ids::*;
 pub use queries::contributors::ContributorSelector;
+pub use queries::notifications::{
+    decode_notification_cursor, effective_notification_limit, encode_notification_cursor,
+    DEFAULT_NOTIFICATION_PAGE_SIZE, MAX_NOTIFICATION_PAGE_SIZE, MAX_SEARCHED_NOTIFICATIONS,
+    NOTIFICATION_COUNT_FIELD,
+};
 pub use sea_orm::ConnectOptions;
 pub use tables::user::Model as User;
 
@@ -56,8 +62,9 @@ pub struct Database {
     rooms: DashMap<RoomId, Arc<Mutex<()>>>,
     rng: Mutex<StdRng>,
     executor: Executor,
-    notification_kinds_by_id: HashMap<NotificationKindId, &'static str>,
-    notification_kinds_by_name: HashMap<String, NotificationKindId>,
+    notification_kinds_by_id: DashMap<NotificationKindId, &'static str>,
+    notification_kinds_by_name: DashMap<String, NotificationKindId>,
+    notification_options: NotificationOptions,
     #[cfg(test)]
     runtime: Option<tokio::runtime::Runtime>,
 }
@@ -66,15 +73,20 @@ pub struct Database {
 // separate files in the `queries` folder.
 impl Database {
     /// Connects to the database with the given options
-    pub async fn new(options: ConnectOptions, executor: Executor) -> Result<Self> {
+    pub async fn new(
+        options: ConnectOptions,
+        notification_options: NotificationOptions,
+        executor: Executor,
+    ) -> Result<Self> {
         sqlx::any::install_default_drivers();
         Ok(Self {
             options: options.clone(),
             pool: sea_orm::Database::connect(options).await?,
             rooms: DashMap::with_capacity(16384),
             rng: Mutex::new(StdRng::seed_from_u64(0)),
-            notification_kinds_by_id: HashMap::default(),
-            notification_kinds_by_name: HashMap::default(),
+            notification_kinds_by_id: DashMap::default(),
+            notification_kinds_by_name: DashMap::default(),
+            notification_options,
             executor,
             #[cfg(test)]
             runtime: None,
@@ -145,10 +157,13 @@ impl Database {
         let body = async {
             let mut i = 0;
             loop {
-                let (tx, result) = self.with_transaction(&f).await?;
+                let (tx, result, changes) = self.with_transaction(&f).await?;
                 match result {
                     Ok(result) => match tx.commit().await.map_err(Into::into) {
-                        Ok(()) => return Ok(result),
+                        Ok(()) => {
+                            self.apply_notification_changes(changes);
+                            return Ok(result);
+                        }
                         Err(error) => {
                             if !self.retry_on_serialization_error(&error, i).await {
                                 return Err(error);
@@ -178,13 +193,14 @@ impl Database {
         let body = async {
             let mut i = 0;
             loop {
-                let (tx, result) = self.with_transaction(&f).await?;
+                let (tx, result, changes) = self.with_transaction(&f).await?;
                 match result {
                     Ok(Some((room_id, data))) => {
                         let lock = self.rooms.entry(room_id).or_default().clone();
                         let _guard = lock.lock_owned().await;
                         match tx.commit().await.map_err(Into::into) {
                             Ok(()) => {
+                                self.apply_notification_changes(changes);
                                 return Ok(Some(RoomGuard {
                                     data,
                                     _guard,
@@ -199,7 +215,10 @@ impl Database {
                         }
                     }
                     Ok(None) => match tx.commit().await.map_err(Into::into) {
-                        Ok(()) => return Ok(None),
+                        Ok(()) => {
+                            self.apply_notification_changes(changes);
+                            return Ok(None);
+                        }
                         Err(error) => {
                             if !self.retry_on_serialization_error(&error, i).await {
                                 return Err(error);
@@ -233,10 +252,11 @@ impl Database {
             loop {
                 let lock = self.rooms.entry(room_id).or_default().clone();
                 let _guard = lock.lock_owned().await;
-                let (tx, result) = self.with_transaction(&f).await?;
+                let (tx, result, changes) = self.with_transaction(&f).await?;
                 match result {
                     Ok(data) => match tx.commit().await.map_err(Into::into) {
                         Ok(()) => {
+                            self.apply_notification_changes(changes);
                             return Ok(RoomGuard {
                                 data,
                                 _guard,
@@ -263,7 +283,29 @@ impl Database {
         self.run(body).await
     }
 
-    async fn with_transaction<F, Fut, T>(&self, f: &F) -> Result<(DatabaseTransaction, Result<T>)>
+    fn apply_notification_changes(&self, changes: PendingNotificationChanges) {
+        for (id, name) in changes.kinds {
+            self.notification_kinds_by_name.insert(name.to_string(), id);
+            self.notification_kinds_by_id.insert(id, name);
+        }
+        self.send_notification_events(changes.events);
+    }
+
+    fn send_notification_events(&self, events: impl IntoIterator<Item = NotificationEvent>) {
+        if let Some(sender) = &self.notification_options.event_sender {
+            for event in events {
+                sender.unbounded_send(event).ok();
+            }
+        }
+    }
+
+    /// Runs the block in a new transaction, returning the transaction so that it can be
+    /// committed or rolled back, along with the changes to notifications that should be
+    /// applied if it's committed.
+    async fn with_transaction<F, Fut, T>(
+        &self,
+        f: &F,
+    ) -> Result<(DatabaseTransaction, Result<T>, PendingNotificationChanges)>
     where
         F: Send + Fn(TransactionHandle) -> Fut,
         Fut: Send + Future<Output = Result<T>>,
@@ -274,14 +316,22 @@ impl Database {
             .await?;
 
         let mut tx = Arc::new(Some(tx));
-        let result = f(TransactionHandle(tx.clone())).await;
+        let notification_changes = Arc::new(parking_lot::Mutex::new(
+            PendingNotificationChanges::default(),
+        ));
+        let result = f(TransactionHandle {
+            tx: tx.clone(),
+            notification_changes: notification_changes.clone(),
+        })
+        .await;
+        let changes = mem::take(&mut *notification_changes.lock());
         let Some(tx) = Arc::get_mut(&mut tx).and_then(|tx| tx.take()) else {
             return Err(anyhow!(
                 "couldn't complete transaction because it's still in use"
             ))?;
         };
 
-        Ok((tx, result))
+        Ok((tx, result, changes))
     }
 
     async fn run<F, T>(&self, future: F) -> Result<T>
@@ -343,14 +393,27 @@ fn is_serialization_error(error: &Error) -> bool {
     }
 }
 
-/// A handle to a [`DatabaseTransaction`].
-pub struct TransactionHandle(Arc<Option<DatabaseTransaction>>);
+/// The notification kinds that were added and the notification events that occurred
+/// within a transaction, which are only cached and sent once the transaction is
+/// committed.
+#[derive(Default)]
+struct PendingNotificationChanges {
+    kinds: Vec<(NotificationKindId, &'static str)>,
+    events: Vec<NotificationEvent>,
+}
+
+/// A handle to a [`DatabaseTransaction`], along with the changes to notifications made
+/// within it, which are discarded along with the handle unless it's committed.
+pub struct TransactionHandle {
+    tx: Arc<Option<DatabaseTransaction>>,
+    notification_changes: Arc<parking_lot::Mutex<PendingNotificationChanges>>,
+}
 
 impl Deref for TransactionHandle {
     type Target = DatabaseTransaction;
 
     fn deref(&self) -> &Self::Target {
-        self.0.as_ref().as_ref().unwrap()
+        self.tx.as_ref().as_ref().unwrap()
     }
 }
 
@@ -402,6 +465,92 @@ impl Contact {
 
 pub type NotificationBatch = Vec<(UserId, proto::Notification)>;
 
+/// The outcome of [`Database::create_notification`] and
+/// [`Database::create_or_coalesce_notification`], which distinguishes the reasons
+/// that a notification wasn't created, so that only new notifications are broadcast.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CreateNotificationResult {
+    /// The notification was created.
+    Inserted(proto::Notification),
+    /// The notification was coalesced into the recipient's existing unread notification
+    /// with the same kind and entity id, which is given with its updated count.
+    Coalesced(proto::Notification),
+    /// The recipient already has the notification with the given id, such as an unread
+    /// one with the same kind and entity id, or one created with the same idempotency key.
+    Duplicate(NotificationId),
+    /// The recipient has muted the notification's kind.
+    Muted,
+    /// The notification's actor has sent the recipient too many notifications recently,
+    /// according to the [`NotificationOptions::rate_limit`].
+    RateLimited,
+}
+
+impl CreateNotificationResult {
+    /// Returns the notification if it was created.
+    pub fn inserted(self) -> Option<proto::Notification> {
+        match self {
+            Self::Inserted(notification) => Some(notification),
+            Self::Coalesced(_) | Self::Duplicate(_) | Self::Muted | Self::RateLimited => None,
+        }
+    }
+
+    /// Returns the notification if it was created or coalesced into an existing one.
+    pub fn notification(self) -> Option<proto::Notification> {
+        match self {
+            Self::Inserted(notification) | Self::Coalesced(notification) => Some(notification),
+            Self::Duplicate(_) | Self::Muted | Self::RateLimited => None,
+        }
+    }
+}
+
+/// Limits how many notifications a single actor can send to a single recipient within a
+/// sliding window, so that a misbehaving client can't spam another user.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NotificationRateLimit {
+    /// The number of notifications the actor can send the recipient within the window.
+    pub max_notifications: u64,
+    pub window: time::Duration,
+}
+
+/// Configures how a [`Database`] reports changes to notifications.
+#[derive(Clone, Default)]
+pub struct NotificationOptions {
+    /// A sender that is sent an event whenever a notification is created, read, or
+    /// removed, other than when old notifications are pruned. Events for changes made
+    /// within a transaction are only sent once that transaction is committed.
+    pub event_sender: Option<mpsc::UnboundedSender<NotificationEvent>>,
+    /// The limit on how many notifications a given actor can send a given recipient,
+    /// or `None` for no limit.
+    ///
+    /// The notifications within the window are counted whether or not they've since been
+    /// read or deleted, but notifications that were coalesced into an existing one only
+    /// count once.
+    pub rate_limit: Option<NotificationRateLimit>,
+}
+
+/// A change to a notification, which is sent to [`NotificationOptions::event_sender`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct NotificationEvent {
+    pub kind: NotificationEventKind,
+    pub recipient_id: UserId,
+    pub notification: proto::Notification,
+}
+
+/// The user whose action caused a notification, as returned by
+/// [`Database::get_notifications_with_actors`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotificationActor {
+    pub id: UserId,
+    pub github_login: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotificationEventKind {
+    Created,
+    Read,
+    Removed,
+}
+
 pub struct CreatedChannelMessage {
     pub message_id: MessageId,
     pub participant_connection_ids: Vec<ConnectionId>,
@@ -349,9 +349,12 @@ impl Database {
                         inviter_id: inviter_id.to_proto(),
                     },
                     true,
-                    &*tx,
+                    None,
+                    &tx,
                 )
                 .await?
+                .inserted()
+                .map(|notification| (invitee_id, notification))
                 .into_iter()
                 .collect();
 
@@ -473,7 +476,7 @@ impl Database {
                             inviter_id: Default::default(),
                         },
                         accept,
-                        &*tx,
+                        &tx,
                     )
                     .await?
                     .into_iter()
@@ -547,7 +550,7 @@ impl Database {
                             channel_name: Default::default(),
                             inviter_id: Default::default(),
                         },
-                        &*tx,
+                        &tx,
                     )
                     .await?,
             })
@@ -177,9 +177,12 @@ impl Database {
                         sender_id: sender_id.to_proto(),
                     },
                     true,
-                    &*tx,
+                    None,
+                    &tx,
                 )
                 .await?
+                .inserted()
+                .map(|notification| (receiver_id, notification))
                 .into_iter()
                 .collect())
         })
@@ -227,7 +230,7 @@ impl Database {
                         rpc::Notification::ContactRequest {
                             sender_id: requester_id.to_proto(),
                         },
-                        &*tx,
+                        &tx,
                     )
                     .await?;
             }
@@ -335,7 +338,7 @@ impl Database {
                         sender_id: requester_id.to_proto(),
                     },
                     accept,
-                    &*tx,
+                    &tx,
                 )
                 .await?,
             );
@@ -348,9 +351,12 @@ impl Database {
                             responder_id: responder_id.to_proto(),
                         },
                         true,
-                        &*tx,
+                        None,
+                        &tx,
                     )
-                    .await?,
+                    .await?
+                    .inserted()
+                    .map(|notification| (requester_id, notification)),
                 );
             }
 
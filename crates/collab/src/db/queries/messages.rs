@@ -263,8 +263,10 @@ impl Database {
             match result {
                 TryInsertResult::Inserted(result) => {
                     message_id = result.last_insert_id;
-                    let mentioned_user_ids =
-                        mentions.iter().map(|m| m.user_id).collect::<HashSet<_>>();
+                    let mentioned_user_ids = mentions
+                        .iter()
+                        .map(|m| UserId::from_proto(m.user_id))
+                        .collect::<Vec<_>>();
 
                     let mentions = mentions
                         .iter()
@@ -289,21 +291,19 @@ impl Database {
                             .await?;
                     }
 
-                    for mentioned_user in mentioned_user_ids {
-                        notifications.extend(
-                            self.create_notification(
-                                UserId::from_proto(mentioned_user),
-                                rpc::Notification::ChannelMessageMention {
-                                    message_id: message_id.to_proto(),
-                                    sender_id: user_id.to_proto(),
-                                    channel_id: channel_id.to_proto(),
-                                },
-                                false,
-                                &*tx,
-                            )
-                            .await?,
-                        );
-                    }
+                    notifications.extend(
+                        self.create_notifications(
+                            &mentioned_user_ids,
+                            rpc::Notification::ChannelMessageMention {
+                                message_id: message_id.to_proto(),
+                                sender_id: user_id.to_proto(),
+                                channel_id: channel_id.to_proto(),
+                            },
+                            false,
+                            &tx,
+                        )
+                        .await?,
+                    );
 
                     self.observe_channel_message_internal(channel_id, user_id, message_id, &*tx)
                         .await?;
@@ -342,14 +342,14 @@ impl Database {
                 .await?;
             let mut batch = NotificationBatch::default();
             batch.extend(
-                self.mark_notification_as_read(
+                self.mark_notification_as_read_by_entity(
                     user_id,
                     &Notification::ChannelMessageMention {
                         message_id: message_id.to_proto(),
                         sender_id: Default::default(),
                         channel_id: Default::default(),
                     },
-                    &*tx,
+                    &tx,
                 )
                 .await?,
             );
@@ -1,5 +1,48 @@
 use super::*;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter, IntCounter};
 use rpc::Notification;
+use sea_orm::{
+    sea_query::{Func, LikeExpr},
+    QueryTrait, TryInsertResult,
+};
+use time::OffsetDateTime;
+
+lazy_static! {
+    static ref METRIC_INVALID_NOTIFICATIONS: IntCounter = register_int_counter!(
+        "invalid_notifications",
+        "number of notifications skipped because their kind is unknown or their content is invalid"
+    )
+    .unwrap();
+}
+
+/// The field of a notification's content that counts how many identical
+/// notifications have been coalesced into it. Notifications whose content
+/// doesn't have this field were only created once.
+pub const NOTIFICATION_COUNT_FIELD: &str = "count";
+
+/// The number of notifications returned when a limit of zero is requested.
+pub const DEFAULT_NOTIFICATION_PAGE_SIZE: usize = 50;
+
+/// The largest number of notifications returned by a single query, regardless of
+/// the limit requested.
+pub const MAX_NOTIFICATION_PAGE_SIZE: usize = 1000;
+
+/// The largest number of notifications that a single search looks through. Queries
+/// that can't be narrowed down by the database are matched against each notification
+/// in turn, so this bounds the work done for them.
+pub const MAX_SEARCHED_NOTIFICATIONS: u64 = 5000;
+
+/// Returns the number of notifications that are returned for the given limit: a
+/// limit of zero uses [`DEFAULT_NOTIFICATION_PAGE_SIZE`], and larger limits are
+/// clamped to [`MAX_NOTIFICATION_PAGE_SIZE`].
+pub fn effective_notification_limit(limit: usize) -> usize {
+    if limit == 0 {
+        DEFAULT_NOTIFICATION_PAGE_SIZE
+    } else {
+        limit.min(MAX_NOTIFICATION_PAGE_SIZE)
+    }
+}
 
 impl Database {
     /// Initializes the different kinds of notifications by upserting records for them.
@@ -21,7 +64,7 @@ impl Database {
         }
 
         for name in Notification::all_variant_names() {
-            if let Some(id) = self.notification_kinds_by_name.get(*name).copied() {
+            if let Some(id) = self.notification_kinds_by_name.get(*name).map(|id| *id) {
                 self.notification_kinds_by_id.insert(id, name);
             }
         }
@@ -29,17 +72,148 @@ impl Database {
         Ok(())
     }
 
-    /// Returns the notifications for the given recipient.
+    fn send_notification_event(
+        &self,
+        kind: NotificationEventKind,
+        recipient_id: UserId,
+        notification: &proto::Notification,
+    ) {
+        self.send_notification_events([NotificationEvent {
+            kind,
+            recipient_id,
+            notification: notification.clone(),
+        }]);
+    }
+
+    /// Queues an event for a change made within the given transaction, which is sent once
+    /// the transaction is committed, and discarded if it's rolled back.
+    fn send_notification_event_after_commit(
+        &self,
+        kind: NotificationEventKind,
+        recipient_id: UserId,
+        notification: &proto::Notification,
+        tx: &TransactionHandle,
+    ) {
+        if self.notification_options.event_sender.is_some() {
+            tx.notification_changes
+                .lock()
+                .events
+                .push(NotificationEvent {
+                    kind,
+                    recipient_id,
+                    notification: notification.clone(),
+                });
+        }
+    }
+
+    /// Returns the most recent notifications for the given recipient that are older
+    /// than the notification with the given ID, if any, ordered from oldest to newest.
+    /// If `kinds` is given, only notifications with those kinds are returned.
+    ///
+    /// The limit is adjusted with [`effective_notification_limit`].
     pub async fn get_notifications(
         &self,
         recipient_id: UserId,
         limit: usize,
         before_id: Option<NotificationId>,
+        kinds: Option<&[&str]>,
     ) -> Result<Vec<proto::Notification>> {
+        let (notifications, _) = self
+            .get_notifications_page_internal(
+                recipient_id,
+                effective_notification_limit(limit),
+                before_id,
+                kinds,
+                false,
+            )
+            .await?;
+        Ok(notifications)
+    }
+
+    /// Returns a page of the most recent notifications for the given recipient, ordered
+    /// from oldest to newest, starting before the given cursor. The returned cursor
+    /// retrieves the next page of older notifications, and is `None` if there are none.
+    pub async fn get_notifications_by_cursor(
+        &self,
+        recipient_id: UserId,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<(Vec<proto::Notification>, Option<String>)> {
+        let before_id = cursor
+            .as_deref()
+            .map(decode_notification_cursor)
+            .transpose()?;
+        // Retrieve one extra notification to determine whether there's another page.
+        let limit = effective_notification_limit(limit);
+        let (mut notifications, _) = self
+            .get_notifications_page_internal(recipient_id, limit + 1, before_id, None, false)
+            .await?;
+        let next_cursor = if notifications.len() > limit {
+            notifications.remove(0);
+            notifications.first().map(|notification| {
+                encode_notification_cursor(NotificationId::from_proto(notification.id))
+            })
+        } else {
+            None
+        };
+        Ok((notifications, next_cursor))
+    }
+
+    /// Returns the same notifications as [`Database::get_notifications`], along with
+    /// the total number of notifications the recipient has with the given kinds.
+    pub async fn get_notifications_page(
+        &self,
+        recipient_id: UserId,
+        limit: usize,
+        before_id: Option<NotificationId>,
+        kinds: Option<&[&str]>,
+    ) -> Result<(Vec<proto::Notification>, u64)> {
+        let (notifications, total_count) = self
+            .get_notifications_page_internal(
+                recipient_id,
+                effective_notification_limit(limit),
+                before_id,
+                kinds,
+                true,
+            )
+            .await?;
+        Ok((notifications, total_count.unwrap_or(0)))
+    }
+
+    /// Returns the notifications for a page, along with the total number of
+    /// notifications with the given kinds if `count_total` is true.
+    async fn get_notifications_page_internal(
+        &self,
+        recipient_id: UserId,
+        limit: usize,
+        before_id: Option<NotificationId>,
+        kinds: Option<&[&str]>,
+        count_total: bool,
+    ) -> Result<(Vec<proto::Notification>, Option<u64>)> {
         self.transaction(|tx| async move {
             let mut result = Vec::new();
-            let mut condition =
-                Condition::all().add(notification::Column::RecipientId.eq(recipient_id));
+            let mut condition = Condition::all()
+                .add(notification::Column::RecipientId.eq(recipient_id))
+                .add(notification::Column::DeletedAt.is_null());
+
+            if let Some(kinds) = kinds {
+                let mut kind_ids = Vec::with_capacity(kinds.len());
+                for kind in kinds {
+                    kind_ids.push(self.notification_kind_id(kind, &tx).await?);
+                }
+                condition = condition.add(notification::Column::Kind.is_in(kind_ids));
+            }
+
+            let total_count = if count_total {
+                Some(
+                    notification::Entity::find()
+                        .filter(condition.clone())
+                        .count(&*tx)
+                        .await?,
+                )
+            } else {
+                None
+            };
 
             if let Some(before_id) = before_id {
                 condition = condition.add(notification::Column::Id.lt(before_id));
@@ -52,12 +226,88 @@ impl Database {
                 .stream(&*tx)
                 .await?;
             while let Some(row) = rows.next().await {
-                let row = row?;
-                let kind = row.kind;
-                if let Some(proto) = model_to_proto(self, row) {
+                if let Some(proto) = valid_model_to_proto(self, row?, &tx) {
+                    result.push(proto);
+                }
+            }
+            result.reverse();
+            Ok((result, total_count))
+        })
+        .await
+    }
+
+    /// Returns the notifications for the given recipient that are newer than the
+    /// notification with the given ID, if any, ordered from oldest to newest.
+    ///
+    /// Notification IDs increase in the order the notifications were created, so the
+    /// next page can be retrieved by passing the ID of the last notification returned.
+    pub async fn get_notifications_after(
+        &self,
+        recipient_id: UserId,
+        limit: usize,
+        after_id: Option<NotificationId>,
+    ) -> Result<Vec<proto::Notification>> {
+        self.transaction(|tx| async move {
+            let mut result = Vec::new();
+            let mut condition = Condition::all()
+                .add(notification::Column::RecipientId.eq(recipient_id))
+                .add(notification::Column::DeletedAt.is_null());
+
+            if let Some(after_id) = after_id {
+                condition = condition.add(notification::Column::Id.gt(after_id));
+            }
+
+            let mut rows = notification::Entity::find()
+                .filter(condition)
+                .order_by_asc(notification::Column::Id)
+                .limit(effective_notification_limit(limit) as u64)
+                .stream(&*tx)
+                .await?;
+            while let Some(row) = rows.next().await {
+                if let Some(proto) = valid_model_to_proto(self, row?, &tx) {
                     result.push(proto);
-                } else {
-                    log::warn!("unknown notification kind {:?}", kind);
+                }
+            }
+            Ok(result)
+        })
+        .await
+    }
+
+    /// Returns the same notifications as [`Database::get_notifications`], each with the
+    /// user whose action caused it, so that they can be rendered without looking up
+    /// each user separately. The actor is `None` if their account has been deleted.
+    pub async fn get_notifications_with_actors(
+        &self,
+        recipient_id: UserId,
+        limit: usize,
+        before_id: Option<NotificationId>,
+    ) -> Result<Vec<(proto::Notification, Option<NotificationActor>)>> {
+        self.transaction(|tx| async move {
+            let mut result = Vec::new();
+            let mut condition = Condition::all()
+                .add(notification::Column::RecipientId.eq(recipient_id))
+                .add(notification::Column::DeletedAt.is_null());
+
+            if let Some(before_id) = before_id {
+                condition = condition.add(notification::Column::Id.lt(before_id));
+            }
+
+            let mut rows = notification::Entity::find()
+                .select_also(user::Entity)
+                .join(JoinType::LeftJoin, notification::Relation::Actor.def())
+                .filter(condition)
+                .order_by_desc(notification::Column::Id)
+                .limit(effective_notification_limit(limit) as u64)
+                .stream(&*tx)
+                .await?;
+            while let Some(row) = rows.next().await {
+                let (row, actor) = row?;
+                if let Some(proto) = valid_model_to_proto(self, row, &tx) {
+                    let actor = actor.map(|actor| NotificationActor {
+                        id: actor.id,
+                        github_login: actor.github_login,
+                    });
+                    result.push((proto, actor));
                 }
             }
             result.reverse();
@@ -66,50 +316,713 @@ impl Database {
         .await
     }
 
-    /// Creates a notification. If `avoid_duplicates` is set to true, then avoid
-    /// creating a new notification if the given recipient already has an
-    /// unread notification with the given kind and entity id.
+    /// Returns the changes to the given recipient's notifications since the given time,
+    /// so that a reconnecting client that has the notifications up to the one with the
+    /// given ID can catch up without fetching them all again. The time should be when the
+    /// client last caught up, taken before it made that request, so that changes made
+    /// while the request was running are reported again rather than missed.
+    ///
+    /// The first list has the notifications created after the given one, followed by
+    /// older ones that have been updated since, such as by being read or by having
+    /// another notification coalesced into them, which replace the client's copies. The
+    /// second list has the IDs of the older notifications that have been removed since.
+    /// The given notification doesn't need to exist anymore. Notifications that have
+    /// been pruned by [`Database::delete_notifications_older_than`] aren't reported, so
+    /// clients should discard their copies of notifications older than that cutoff.
+    pub async fn notifications_changed_since(
+        &self,
+        recipient_id: UserId,
+        since_id: NotificationId,
+        since: OffsetDateTime,
+    ) -> Result<(Vec<proto::Notification>, Vec<NotificationId>)> {
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+        enum QueryNotificationIds {
+            Id,
+        }
+
+        let since = since.to_offset(time::UtcOffset::UTC);
+        let since = time::PrimitiveDateTime::new(since.date(), since.time());
+        self.transaction(|tx| async move {
+            let mut changed = Vec::new();
+            for condition in [
+                notification::Column::Id.gt(since_id),
+                notification::Column::Id
+                    .lte(since_id)
+                    .and(notification::Column::UpdatedAt.gte(since)),
+            ] {
+                let mut rows = notification::Entity::find()
+                    .filter(
+                        Condition::all()
+                            .add(notification::Column::RecipientId.eq(recipient_id))
+                            .add(notification::Column::DeletedAt.is_null())
+                            .add(condition),
+                    )
+                    .order_by_asc(notification::Column::Id)
+                    .stream(&*tx)
+                    .await?;
+                while let Some(row) = rows.next().await {
+                    if let Some(proto) = valid_model_to_proto(self, row?, &tx) {
+                        changed.push(proto);
+                    }
+                }
+            }
+
+            let removed_ids = notification::Entity::find()
+                .select_only()
+                .column(notification::Column::Id)
+                .filter(
+                    Condition::all()
+                        .add(notification::Column::RecipientId.eq(recipient_id))
+                        .add(notification::Column::Id.lte(since_id))
+                        .add(notification::Column::DeletedAt.gte(since)),
+                )
+                .order_by_asc(notification::Column::Id)
+                .into_values::<_, QueryNotificationIds>()
+                .all(&*tx)
+                .await?;
+
+            Ok((changed, removed_ids))
+        })
+        .await
+    }
+
+    /// Returns up to `limit_per` of the most recent notifications of each of the given
+    /// recipients, in a single query, ordered from oldest to newest. Each recipient is
+    /// limited separately, so that one with many notifications doesn't crowd out the
+    /// others, and every given recipient has an entry, even if it has no notifications.
+    ///
+    /// This is intended for admin tools that inspect notification delivery. Clients
+    /// should use [`Database::get_notifications`] instead.
+    pub async fn get_notifications_for_recipients(
+        &self,
+        recipient_ids: &[UserId],
+        limit_per: usize,
+    ) -> Result<HashMap<UserId, Vec<proto::Notification>>> {
+        let mut values = String::new();
+        for recipient_id in recipient_ids {
+            if !values.is_empty() {
+                values.push_str(", ");
+            }
+            write!(&mut values, "{}", recipient_id).unwrap();
+        }
+        if values.is_empty() {
+            return Ok(HashMap::default());
+        }
+
+        let sql = format!(
+            r#"
+            SELECT
+                *
+            FROM
+            (
+                SELECT
+                    *,
+                    row_number() OVER (
+                        PARTITION BY recipient_id
+                        ORDER BY id DESC
+                    ) as row_number
+                FROM notifications
+                WHERE
+                    recipient_id in ({values})
+                    AND deleted_at IS NULL
+            ) AS recent_notifications
+            WHERE
+                row_number <= {limit}
+            ORDER BY id
+            "#,
+            limit = effective_notification_limit(limit_per),
+        );
+
+        let sql = &sql;
+
+        self.transaction(|tx| async move {
+            let mut result = recipient_ids
+                .iter()
+                .map(|recipient_id| (*recipient_id, Vec::new()))
+                .collect::<HashMap<_, _>>();
+            let stmt = Statement::from_string(self.pool.get_database_backend(), sql.clone());
+            let rows = notification::Entity::find()
+                .from_raw_sql(stmt)
+                .all(&*tx)
+                .await?;
+            for row in rows {
+                let recipient_id = row.recipient_id;
+                if let Some(proto) = valid_model_to_proto(self, row, &tx) {
+                    result.entry(recipient_id).or_default().push(proto);
+                }
+            }
+            Ok(result)
+        })
+        .await
+    }
+
+    /// Returns the notification with the given ID, if it belongs to the given recipient.
+    /// Notifications that belong to other recipients aren't distinguished from missing
+    /// ones, so that their IDs can't be probed.
+    pub async fn get_notification(
+        &self,
+        recipient_id: UserId,
+        id: NotificationId,
+    ) -> Result<Option<proto::Notification>> {
+        self.transaction(|tx| async move {
+            let row = notification::Entity::find_by_id(id)
+                .filter(
+                    Condition::all()
+                        .add(notification::Column::RecipientId.eq(recipient_id))
+                        .add(notification::Column::DeletedAt.is_null()),
+                )
+                .one(&*tx)
+                .await?;
+            Ok(row.and_then(|row| valid_model_to_proto(self, row, &tx)))
+        })
+        .await
+    }
+
+    /// Returns the most recent notifications for the given recipient with a string in
+    /// their content that contains the given text, ignoring case, ordered from newest
+    /// to oldest. If the query is empty, the most recent notifications are returned.
+    ///
+    /// Only the [`MAX_SEARCHED_NOTIFICATIONS`] most recent notifications that may match
+    /// are searched, so older matches are missed, particularly for queries that aren't
+    /// ASCII, which the database can't narrow down.
+    pub async fn search_notifications(
+        &self,
+        recipient_id: UserId,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<proto::Notification>> {
+        let limit = effective_notification_limit(limit);
+        let lowercase_query = query.to_lowercase();
+        let lowercase_query = &lowercase_query;
+
+        // The content is stored as JSON, so narrow down the notifications to those whose
+        // content contains the query as it would be encoded within a JSON string. The
+        // backends only agree on how to lowercase ASCII, so other queries are matched
+        // against every notification.
+        let pattern = if !query.is_empty() && query.is_ascii() {
+            let encoded_query = serde_json::to_string(lowercase_query)?;
+            let encoded_query = &encoded_query[1..encoded_query.len() - 1];
+            let mut pattern = String::with_capacity(encoded_query.len() + 2);
+            pattern.push('%');
+            for c in encoded_query.chars() {
+                if matches!(c, '%' | '_' | '\\') {
+                    pattern.push('\\');
+                }
+                pattern.push(c);
+            }
+            pattern.push('%');
+            Some(pattern)
+        } else {
+            None
+        };
+        let pattern = pattern.as_deref();
+
+        self.transaction(|tx| async move {
+            let mut result = Vec::new();
+            let mut condition = Condition::all()
+                .add(notification::Column::RecipientId.eq(recipient_id))
+                .add(notification::Column::DeletedAt.is_null());
+
+            if let Some(pattern) = pattern {
+                condition = condition.add(
+                    Expr::expr(Func::lower(Expr::col(notification::Column::Content)))
+                        .like(LikeExpr::new(pattern).escape('\\')),
+                );
+            }
+
+            let mut rows = notification::Entity::find()
+                .filter(condition)
+                .order_by_desc(notification::Column::Id)
+                .limit(MAX_SEARCHED_NOTIFICATIONS)
+                .stream(&*tx)
+                .await?;
+            while result.len() < limit {
+                let Some(row) = rows.next().await else {
+                    break;
+                };
+                let row = row?;
+                // Only match the content's strings, rather than its keys or ids.
+                if !query.is_empty()
+                    && !serde_json::from_str::<serde_json::Value>(&row.content)
+                        .is_ok_and(|content| json_strings_contain(&content, lowercase_query))
+                {
+                    continue;
+                }
+                if let Some(proto) = valid_model_to_proto(self, row, &tx) {
+                    result.push(proto);
+                }
+            }
+            Ok(result)
+        })
+        .await
+    }
+
+    /// Returns the number of unread notifications for the given recipient.
+    pub async fn unread_notification_count(&self, recipient_id: UserId) -> Result<u64> {
+        self.transaction(|tx| async move {
+            Ok(notification::Entity::find()
+                .filter(
+                    Condition::all()
+                        .add(notification::Column::RecipientId.eq(recipient_id))
+                        .add(notification::Column::IsRead.eq(false))
+                        .add(notification::Column::DeletedAt.is_null()),
+                )
+                .count(&*tx)
+                .await?)
+        })
+        .await
+    }
+
+    /// Returns the number of unread notifications of each kind for the given recipient,
+    /// keyed by the kind's name. Kinds without any unread notifications are omitted.
+    pub async fn unread_counts_by_kind(
+        &self,
+        recipient_id: UserId,
+    ) -> Result<HashMap<String, u64>> {
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+        enum QueryKindCounts {
+            Kind,
+            Count,
+        }
+
+        self.transaction(|tx| async move {
+            let rows: Vec<(NotificationKindId, i64)> = notification::Entity::find()
+                .select_only()
+                .column(notification::Column::Kind)
+                .column_as(
+                    Expr::col(notification::Column::Id).count(),
+                    QueryKindCounts::Count,
+                )
+                .filter(
+                    Condition::all()
+                        .add(notification::Column::RecipientId.eq(recipient_id))
+                        .add(notification::Column::IsRead.eq(false))
+                        .add(notification::Column::DeletedAt.is_null()),
+                )
+                .group_by(notification::Column::Kind)
+                .into_values::<_, QueryKindCounts>()
+                .all(&*tx)
+                .await?;
+            Ok(rows
+                .into_iter()
+                .filter_map(|(kind, count)| {
+                    let kind = *self.notification_kinds_by_id.get(&kind)?;
+                    Some((kind.to_string(), count as u64))
+                })
+                .collect())
+        })
+        .await
+    }
+
+    /// Mutes or unmutes the given kind of notification for the given recipient.
+    pub async fn set_notification_kind_muted(
+        &self,
+        recipient_id: UserId,
+        kind: &str,
+        muted: bool,
+    ) -> Result<()> {
+        self.transaction(|tx| async move {
+            let kind = self.notification_kind_id(kind, &tx).await?;
+            if muted {
+                notification_mute::Entity::insert(notification_mute::ActiveModel {
+                    recipient_id: ActiveValue::Set(recipient_id),
+                    kind: ActiveValue::Set(kind),
+                })
+                .on_conflict(OnConflict::new().do_nothing().to_owned())
+                .exec_without_returning(&*tx)
+                .await?;
+            } else {
+                notification_mute::Entity::delete_by_id((recipient_id, kind))
+                    .exec(&*tx)
+                    .await?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns the names of the kinds of notification that the given recipient has
+    /// muted, in alphabetical order.
+    pub async fn get_muted_notification_kinds(
+        &self,
+        recipient_id: UserId,
+    ) -> Result<Vec<&'static str>> {
+        self.transaction(|tx| async move {
+            let mut kinds = notification_mute::Entity::find()
+                .filter(notification_mute::Column::RecipientId.eq(recipient_id))
+                .all(&*tx)
+                .await?
+                .into_iter()
+                .filter_map(|mute| {
+                    self.notification_kinds_by_id
+                        .get(&mute.kind)
+                        .map(|kind| *kind)
+                })
+                .collect::<Vec<_>>();
+            kinds.sort_unstable();
+            Ok(kinds)
+        })
+        .await
+    }
+
+    async fn is_notification_kind_muted(
+        &self,
+        recipient_id: UserId,
+        kind: NotificationKindId,
+        tx: &TransactionHandle,
+    ) -> Result<bool> {
+        Ok(notification_mute::Entity::find_by_id((recipient_id, kind))
+            .one(&**tx)
+            .await?
+            .is_some())
+    }
+
+    /// Creates a notification, unless the given recipient has muted its kind, or its
+    /// actor has reached the [`NotificationOptions::rate_limit`]. If
+    /// `avoid_duplicates` is set to true, then avoid creating a new notification if
+    /// the given recipient already has an unread notification with the given kind
+    /// and entity id. If `dedup_within` is also given, only unread notifications that
+    /// were created within that duration count as duplicates.
     pub async fn create_notification(
         &self,
         recipient_id: UserId,
         notification: Notification,
         avoid_duplicates: bool,
-        tx: &DatabaseTransaction,
-    ) -> Result<Option<(UserId, proto::Notification)>> {
-        if avoid_duplicates {
-            if self
-                .find_notification(recipient_id, &notification, tx)
+        dedup_within: Option<time::Duration>,
+        tx: &TransactionHandle,
+    ) -> Result<CreateNotificationResult> {
+        self.create_notification_with_idempotency_key(
+            recipient_id,
+            notification,
+            avoid_duplicates,
+            dedup_within,
+            None,
+            tx,
+        )
+        .await
+    }
+
+    /// Like [`Database::create_notification`], but if an idempotency key is given and
+    /// the recipient already has a notification that was created with the same key, no
+    /// notification is created and the existing one's id is returned instead. This makes
+    /// it safe to retry creating a notification whose result was lost.
+    pub async fn create_notification_with_idempotency_key(
+        &self,
+        recipient_id: UserId,
+        notification: Notification,
+        avoid_duplicates: bool,
+        dedup_within: Option<time::Duration>,
+        idempotency_key: Option<Uuid>,
+        tx: &TransactionHandle,
+    ) -> Result<CreateNotificationResult> {
+        // A retry shouldn't bring back a notification that has since been removed, so
+        // the existing notification is a duplicate even if it's been deleted.
+        if let Some(idempotency_key) = idempotency_key {
+            if let Some(existing) = self
+                .find_notification_by_idempotency_key(recipient_id, idempotency_key, tx)
                 .await?
-                .is_some()
             {
-                return Ok(None);
+                return Ok(CreateNotificationResult::Duplicate(existing.id));
             }
         }
 
         let proto = notification.to_proto();
-        let kind = notification_kind_from_proto(self, &proto)?;
+        let kind = self.notification_kind_id(&proto.kind, tx).await?;
+        if self
+            .is_notification_kind_muted(recipient_id, kind, tx)
+            .await?
+        {
+            return Ok(CreateNotificationResult::Muted);
+        }
+
+        if avoid_duplicates {
+            let mut condition = Condition::all().add(notification::Column::IsRead.eq(false));
+            if let Some(dedup_within) = dedup_within {
+                condition =
+                    condition.add(notification::Column::CreatedAt.gt(utc_now() - dedup_within));
+            }
+            if let Some(id) = self
+                .find_notification_internal(recipient_id, &notification, condition, tx)
+                .await?
+            {
+                return Ok(CreateNotificationResult::Duplicate(id));
+            }
+        }
+
+        let actor_id = UserId::from_proto(notification.actor_id());
+        if !self
+            .rate_limited_recipient_ids(&[recipient_id], actor_id, tx)
+            .await?
+            .is_empty()
+        {
+            return Ok(CreateNotificationResult::RateLimited);
+        }
+
         let model = notification::ActiveModel {
             recipient_id: ActiveValue::Set(recipient_id),
             kind: ActiveValue::Set(kind),
             entity_id: ActiveValue::Set(proto.entity_id.map(|id| id as i32)),
             content: ActiveValue::Set(proto.content.clone()),
+            actor_id: ActiveValue::Set(Some(actor_id)),
+            idempotency_key: ActiveValue::Set(idempotency_key),
             ..Default::default()
+        };
+        let row = if let Some(idempotency_key) = idempotency_key {
+            let result = notification::Entity::insert(model)
+                .on_conflict(
+                    OnConflict::columns([
+                        notification::Column::RecipientId,
+                        notification::Column::IdempotencyKey,
+                    ])
+                    .do_nothing()
+                    .to_owned(),
+                )
+                .do_nothing()
+                .exec(&**tx)
+                .await?;
+            match result {
+                TryInsertResult::Inserted(result) => {
+                    notification::Entity::find_by_id(result.last_insert_id)
+                        .one(&**tx)
+                        .await?
+                        .ok_or_else(|| anyhow!("no such notification"))?
+                }
+                // The notification was created concurrently with the same key.
+                _ => {
+                    let existing = self
+                        .find_notification_by_idempotency_key(recipient_id, idempotency_key, tx)
+                        .await?
+                        .ok_or_else(|| anyhow!("no such notification"))?;
+                    return Ok(CreateNotificationResult::Duplicate(existing.id));
+                }
+            }
+        } else {
+            model.insert(&**tx).await?
+        };
+
+        let notification = proto::Notification {
+            id: row.id.to_proto(),
+            kind: proto.kind,
+            timestamp: notification_timestamp(row.created_at),
+            is_read: false,
+            read_at: None,
+            response: None,
+            content: proto.content,
+            entity_id: proto.entity_id,
+        };
+        self.send_notification_event_after_commit(
+            NotificationEventKind::Created,
+            recipient_id,
+            &notification,
+            tx,
+        );
+        Ok(CreateNotificationResult::Inserted(notification))
+    }
+
+    /// Creates a notification, unless the given recipient already has a notification
+    /// with the given kind and entity id that is either unread, or was created within
+    /// the given window. This avoids notifying the recipient again about something
+    /// they've only just read.
+    pub async fn create_notification_avoiding_recent_duplicates(
+        &self,
+        recipient_id: UserId,
+        notification: Notification,
+        window: time::Duration,
+        tx: &TransactionHandle,
+    ) -> Result<CreateNotificationResult> {
+        let created_since = (OffsetDateTime::now_utc() - window).to_offset(time::UtcOffset::UTC);
+        let created_since =
+            time::PrimitiveDateTime::new(created_since.date(), created_since.time());
+        if let Some(id) = self
+            .find_notification_internal(
+                recipient_id,
+                &notification,
+                Condition::any()
+                    .add(notification::Column::IsRead.eq(false))
+                    .add(notification::Column::CreatedAt.gte(created_since)),
+                tx,
+            )
+            .await?
+        {
+            return Ok(CreateNotificationResult::Duplicate(id));
         }
-        .save(&*tx)
-        .await?;
 
-        Ok(Some((
+        self.create_notification(recipient_id, notification, false, None, tx)
+            .await
+    }
+
+    /// Creates a notification, or, if the given recipient already has an unread
+    /// notification with the given kind and entity id, increments the count in that
+    /// notification's content and updates its `updated_at` to now. The notification
+    /// keeps its original creation time and its place in listings, which are ordered by
+    /// ID, but it's reported as changed by [`Database::notifications_changed_since`],
+    /// and a created event is sent for it so that clients can move it to the top.
+    /// Nothing happens if the recipient has muted the notification's kind, or if a new
+    /// notification would be created and its actor has reached the
+    /// [`NotificationOptions::rate_limit`].
+    pub async fn create_or_coalesce_notification(
+        &self,
+        recipient_id: UserId,
+        notification: Notification,
+        tx: &TransactionHandle,
+    ) -> Result<CreateNotificationResult> {
+        let proto = notification.to_proto();
+        let kind = self.notification_kind_id(&proto.kind, tx).await?;
+        if self
+            .is_notification_kind_muted(recipient_id, kind, tx)
+            .await?
+        {
+            return Ok(CreateNotificationResult::Muted);
+        }
+
+        let Some(id) = self
+            .find_notification(recipient_id, &notification, tx)
+            .await?
+        else {
+            return self
+                .create_notification(recipient_id, notification, false, None, tx)
+                .await;
+        };
+
+        let row = notification::Entity::find_by_id(id)
+            .one(&**tx)
+            .await?
+            .ok_or_else(|| anyhow!("no such notification"))?;
+        let mut content = serde_json::from_str::<serde_json::Value>(&row.content)?;
+        let content_fields = content
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("invalid notification content {:?}", row.content))?;
+        let count = content_fields
+            .get(NOTIFICATION_COUNT_FIELD)
+            .and_then(|count| count.as_u64())
+            .unwrap_or(1);
+        content_fields.insert(NOTIFICATION_COUNT_FIELD.into(), (count + 1).into());
+
+        let row = notification::Entity::update(notification::ActiveModel {
+            id: ActiveValue::Unchanged(id),
+            content: ActiveValue::Set(serde_json::to_string(&content)?),
+            updated_at: ActiveValue::Set(utc_now()),
+            ..Default::default()
+        })
+        .exec(&**tx)
+        .await?;
+        let notification = model_to_proto_with_kind(row, &proto.kind);
+        self.send_notification_event_after_commit(
+            NotificationEventKind::Created,
             recipient_id,
-            proto::Notification {
-                id: model.id.as_ref().to_proto(),
-                kind: proto.kind,
-                timestamp: model.created_at.as_ref().assume_utc().unix_timestamp() as u64,
-                is_read: false,
-                response: None,
-                content: proto.content,
-                entity_id: proto.entity_id,
-            },
-        )))
+            &notification,
+            tx,
+        );
+        Ok(CreateNotificationResult::Coalesced(notification))
+    }
+
+    /// Creates the given notification for each of the given recipients, using a
+    /// single query. Recipients who have muted the notification's kind, or who the
+    /// notification's actor has reached the [`NotificationOptions::rate_limit`] for,
+    /// are skipped.
+    /// If `avoid_duplicates` is set to true, then also skip recipients who already
+    /// have an unread notification with the given kind and entity id.
+    pub async fn create_notifications(
+        &self,
+        recipient_ids: &[UserId],
+        notification: Notification,
+        avoid_duplicates: bool,
+        tx: &TransactionHandle,
+    ) -> Result<NotificationBatch> {
+        let proto = notification.to_proto();
+        let kind = self.notification_kind_id(&proto.kind, tx).await?;
+        let actor_id = UserId::from_proto(notification.actor_id());
+
+        let mut recipient_ids = recipient_ids.to_vec();
+        recipient_ids.sort();
+        recipient_ids.dedup();
+        if !recipient_ids.is_empty() {
+            let muted_recipient_ids: HashSet<UserId> = notification_mute::Entity::find()
+                .filter(
+                    Condition::all()
+                        .add(
+                            notification_mute::Column::RecipientId
+                                .is_in(recipient_ids.iter().copied()),
+                        )
+                        .add(notification_mute::Column::Kind.eq(kind)),
+                )
+                .all(&**tx)
+                .await?
+                .into_iter()
+                .map(|mute| mute.recipient_id)
+                .collect();
+            recipient_ids.retain(|recipient_id| !muted_recipient_ids.contains(recipient_id));
+        }
+        let rate_limited_recipient_ids = self
+            .rate_limited_recipient_ids(&recipient_ids, actor_id, tx)
+            .await?;
+        recipient_ids.retain(|recipient_id| !rate_limited_recipient_ids.contains(recipient_id));
+        if avoid_duplicates && !recipient_ids.is_empty() {
+            #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+            enum QueryRecipientIds {
+                RecipientId,
+            }
+
+            let existing_recipient_ids: HashSet<UserId> = notification::Entity::find()
+                .select_only()
+                .column(notification::Column::RecipientId)
+                .filter(
+                    Condition::all()
+                        .add(notification::Column::RecipientId.is_in(recipient_ids.iter().copied()))
+                        .add(notification::Column::IsRead.eq(false))
+                        .add(notification::Column::DeletedAt.is_null())
+                        .add(notification::Column::Kind.eq(kind))
+                        .add(if proto.entity_id.is_some() {
+                            notification::Column::EntityId.eq(proto.entity_id)
+                        } else {
+                            notification::Column::EntityId.is_null()
+                        }),
+                )
+                .into_values::<_, QueryRecipientIds>()
+                .all(&**tx)
+                .await?
+                .into_iter()
+                .collect();
+            recipient_ids.retain(|recipient_id| !existing_recipient_ids.contains(recipient_id));
+        }
+        if recipient_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut query =
+            notification::Entity::insert_many(recipient_ids.iter().map(|recipient_id| {
+                notification::ActiveModel {
+                    recipient_id: ActiveValue::Set(*recipient_id),
+                    kind: ActiveValue::Set(kind),
+                    entity_id: ActiveValue::Set(proto.entity_id.map(|id| id as i32)),
+                    content: ActiveValue::Set(proto.content.clone()),
+                    actor_id: ActiveValue::Set(Some(actor_id)),
+                    ..Default::default()
+                }
+            }))
+            .into_query();
+        query.returning_all();
+        let rows = notification::Entity::find()
+            .from_raw_sql(tx.get_database_backend().build(&query))
+            .all(&**tx)
+            .await?;
+
+        let batch = rows
+            .into_iter()
+            .filter_map(|row| {
+                let recipient_id = row.recipient_id;
+                Some((recipient_id, model_to_proto_in_transaction(self, row, tx)?))
+            })
+            .collect::<NotificationBatch>();
+        for (recipient_id, notification) in &batch {
+            self.send_notification_event_after_commit(
+                NotificationEventKind::Created,
+                *recipient_id,
+                notification,
+                tx,
+            );
+        }
+        Ok(batch)
     }
 
     /// Remove an unread notification with the given recipient, kind and
@@ -118,17 +1031,202 @@ impl Database {
         &self,
         recipient_id: UserId,
         notification: Notification,
-        tx: &DatabaseTransaction,
+        tx: &TransactionHandle,
     ) -> Result<Option<NotificationId>> {
         let id = self
             .find_notification(recipient_id, &notification, tx)
             .await?;
         if let Some(id) = id {
-            notification::Entity::delete_by_id(id).exec(tx).await?;
+            let row = notification::Entity::find_by_id(id)
+                .one(&**tx)
+                .await?
+                .ok_or_else(|| anyhow!("no such notification"))?;
+            self.delete_notification_internal(&row, tx).await?;
+            if let Some(notification) = model_to_proto_in_transaction(self, row, tx) {
+                self.send_notification_event_after_commit(
+                    NotificationEventKind::Removed,
+                    recipient_id,
+                    &notification,
+                    tx,
+                );
+            }
         }
         Ok(id)
     }
 
+    /// Remove a notification with the given recipient, kind and entity id, whether
+    /// or not it has been read, returning the notification that was removed. If
+    /// there are several, an unread one is removed in preference to a read one, and
+    /// otherwise the most recent one is removed.
+    pub async fn remove_notification_any(
+        &self,
+        recipient_id: UserId,
+        notification: Notification,
+        tx: &TransactionHandle,
+    ) -> Result<Option<proto::Notification>> {
+        let proto = notification.to_proto();
+        let kind = self.notification_kind_id(&proto.kind, tx).await?;
+        let Some(row) = notification::Entity::find()
+            .filter(
+                Condition::all()
+                    .add(notification::Column::RecipientId.eq(recipient_id))
+                    .add(notification::Column::DeletedAt.is_null())
+                    .add(notification::Column::Kind.eq(kind))
+                    .add(if proto.entity_id.is_some() {
+                        notification::Column::EntityId.eq(proto.entity_id)
+                    } else {
+                        notification::Column::EntityId.is_null()
+                    }),
+            )
+            .order_by_asc(notification::Column::IsRead)
+            .order_by_desc(notification::Column::Id)
+            .one(&**tx)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        self.delete_notification_internal(&row, tx).await?;
+        let notification = model_to_proto_in_transaction(self, row, tx);
+        if let Some(notification) = &notification {
+            self.send_notification_event_after_commit(
+                NotificationEventKind::Removed,
+                recipient_id,
+                notification,
+                tx,
+            );
+        }
+        Ok(notification)
+    }
+
+    /// Restores the removed notification with the given ID, undoing its removal, and
+    /// returns it. Nothing is restored if the notification doesn't belong to the given
+    /// recipient, wasn't removed, or has since been pruned.
+    pub async fn restore_notification(
+        &self,
+        recipient_id: UserId,
+        id: NotificationId,
+    ) -> Result<Option<proto::Notification>> {
+        let rows = self
+            .transaction(|tx| async move {
+                Ok(notification::Entity::update_many()
+                    .set(notification::ActiveModel {
+                        deleted_at: ActiveValue::Set(None),
+                        updated_at: ActiveValue::Set(utc_now()),
+                        ..Default::default()
+                    })
+                    .filter(
+                        Condition::all()
+                            .add(notification::Column::Id.eq(id))
+                            .add(notification::Column::RecipientId.eq(recipient_id))
+                            .add(notification::Column::DeletedAt.is_not_null()),
+                    )
+                    .exec_with_returning(&*tx)
+                    .await?)
+            })
+            .await?;
+        let notification = rows
+            .into_iter()
+            .next()
+            .and_then(|row| model_to_proto(self, row));
+        if let Some(notification) = &notification {
+            self.send_notification_event(
+                NotificationEventKind::Created,
+                recipient_id,
+                notification,
+            );
+        }
+        Ok(notification)
+    }
+
+    /// Deletes the notifications that were created before the given time, returning
+    /// how many were deleted, including those that had already been removed. This is
+    /// the only way notifications are permanently deleted. The notifications are
+    /// deleted in batches, each in its own transaction, to avoid holding one long
+    /// transaction on a large table.
+    pub async fn delete_notifications_older_than(&self, cutoff: OffsetDateTime) -> Result<u64> {
+        const BATCH_SIZE: u64 = 1000;
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+        enum QueryIds {
+            Id,
+        }
+
+        let cutoff = cutoff.to_offset(time::UtcOffset::UTC);
+        let cutoff = time::PrimitiveDateTime::new(cutoff.date(), cutoff.time());
+        let mut deleted_count = 0;
+        loop {
+            let batch_count = self
+                .transaction(|tx| async move {
+                    let ids: Vec<NotificationId> = notification::Entity::find()
+                        .select_only()
+                        .column(notification::Column::Id)
+                        .filter(notification::Column::CreatedAt.lt(cutoff))
+                        .order_by_asc(notification::Column::Id)
+                        .limit(BATCH_SIZE)
+                        .into_values::<_, QueryIds>()
+                        .all(&*tx)
+                        .await?;
+                    if ids.is_empty() {
+                        return Ok(0);
+                    }
+
+                    let result = notification::Entity::delete_many()
+                        .filter(notification::Column::Id.is_in(ids))
+                        .exec(&*tx)
+                        .await?;
+                    Ok(result.rows_affected)
+                })
+                .await?;
+            deleted_count += batch_count;
+            if batch_count < BATCH_SIZE {
+                break;
+            }
+        }
+        Ok(deleted_count)
+    }
+
+    /// Deletes the notifications whose content isn't a valid JSON object, which are
+    /// skipped whenever notifications are retrieved, returning how many were deleted.
+    /// Notifications with unknown kinds are kept, since they may have been created by
+    /// a newer version of the server.
+    pub async fn delete_invalid_notifications(&self) -> Result<u64> {
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+        enum QueryIdsAndContents {
+            Id,
+            Content,
+        }
+
+        self.transaction(|tx| async move {
+            let mut invalid_ids = Vec::new();
+            let mut rows = notification::Entity::find()
+                .select_only()
+                .column(notification::Column::Id)
+                .column(notification::Column::Content)
+                .into_values::<(NotificationId, String), QueryIdsAndContents>()
+                .stream(&*tx)
+                .await?;
+            while let Some(row) = rows.next().await {
+                let (id, content) = row?;
+                if !is_valid_notification_content(&content) {
+                    invalid_ids.push(id);
+                }
+            }
+            drop(rows);
+
+            let mut deleted_count = 0;
+            for ids in invalid_ids.chunks(1000) {
+                deleted_count += notification::Entity::delete_many()
+                    .filter(notification::Column::Id.is_in(ids.iter().copied()))
+                    .exec(&*tx)
+                    .await?
+                    .rows_affected;
+            }
+            Ok(deleted_count)
+        })
+        .await
+    }
+
     /// Populate the response for the notification with the given kind and
     /// entity id.
     pub async fn mark_notification_as_read_with_response(
@@ -136,85 +1234,360 @@ impl Database {
         recipient_id: UserId,
         notification: &Notification,
         response: bool,
-        tx: &DatabaseTransaction,
+        tx: &TransactionHandle,
     ) -> Result<Option<(UserId, proto::Notification)>> {
         self.mark_notification_as_read_internal(recipient_id, notification, Some(response), tx)
             .await
     }
 
-    /// Marks the given notification as read.
-    pub async fn mark_notification_as_read(
+    /// Marks the unread notification with the given recipient, kind and entity id as
+    /// read.
+    pub async fn mark_notification_as_read_by_entity(
         &self,
         recipient_id: UserId,
         notification: &Notification,
-        tx: &DatabaseTransaction,
+        tx: &TransactionHandle,
     ) -> Result<Option<(UserId, proto::Notification)>> {
         self.mark_notification_as_read_internal(recipient_id, notification, None, tx)
             .await
     }
 
-    /// Marks the notification with the given ID as read.
-    pub async fn mark_notification_as_read_by_id(
+    /// Marks the notification with the given ID as read, and returns it, or `None` if
+    /// it doesn't belong to the given recipient. A notification that was already read is
+    /// returned as it is, keeping the time at which it was first read.
+    pub async fn mark_notification_as_read(
         &self,
         recipient_id: UserId,
-        notification_id: NotificationId,
-    ) -> Result<NotificationBatch> {
+        id: NotificationId,
+    ) -> Result<Option<proto::Notification>> {
         self.transaction(|tx| async move {
-            let row = notification::Entity::update(notification::ActiveModel {
-                id: ActiveValue::Unchanged(notification_id),
-                recipient_id: ActiveValue::Unchanged(recipient_id),
-                is_read: ActiveValue::Set(true),
-                ..Default::default()
-            })
-            .exec(&*tx)
-            .await?;
-            Ok(model_to_proto(self, row)
-                .map(|notification| (recipient_id, notification))
-                .into_iter()
-                .collect())
+            let condition = Condition::all()
+                .add(notification::Column::Id.eq(id))
+                .add(notification::Column::RecipientId.eq(recipient_id))
+                .add(notification::Column::DeletedAt.is_null());
+            let rows = notification::Entity::update_many()
+                .set(notification::ActiveModel {
+                    is_read: ActiveValue::Set(true),
+                    read_at: ActiveValue::Set(Some(utc_now())),
+                    updated_at: ActiveValue::Set(utc_now()),
+                    ..Default::default()
+                })
+                .filter(
+                    condition
+                        .clone()
+                        .add(notification::Column::IsRead.eq(false)),
+                )
+                .exec_with_returning(&*tx)
+                .await?;
+            if let Some(row) = rows.into_iter().next() {
+                let notification = model_to_proto_in_transaction(self, row, &tx);
+                if let Some(notification) = &notification {
+                    self.send_notification_event_after_commit(
+                        NotificationEventKind::Read,
+                        recipient_id,
+                        notification,
+                        &tx,
+                    );
+                }
+                return Ok(notification);
+            }
+
+            let row = notification::Entity::find()
+                .filter(condition)
+                .one(&*tx)
+                .await?;
+            Ok(row.and_then(|row| model_to_proto_in_transaction(self, row, &tx)))
         })
         .await
     }
 
+    /// Marks all of the given recipient's unread notifications as read, returning
+    /// the IDs of the notifications that were updated.
+    pub async fn mark_all_notifications_as_read(
+        &self,
+        recipient_id: UserId,
+    ) -> Result<Vec<NotificationId>> {
+        self.mark_notifications_as_read_internal(recipient_id, None)
+            .await
+    }
+
+    /// Marks the given recipient's unread notifications of the given kind as read,
+    /// returning the IDs of the notifications that were updated.
+    pub async fn mark_notifications_as_read_by_kind(
+        &self,
+        recipient_id: UserId,
+        kind: &str,
+    ) -> Result<Vec<NotificationId>> {
+        self.mark_notifications_as_read_internal(recipient_id, Some(kind))
+            .await
+    }
+
+    async fn mark_notifications_as_read_internal(
+        &self,
+        recipient_id: UserId,
+        kind: Option<&str>,
+    ) -> Result<Vec<NotificationId>> {
+        let mut rows = self
+            .transaction(|tx| async move {
+                let mut condition = Condition::all()
+                    .add(notification::Column::RecipientId.eq(recipient_id))
+                    .add(notification::Column::IsRead.eq(false))
+                    .add(notification::Column::DeletedAt.is_null());
+                if let Some(kind) = kind {
+                    let kind = self.notification_kind_id(kind, &tx).await?;
+                    condition = condition.add(notification::Column::Kind.eq(kind));
+                }
+
+                Ok(notification::Entity::update_many()
+                    .set(notification::ActiveModel {
+                        is_read: ActiveValue::Set(true),
+                        read_at: ActiveValue::Set(Some(utc_now())),
+                        updated_at: ActiveValue::Set(utc_now()),
+                        ..Default::default()
+                    })
+                    .filter(condition)
+                    .exec_with_returning(&*tx)
+                    .await?)
+            })
+            .await?;
+        rows.sort_by_key(|row| row.id);
+
+        let ids = rows.iter().map(|row| row.id).collect();
+        for row in rows {
+            if let Some(notification) = model_to_proto(self, row) {
+                self.send_notification_event(
+                    NotificationEventKind::Read,
+                    recipient_id,
+                    &notification,
+                );
+            }
+        }
+        Ok(ids)
+    }
+
     async fn mark_notification_as_read_internal(
         &self,
         recipient_id: UserId,
         notification: &Notification,
         response: Option<bool>,
-        tx: &DatabaseTransaction,
+        tx: &TransactionHandle,
     ) -> Result<Option<(UserId, proto::Notification)>> {
         if let Some(id) = self
-            .find_notification(recipient_id, notification, &*tx)
+            .find_notification(recipient_id, notification, tx)
             .await?
         {
             let row = notification::Entity::update(notification::ActiveModel {
                 id: ActiveValue::Unchanged(id),
                 recipient_id: ActiveValue::Unchanged(recipient_id),
                 is_read: ActiveValue::Set(true),
+                read_at: ActiveValue::Set(Some(utc_now())),
                 response: if let Some(response) = response {
                     ActiveValue::Set(Some(response))
                 } else {
                     ActiveValue::NotSet
                 },
+                updated_at: ActiveValue::Set(utc_now()),
                 ..Default::default()
             })
-            .exec(tx)
+            .exec(&**tx)
             .await?;
-            Ok(model_to_proto(self, row).map(|notification| (recipient_id, notification)))
+            let notification = model_to_proto_in_transaction(self, row, tx);
+            if let Some(notification) = &notification {
+                self.send_notification_event_after_commit(
+                    NotificationEventKind::Read,
+                    recipient_id,
+                    notification,
+                    tx,
+                );
+            }
+            Ok(notification.map(|notification| (recipient_id, notification)))
         } else {
             Ok(None)
         }
     }
 
+    /// Returns the recipients among those given who have been sent as many notifications
+    /// by the given actor as the [`NotificationOptions::rate_limit`] allows.
+    async fn rate_limited_recipient_ids(
+        &self,
+        recipient_ids: &[UserId],
+        actor_id: UserId,
+        tx: &TransactionHandle,
+    ) -> Result<HashSet<UserId>> {
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+        enum QueryRecipientCounts {
+            RecipientId,
+            Count,
+        }
+
+        let Some(rate_limit) = self.notification_options.rate_limit else {
+            return Ok(HashSet::default());
+        };
+        if recipient_ids.is_empty() {
+            return Ok(HashSet::default());
+        }
+
+        let rows: Vec<(UserId, i64)> = notification::Entity::find()
+            .select_only()
+            .column(notification::Column::RecipientId)
+            .column_as(
+                Expr::col(notification::Column::Id).count(),
+                QueryRecipientCounts::Count,
+            )
+            .filter(
+                Condition::all()
+                    .add(notification::Column::RecipientId.is_in(recipient_ids.iter().copied()))
+                    .add(notification::Column::ActorId.eq(actor_id))
+                    .add(notification::Column::CreatedAt.gt(utc_now() - rate_limit.window)),
+            )
+            .group_by(notification::Column::RecipientId)
+            .into_values::<_, QueryRecipientCounts>()
+            .all(&**tx)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .filter(|(_, count)| *count as u64 >= rate_limit.max_notifications)
+            .map(|(recipient_id, _)| recipient_id)
+            .collect())
+    }
+
+    /// Returns the id of the notification kind with the given name. Kinds that aren't
+    /// in the database yet, because they were added after the notification kinds were
+    /// initialized, are inserted on first use, so that a server that ships a new kind
+    /// during a rolling deploy doesn't fail to create those notifications.
+    ///
+    /// Kinds that weren't already cached are only cached once the given transaction is
+    /// committed, since it may still be rolled back.
+    async fn notification_kind_id(
+        &self,
+        name: &str,
+        tx: &TransactionHandle,
+    ) -> Result<NotificationKindId> {
+        if let Some(id) = self.notification_kinds_by_name.get(name) {
+            return Ok(*id);
+        }
+
+        let name = Notification::all_variant_names()
+            .iter()
+            .copied()
+            .find(|variant_name| *variant_name == name)
+            .ok_or_else(|| anyhow!("invalid notification kind {:?}", name))?;
+        let pending_id = tx
+            .notification_changes
+            .lock()
+            .kinds
+            .iter()
+            .find(|(_, pending_name)| *pending_name == name)
+            .map(|(id, _)| *id);
+        if let Some(id) = pending_id {
+            return Ok(id);
+        }
+
+        let find_kind = || {
+            notification_kind::Entity::find()
+                .filter(notification_kind::Column::Name.eq(name))
+                .one(&**tx)
+        };
+        let row = if let Some(row) = find_kind().await? {
+            row
+        } else {
+            notification_kind::Entity::insert(notification_kind::ActiveModel {
+                name: ActiveValue::Set(name.to_string()),
+                ..Default::default()
+            })
+            .on_conflict(OnConflict::new().do_nothing().to_owned())
+            .exec_without_returning(&**tx)
+            .await?;
+            find_kind()
+                .await?
+                .ok_or_else(|| anyhow!("failed to insert notification kind {:?}", name))?
+        };
+
+        tx.notification_changes.lock().kinds.push((row.id, name));
+        Ok(row.id)
+    }
+
+    /// Returns the name of the notification kind with the given id, including kinds
+    /// that were first looked up within the given transaction.
+    fn notification_kind_name(
+        &self,
+        id: NotificationKindId,
+        tx: &TransactionHandle,
+    ) -> Option<&'static str> {
+        if let Some(name) = self.notification_kinds_by_id.get(&id) {
+            return Some(*name);
+        }
+
+        tx.notification_changes
+            .lock()
+            .kinds
+            .iter()
+            .find(|(pending_id, _)| *pending_id == id)
+            .map(|(_, name)| *name)
+    }
+
+    /// Marks the given notification as removed. It's kept until it's pruned by
+    /// [`Database::delete_notifications_older_than`], so that the removal can be undone
+    /// and clients can be told about it by [`Database::notifications_changed_since`].
+    async fn delete_notification_internal(
+        &self,
+        row: &notification::Model,
+        tx: &TransactionHandle,
+    ) -> Result<()> {
+        notification::Entity::update(notification::ActiveModel {
+            id: ActiveValue::Unchanged(row.id),
+            deleted_at: ActiveValue::Set(Some(utc_now())),
+            updated_at: ActiveValue::Set(utc_now()),
+            ..Default::default()
+        })
+        .exec(&**tx)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_notification_by_idempotency_key(
+        &self,
+        recipient_id: UserId,
+        idempotency_key: Uuid,
+        tx: &TransactionHandle,
+    ) -> Result<Option<notification::Model>> {
+        Ok(notification::Entity::find()
+            .filter(
+                Condition::all()
+                    .add(notification::Column::RecipientId.eq(recipient_id))
+                    .add(notification::Column::IdempotencyKey.eq(idempotency_key)),
+            )
+            .one(&**tx)
+            .await?)
+    }
+
     /// Find an unread notification by its recipient, kind and entity id.
     async fn find_notification(
         &self,
         recipient_id: UserId,
         notification: &Notification,
-        tx: &DatabaseTransaction,
+        tx: &TransactionHandle,
+    ) -> Result<Option<NotificationId>> {
+        self.find_notification_internal(
+            recipient_id,
+            notification,
+            Condition::all().add(notification::Column::IsRead.eq(false)),
+            tx,
+        )
+        .await
+    }
+
+    /// Find a notification by its recipient, kind and entity id that also matches the
+    /// given condition.
+    async fn find_notification_internal(
+        &self,
+        recipient_id: UserId,
+        notification: &Notification,
+        condition: Condition,
+        tx: &TransactionHandle,
     ) -> Result<Option<NotificationId>> {
         let proto = notification.to_proto();
-        let kind = notification_kind_from_proto(self, &proto)?;
+        let kind = self.notification_kind_id(&proto.kind, tx).await?;
 
         #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
         enum QueryIds {
@@ -227,7 +1600,8 @@ impl Database {
             .filter(
                 Condition::all()
                     .add(notification::Column::RecipientId.eq(recipient_id))
-                    .add(notification::Column::IsRead.eq(false))
+                    .add(notification::Column::DeletedAt.is_null())
+                    .add(condition)
                     .add(notification::Column::Kind.eq(kind))
                     .add(if proto.entity_id.is_some() {
                         notification::Column::EntityId.eq(proto.entity_id)
@@ -236,31 +1610,107 @@ impl Database {
                     }),
             )
             .into_values::<_, QueryIds>()
-            .one(&*tx)
+            .one(&**tx)
             .await?)
     }
 }
 
+/// Like [`model_to_proto_in_transaction`], but also checks that the notification's
+/// content is valid. Invalid notifications are logged and counted, so that corrupt rows
+/// can be detected and removed with [`Database::delete_invalid_notifications`].
+fn valid_model_to_proto(
+    this: &Database,
+    row: notification::Model,
+    tx: &TransactionHandle,
+) -> Option<proto::Notification> {
+    if let Some(kind) = this.notification_kind_name(row.kind, tx) {
+        if is_valid_notification_content(&row.content) {
+            return Some(model_to_proto_with_kind(row, kind));
+        }
+        log::warn!("skipping notification {:?} with invalid content", row.id);
+    } else {
+        log::warn!(
+            "skipping notification {:?} with unknown kind {:?}",
+            row.id,
+            row.kind
+        );
+    }
+    METRIC_INVALID_NOTIFICATIONS.inc();
+    None
+}
+
+/// Returns whether any of the strings within the given JSON value contain the given
+/// lowercase text, ignoring case.
+fn json_strings_contain(value: &serde_json::Value, lowercase_text: &str) -> bool {
+    match value {
+        serde_json::Value::String(string) => string.to_lowercase().contains(lowercase_text),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .any(|value| json_strings_contain(value, lowercase_text)),
+        serde_json::Value::Object(fields) => fields
+            .values()
+            .any(|value| json_strings_contain(value, lowercase_text)),
+        _ => false,
+    }
+}
+
+fn is_valid_notification_content(content: &str) -> bool {
+    serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(content).is_ok()
+}
+
+/// Encodes a notification's ID as an opaque cursor, for paginating with
+/// [`Database::get_notifications_by_cursor`].
+pub fn encode_notification_cursor(id: NotificationId) -> String {
+    base64::encode_config(id.to_proto().to_be_bytes(), base64::URL_SAFE_NO_PAD)
+}
+
+/// Decodes a cursor returned by [`encode_notification_cursor`].
+pub fn decode_notification_cursor(cursor: &str) -> Result<NotificationId> {
+    let bytes = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+        .ok_or_else(|| anyhow!("invalid notification cursor {:?}", cursor))?;
+    Ok(NotificationId::from_proto(u64::from_be_bytes(bytes)))
+}
+
 fn model_to_proto(this: &Database, row: notification::Model) -> Option<proto::Notification> {
-    let kind = this.notification_kinds_by_id.get(&row.kind)?;
-    Some(proto::Notification {
+    let kind = *this.notification_kinds_by_id.get(&row.kind)?;
+    Some(model_to_proto_with_kind(row, kind))
+}
+
+/// Like [`model_to_proto`], but also recognizes the kinds that were first looked up
+/// within the given transaction.
+fn model_to_proto_in_transaction(
+    this: &Database,
+    row: notification::Model,
+    tx: &TransactionHandle,
+) -> Option<proto::Notification> {
+    let kind = this.notification_kind_name(row.kind, tx)?;
+    Some(model_to_proto_with_kind(row, kind))
+}
+
+fn model_to_proto_with_kind(row: notification::Model, kind: &str) -> proto::Notification {
+    proto::Notification {
         id: row.id.to_proto(),
         kind: kind.to_string(),
-        timestamp: row.created_at.assume_utc().unix_timestamp() as u64,
+        timestamp: notification_timestamp(row.created_at),
         is_read: row.is_read,
+        read_at: row.read_at.map(notification_timestamp),
         response: row.response,
         content: row.content,
         entity_id: row.entity_id.map(|id| id as u64),
-    })
+    }
 }
 
-fn notification_kind_from_proto(
-    this: &Database,
-    proto: &proto::Notification,
-) -> Result<NotificationKindId> {
-    Ok(this
-        .notification_kinds_by_name
-        .get(&proto.kind)
-        .copied()
-        .ok_or_else(|| anyhow!("invalid notification kind {:?}", proto.kind))?)
+/// Returns the current time in UTC, which is how notifications' times are stored.
+fn utc_now() -> time::PrimitiveDateTime {
+    let now = OffsetDateTime::now_utc();
+    time::PrimitiveDateTime::new(now.date(), now.time())
+}
+
+/// Converts a notification's creation or read time, which is stored in UTC, to a Unix
+/// timestamp. Times before the Unix epoch are clamped to it. Since timestamps have only
+/// second precision, notifications are ordered by their IDs instead.
+fn notification_timestamp(created_at: time::PrimitiveDateTime) -> u64 {
+    created_at.assume_utc().unix_timestamp().max(0) as u64
 }
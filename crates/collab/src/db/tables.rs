@@ -15,6 +15,7 @@ pub mod follower;
 pub mod language_server;
 pub mod notification;
 pub mod notification_kind;
+pub mod notification_mute;
 pub mod observed_buffer_edits;
 pub mod observed_channel_messages;
 pub mod project;
@@ -13,7 +13,16 @@ pub struct Model {
     pub entity_id: Option<i32>,
     pub content: String,
     pub is_read: bool,
+    pub read_at: Option<PrimitiveDateTime>,
     pub response: Option<bool>,
+    pub actor_id: Option<UserId>,
+    pub idempotency_key: Option<Uuid>,
+    /// When the notification was removed. Removed notifications are kept, so that the
+    /// removal can be undone and reported to clients, until they're pruned.
+    pub deleted_at: Option<PrimitiveDateTime>,
+    /// When the notification was created or last changed, such as by being read,
+    /// removed, or having another notification coalesced into it.
+    pub updated_at: PrimitiveDateTime,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -24,6 +33,12 @@ pub enum Relation {
         to = "super::user::Column::Id"
     )]
     Recipient,
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::ActorId",
+        to = "super::user::Column::Id"
+    )]
+    Actor,
 }
 
 impl ActiveModelBehavior for ActiveModel {}
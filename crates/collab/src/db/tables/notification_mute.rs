@@ -0,0 +1,24 @@
+use crate::db::{NotificationKindId, UserId};
+use sea_orm::entity::prelude::*;
+
+/// A kind of notification that a user doesn't want to receive.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "notification_mutes")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub recipient_id: UserId,
+    #[sea_orm(primary_key)]
+    pub kind: NotificationKindId,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::RecipientId",
+        to = "super::user::Column::Id"
+    )]
+    Recipient,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -4,6 +4,7 @@ mod contributor_tests;
 mod db_tests;
 mod feature_flag_tests;
 mod message_tests;
+mod notification_tests;
 
 use super::*;
 use gpui::BackgroundExecutor;
@@ -24,6 +25,13 @@ pub struct TestDb {
 
 impl TestDb {
     pub fn sqlite(background: BackgroundExecutor) -> Self {
+        Self::sqlite_with_notification_options(background, NotificationOptions::default())
+    }
+
+    pub fn sqlite_with_notification_options(
+        background: BackgroundExecutor,
+        notification_options: NotificationOptions,
+    ) -> Self {
         let url = format!("sqlite::memory:");
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_io()
@@ -34,9 +42,13 @@ impl TestDb {
         let mut db = runtime.block_on(async {
             let mut options = ConnectOptions::new(url);
             options.max_connections(5);
-            let mut db = Database::new(options, Executor::Deterministic(background))
-                .await
-                .unwrap();
+            let mut db = Database::new(
+                options,
+                notification_options,
+                Executor::Deterministic(background),
+            )
+            .await
+            .unwrap();
             let sql = include_str!(concat!(
                 env!("CARGO_MANIFEST_DIR"),
                 "/migrations.sqlite/20221109000000_test_schema.sql"
@@ -83,9 +95,13 @@ impl TestDb {
             options
                 .max_connections(5)
                 .idle_timeout(Duration::from_secs(0));
-            let mut db = Database::new(options, Executor::Deterministic(background))
-                .await
-                .unwrap();
+            let mut db = Database::new(
+                options,
+                NotificationOptions::default(),
+                Executor::Deterministic(background),
+            )
+            .await
+            .unwrap();
             let migrations_path = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
             db.migrate(Path::new(migrations_path), false).await.unwrap();
             db.initialize_notification_kinds().await.unwrap();
@@ -0,0 +1,1932 @@
+use super::new_test_user;
+use crate::{
+    db::{
+        decode_notification_cursor, effective_notification_limit, encode_notification_cursor,
+        notification, notification_kind, CreateNotificationResult, Database, NotificationActor,
+        NotificationEvent, NotificationEventKind, NotificationId, NotificationOptions,
+        NotificationRateLimit, TestDb, UserId, DEFAULT_NOTIFICATION_PAGE_SIZE,
+        MAX_NOTIFICATION_PAGE_SIZE, NOTIFICATION_COUNT_FIELD,
+    },
+    test_both_dbs,
+};
+use anyhow::anyhow;
+use collections::HashMap;
+use futures::{channel::mpsc, StreamExt};
+use gpui::TestAppContext;
+use rpc::{proto, Notification};
+use sea_orm::{entity::prelude::*, sea_query::Expr};
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+
+test_both_dbs!(
+    test_mark_all_notifications_as_read,
+    test_mark_all_notifications_as_read_postgres,
+    test_mark_all_notifications_as_read_sqlite
+);
+
+async fn test_mark_all_notifications_as_read(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+
+    let notification_1 = create_contact_request(db, user_1, user_2).await;
+    let notification_2 = create_contact_request(db, user_1, user_3).await;
+    let notification_3 = create_contact_request(db, user_2, user_3).await;
+
+    assert_eq!(
+        db.mark_all_notifications_as_read(user_1).await.unwrap(),
+        &[notification_1.id, notification_2.id].map(NotificationId::from_proto)
+    );
+    assert!(db
+        .get_notifications(user_1, 10, None, None)
+        .await
+        .unwrap()
+        .iter()
+        .all(|notification| notification.is_read));
+
+    // Other recipients' notifications are unaffected.
+    let user_2_notifications = db.get_notifications(user_2, 10, None, None).await.unwrap();
+    assert_eq!(user_2_notifications.len(), 1);
+    assert_eq!(user_2_notifications[0].id, notification_3.id);
+    assert!(!user_2_notifications[0].is_read);
+
+    // When there's nothing left to read, nothing is updated.
+    assert_eq!(
+        db.mark_all_notifications_as_read(user_1).await.unwrap(),
+        Vec::<NotificationId>::new()
+    );
+}
+
+test_both_dbs!(
+    test_mark_notifications_as_read_by_kind,
+    test_mark_notifications_as_read_by_kind_postgres,
+    test_mark_notifications_as_read_by_kind_sqlite
+);
+
+async fn test_mark_notifications_as_read_by_kind(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+
+    let notification_1 = create_contact_request(db, user_1, user_2).await;
+    let notification_2 = create_contact_request(db, user_1, user_3).await;
+    let notification_3 = create_contact_request(db, user_2, user_3).await;
+    let notification_4 = create_notification(
+        db,
+        user_1,
+        Notification::ContactRequestAccepted {
+            responder_id: user_2.to_proto(),
+        },
+    )
+    .await
+    .inserted()
+    .unwrap();
+
+    assert_eq!(
+        db.mark_notifications_as_read_by_kind(user_1, "ContactRequest")
+            .await
+            .unwrap(),
+        &[notification_1.id, notification_2.id].map(NotificationId::from_proto)
+    );
+
+    // Notifications of other kinds, and other recipients' notifications, are unaffected.
+    let user_1_notifications = db.get_notifications(user_1, 10, None, None).await.unwrap();
+    assert_eq!(
+        user_1_notifications
+            .iter()
+            .filter(|notification| !notification.is_read)
+            .map(|notification| notification.id)
+            .collect::<Vec<_>>(),
+        &[notification_4.id]
+    );
+    let user_2_notifications = db.get_notifications(user_2, 10, None, None).await.unwrap();
+    assert_eq!(user_2_notifications.len(), 1);
+    assert_eq!(user_2_notifications[0].id, notification_3.id);
+    assert!(!user_2_notifications[0].is_read);
+
+    assert_eq!(
+        db.mark_notifications_as_read_by_kind(user_1, "ContactRequest")
+            .await
+            .unwrap(),
+        Vec::<NotificationId>::new()
+    );
+    assert!(db
+        .mark_notifications_as_read_by_kind(user_1, "NoSuchKind")
+        .await
+        .is_err());
+}
+
+test_both_dbs!(
+    test_unread_notification_count,
+    test_unread_notification_count_postgres,
+    test_unread_notification_count_sqlite
+);
+
+async fn test_unread_notification_count(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 0);
+
+    let notification = create_contact_request(db, user_1, user_2).await;
+    create_contact_request(db, user_1, user_3).await;
+    create_contact_request(db, user_2, user_3).await;
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 2);
+    assert_eq!(db.unread_notification_count(user_2).await.unwrap(), 1);
+
+    db.mark_notification_as_read(user_1, NotificationId::from_proto(notification.id))
+        .await
+        .unwrap();
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 1);
+
+    db.mark_all_notifications_as_read(user_1).await.unwrap();
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 0);
+    assert_eq!(db.unread_notification_count(user_2).await.unwrap(), 1);
+}
+
+test_both_dbs!(
+    test_unread_counts_by_kind,
+    test_unread_counts_by_kind_postgres,
+    test_unread_counts_by_kind_sqlite
+);
+
+async fn test_unread_counts_by_kind(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    assert_eq!(
+        db.unread_counts_by_kind(user_1).await.unwrap(),
+        HashMap::default()
+    );
+
+    create_contact_request(db, user_1, user_2).await;
+    create_contact_request(db, user_1, user_3).await;
+    create_contact_request(db, user_2, user_3).await;
+    for notification in [
+        Notification::ChannelInvitation {
+            channel_id: 1,
+            channel_name: "the-channel".into(),
+            inviter_id: user_2.to_proto(),
+        },
+        Notification::ContactRequestAccepted {
+            responder_id: user_3.to_proto(),
+        },
+    ] {
+        create_notification(db, user_1, notification).await;
+    }
+    db.mark_notifications_as_read_by_kind(user_1, "ContactRequestAccepted")
+        .await
+        .unwrap();
+
+    // Read notifications and other recipients' notifications aren't counted.
+    assert_eq!(
+        db.unread_counts_by_kind(user_1).await.unwrap(),
+        HashMap::from_iter([
+            ("ContactRequest".to_string(), 2),
+            ("ChannelInvitation".to_string(), 1),
+        ])
+    );
+    assert_eq!(
+        db.unread_counts_by_kind(user_2).await.unwrap(),
+        HashMap::from_iter([("ContactRequest".to_string(), 1)])
+    );
+}
+
+test_both_dbs!(
+    test_get_notifications_for_recipients,
+    test_get_notifications_for_recipients_postgres,
+    test_get_notifications_for_recipients_sqlite
+);
+
+async fn test_get_notifications_for_recipients(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    let user_4 = new_test_user(db, "user4@example.com").await;
+
+    let mut notifications_1 = Vec::new();
+    for _ in 0..5 {
+        notifications_1.push(create_contact_request(db, user_1, user_4).await);
+    }
+    let notification_2 = create_contact_request(db, user_2, user_4).await;
+    create_contact_request(db, user_4, user_1).await;
+
+    // Each recipient is limited separately, and recipients without notifications are
+    // still included.
+    let notifications = db
+        .get_notifications_for_recipients(&[user_1, user_2, user_3], 3)
+        .await
+        .unwrap();
+    assert_eq!(
+        notifications,
+        HashMap::from_iter([
+            (user_1, notifications_1[2..].to_vec()),
+            (user_2, vec![notification_2]),
+            (user_3, Vec::new()),
+        ])
+    );
+    assert_eq!(
+        db.get_notifications_for_recipients(&[], 3).await.unwrap(),
+        HashMap::default()
+    );
+}
+
+test_both_dbs!(
+    test_get_notifications_after,
+    test_get_notifications_after_postgres,
+    test_get_notifications_after_sqlite
+);
+
+async fn test_get_notifications_after(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let mut senders = Vec::new();
+    for i in 2..7 {
+        senders.push(new_test_user(db, &format!("user{i}@example.com")).await);
+    }
+    let mut ids = Vec::new();
+    for sender in senders {
+        ids.push(create_contact_request(db, user_1, sender).await.id);
+    }
+
+    let page_ids = |page: Vec<proto::Notification>| {
+        page.into_iter()
+            .map(|notification| notification.id)
+            .collect::<Vec<_>>()
+    };
+
+    let page = db.get_notifications_after(user_1, 2, None).await.unwrap();
+    assert_eq!(page_ids(page), &ids[0..2]);
+
+    let page = db
+        .get_notifications_after(user_1, 2, Some(NotificationId::from_proto(ids[1])))
+        .await
+        .unwrap();
+    assert_eq!(page_ids(page), &ids[2..4]);
+
+    let page = db
+        .get_notifications_after(user_1, 2, Some(NotificationId::from_proto(ids[3])))
+        .await
+        .unwrap();
+    assert_eq!(page_ids(page), &ids[4..5]);
+
+    let page = db
+        .get_notifications_after(user_1, 2, Some(NotificationId::from_proto(ids[4])))
+        .await
+        .unwrap();
+    assert_eq!(page_ids(page), Vec::<u64>::new());
+
+    // Paging backward returns the same notifications in the same order.
+    let page = db
+        .get_notifications(user_1, 2, Some(NotificationId::from_proto(ids[4])), None)
+        .await
+        .unwrap();
+    assert_eq!(page_ids(page), &ids[2..4]);
+}
+
+test_both_dbs!(
+    test_get_notifications_limit,
+    test_get_notifications_limit_postgres,
+    test_get_notifications_limit_sqlite
+);
+
+async fn test_get_notifications_limit(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let count = DEFAULT_NOTIFICATION_PAGE_SIZE + 5;
+    let mut ids = Vec::new();
+    for channel_id in 0..count as u64 {
+        let notification = create_notification(
+            db,
+            user_1,
+            Notification::ChannelInvitation {
+                channel_id,
+                channel_name: format!("channel-{channel_id}"),
+                inviter_id: user_2.to_proto(),
+            },
+        )
+        .await
+        .inserted()
+        .unwrap();
+        ids.push(notification.id);
+    }
+
+    assert_eq!(
+        effective_notification_limit(0),
+        DEFAULT_NOTIFICATION_PAGE_SIZE
+    );
+    assert_eq!(effective_notification_limit(7), 7);
+    assert_eq!(
+        effective_notification_limit(usize::MAX),
+        MAX_NOTIFICATION_PAGE_SIZE
+    );
+
+    let page_ids = |page: Vec<proto::Notification>| {
+        page.into_iter()
+            .map(|notification| notification.id)
+            .collect::<Vec<_>>()
+    };
+
+    // A limit of zero returns the default number of notifications.
+    let page = db.get_notifications(user_1, 0, None, None).await.unwrap();
+    assert_eq!(
+        page_ids(page),
+        &ids[count - DEFAULT_NOTIFICATION_PAGE_SIZE..]
+    );
+
+    // A huge limit is clamped rather than passed to the database.
+    let page = db
+        .get_notifications(user_1, usize::MAX, None, None)
+        .await
+        .unwrap();
+    assert_eq!(page_ids(page), ids);
+    let page = db
+        .get_notifications_after(user_1, usize::MAX, None)
+        .await
+        .unwrap();
+    assert_eq!(page_ids(page), ids);
+    let (page, cursor) = db
+        .get_notifications_by_cursor(user_1, usize::MAX, None)
+        .await
+        .unwrap();
+    assert_eq!(page_ids(page), ids);
+    assert_eq!(cursor, None);
+}
+
+test_both_dbs!(
+    test_mark_notification_as_read,
+    test_mark_notification_as_read_postgres,
+    test_mark_notification_as_read_sqlite
+);
+
+async fn test_mark_notification_as_read(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let notification = create_contact_request(db, user_1, user_2).await;
+    let notification_id = NotificationId::from_proto(notification.id);
+
+    // Users can't read other users' notifications.
+    assert_eq!(
+        db.mark_notification_as_read(user_2, notification_id)
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 1);
+
+    let read = db
+        .mark_notification_as_read(user_1, notification_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(read.read_at.is_some());
+    assert_eq!(
+        read,
+        proto::Notification {
+            is_read: true,
+            read_at: read.read_at,
+            ..notification
+        }
+    );
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 0);
+
+    // Marking it as read again returns it unchanged.
+    assert_eq!(
+        db.mark_notification_as_read(user_1, notification_id)
+            .await
+            .unwrap(),
+        Some(read)
+    );
+}
+
+test_both_dbs!(
+    test_delete_notifications_older_than,
+    test_delete_notifications_older_than_postgres,
+    test_delete_notifications_older_than_sqlite
+);
+
+async fn test_delete_notifications_older_than(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    let now = OffsetDateTime::now_utc();
+
+    let old_notification = create_contact_request(db, user_1, user_2).await;
+    let new_notification = create_contact_request(db, user_1, user_3).await;
+    let other_old_notification = create_contact_request(db, user_2, user_3).await;
+    set_created_at(db, &old_notification, now - Duration::days(30)).await;
+    set_created_at(db, &other_old_notification, now - Duration::days(10)).await;
+
+    assert_eq!(
+        db.delete_notifications_older_than(now - Duration::days(7))
+            .await
+            .unwrap(),
+        2
+    );
+    let notifications = db.get_notifications(user_1, 10, None, None).await.unwrap();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].id, new_notification.id);
+    assert!(db
+        .get_notifications(user_2, 10, None, None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(
+        db.delete_notifications_older_than(now - Duration::days(7))
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+test_both_dbs!(
+    test_get_notifications_by_kind,
+    test_get_notifications_by_kind_postgres,
+    test_get_notifications_by_kind_sqlite
+);
+
+async fn test_get_notifications_by_kind(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+
+    let request = create_contact_request(db, user_1, user_2).await;
+    let acceptance = create_notification(
+        db,
+        user_1,
+        Notification::ContactRequestAccepted {
+            responder_id: user_3.to_proto(),
+        },
+    )
+    .await
+    .inserted()
+    .unwrap();
+
+    let notifications = db
+        .get_notifications(user_1, 10, None, Some(&["ContactRequestAccepted"]))
+        .await
+        .unwrap();
+    assert_eq!(notifications, &[acceptance.clone()]);
+
+    let notifications = db
+        .get_notifications(
+            user_1,
+            10,
+            None,
+            Some(&["ContactRequest", "ContactRequestAccepted"]),
+        )
+        .await
+        .unwrap();
+    assert_eq!(notifications, &[request, acceptance]);
+
+    assert!(db
+        .get_notifications(user_1, 10, None, Some(&["ChannelInvitation"]))
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(db
+        .get_notifications(user_1, 10, None, Some(&["NoSuchKind"]))
+        .await
+        .is_err());
+}
+
+test_both_dbs!(
+    test_create_notifications,
+    test_create_notifications_postgres,
+    test_create_notifications_sqlite
+);
+
+async fn test_create_notifications(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    let user_4 = new_test_user(db, "user4@example.com").await;
+    create_contact_request(db, user_2, user_1).await;
+
+    let create_notifications = |recipient_ids: Vec<UserId>, avoid_duplicates: bool| async move {
+        db.transaction(|tx| {
+            let recipient_ids = recipient_ids.clone();
+            async move {
+                db.create_notifications(
+                    &recipient_ids,
+                    Notification::ContactRequest {
+                        sender_id: user_1.to_proto(),
+                    },
+                    avoid_duplicates,
+                    &tx,
+                )
+                .await
+            }
+        })
+        .await
+        .unwrap()
+    };
+
+    // Recipients who already have the same unread notification are skipped.
+    let batch = create_notifications(vec![user_3, user_2, user_3], true).await;
+    assert_eq!(
+        batch
+            .iter()
+            .map(|(recipient_id, _)| *recipient_id)
+            .collect::<Vec<_>>(),
+        &[user_3]
+    );
+    assert_eq!(
+        db.get_notifications(user_3, 10, None, None).await.unwrap(),
+        &[batch[0].1.clone()]
+    );
+
+    let batch = create_notifications(vec![user_2, user_4], false).await;
+    assert_eq!(
+        batch
+            .iter()
+            .map(|(recipient_id, _)| *recipient_id)
+            .collect::<Vec<_>>(),
+        &[user_2, user_4]
+    );
+    for (recipient_id, notification) in &batch {
+        assert_eq!(notification.kind, "ContactRequest");
+        assert_eq!(notification.entity_id, Some(user_1.to_proto()));
+        assert_eq!(
+            db.get_notifications(*recipient_id, 10, None, None)
+                .await
+                .unwrap()
+                .last(),
+            Some(notification)
+        );
+    }
+
+    assert!(create_notifications(Vec::new(), true).await.is_empty());
+}
+
+test_both_dbs!(
+    test_notification_timestamps,
+    test_notification_timestamps_postgres,
+    test_notification_timestamps_sqlite
+);
+
+async fn test_notification_timestamps(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    let user_4 = new_test_user(db, "user4@example.com").await;
+
+    let epoch_notification = create_contact_request(db, user_1, user_2).await;
+    let pre_epoch_notification = create_contact_request(db, user_1, user_3).await;
+    let later_notification = create_contact_request(db, user_1, user_4).await;
+    set_created_at(db, &epoch_notification, OffsetDateTime::UNIX_EPOCH).await;
+    set_created_at(
+        db,
+        &pre_epoch_notification,
+        OffsetDateTime::UNIX_EPOCH - Duration::days(1),
+    )
+    .await;
+    set_created_at(
+        db,
+        &later_notification,
+        OffsetDateTime::UNIX_EPOCH + Duration::seconds(90),
+    )
+    .await;
+
+    let timestamps = db
+        .get_notifications(user_1, 10, None, None)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|notification| notification.timestamp)
+        .collect::<Vec<_>>();
+    assert_eq!(timestamps, &[0, 0, 90]);
+}
+
+test_both_dbs!(
+    test_create_notification_avoiding_recent_duplicates,
+    test_create_notification_avoiding_recent_duplicates_postgres,
+    test_create_notification_avoiding_recent_duplicates_sqlite
+);
+
+async fn test_create_notification_avoiding_recent_duplicates(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+
+    let create_notification = || async move {
+        db.transaction(|tx| async move {
+            db.create_notification_avoiding_recent_duplicates(
+                user_1,
+                Notification::ContactRequest {
+                    sender_id: user_2.to_proto(),
+                },
+                Duration::days(1),
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap()
+    };
+
+    let notification = create_notification().await.inserted().unwrap();
+    let duplicate =
+        CreateNotificationResult::Duplicate(NotificationId::from_proto(notification.id));
+
+    // An unread duplicate already exists.
+    assert_eq!(create_notification().await, duplicate);
+
+    // The duplicate was read recently.
+    db.mark_all_notifications_as_read(user_1).await.unwrap();
+    assert_eq!(create_notification().await, duplicate);
+
+    // The duplicate was read, and is older than the window.
+    set_created_at(
+        db,
+        &notification,
+        OffsetDateTime::now_utc() - Duration::days(2),
+    )
+    .await;
+    assert!(create_notification().await.inserted().is_some());
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 1);
+}
+
+test_both_dbs!(
+    test_get_notifications_page,
+    test_get_notifications_page_postgres,
+    test_get_notifications_page_sqlite
+);
+
+async fn test_get_notifications_page(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let other_user = new_test_user(db, "other@example.com").await;
+    let mut ids = Vec::new();
+    for i in 2..7 {
+        let sender = new_test_user(db, &format!("user{i}@example.com")).await;
+        ids.push(create_contact_request(db, user_1, sender).await.id);
+    }
+
+    // Other recipients' notifications aren't counted.
+    create_contact_request(db, other_user, user_1).await;
+
+    let (page, total_count) = db
+        .get_notifications_page(user_1, 2, None, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        page.iter()
+            .map(|notification| notification.id)
+            .collect::<Vec<_>>(),
+        &ids[3..5]
+    );
+    assert_eq!(total_count, 5);
+
+    let (page, total_count) = db
+        .get_notifications_page(user_1, 2, Some(NotificationId::from_proto(ids[1])), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        page.iter()
+            .map(|notification| notification.id)
+            .collect::<Vec<_>>(),
+        &ids[0..1]
+    );
+    assert_eq!(total_count, 5);
+
+    let (page, total_count) = db
+        .get_notifications_page(user_1, 2, None, Some(&["ChannelInvitation"]))
+        .await
+        .unwrap();
+    assert!(page.is_empty());
+    assert_eq!(total_count, 0);
+}
+
+test_both_dbs!(
+    test_create_or_coalesce_notification,
+    test_create_or_coalesce_notification_postgres,
+    test_create_or_coalesce_notification_sqlite
+);
+
+async fn test_create_or_coalesce_notification(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+
+    let create_notification = || async move {
+        db.transaction(|tx| async move {
+            db.create_or_coalesce_notification(
+                user_1,
+                Notification::ContactRequest {
+                    sender_id: user_2.to_proto(),
+                },
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap()
+        .notification()
+        .unwrap()
+    };
+    let count = |notification: &proto::Notification| {
+        serde_json::from_str::<serde_json::Value>(&notification.content).unwrap()
+            [NOTIFICATION_COUNT_FIELD]
+            .as_u64()
+    };
+
+    let updated_at = |id: u64| async move {
+        db.transaction(|tx| async move {
+            Ok(
+                notification::Entity::find_by_id(NotificationId::from_proto(id))
+                    .one(&*tx)
+                    .await?
+                    .unwrap()
+                    .updated_at,
+            )
+        })
+        .await
+        .unwrap()
+    };
+
+    let first = create_notification().await;
+    assert_eq!(count(&first), None);
+    let yesterday = OffsetDateTime::now_utc() - Duration::days(1);
+    set_created_at(db, &first, yesterday).await;
+    let first_id = NotificationId::from_proto(first.id);
+    let yesterday_primitive = PrimitiveDateTime::new(yesterday.date(), yesterday.time());
+    db.transaction(|tx| async move {
+        notification::Entity::update_many()
+            .col_expr(
+                notification::Column::UpdatedAt,
+                Expr::value(yesterday_primitive),
+            )
+            .filter(notification::Column::Id.eq(first_id))
+            .exec(&*tx)
+            .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+
+    // Coalescing updates the notification's `updated_at`, but keeps its creation time.
+    let second = create_notification().await;
+    assert_eq!(second.id, first.id);
+    assert_eq!(count(&second), Some(2));
+    assert_eq!(second.timestamp as i64, yesterday.unix_timestamp());
+    assert!(updated_at(second.id).await > yesterday_primitive);
+
+    let third = create_notification().await;
+    assert_eq!(third.id, first.id);
+    assert_eq!(count(&third), Some(3));
+
+    // Coalesced notifications can still be read as their original kind.
+    assert_eq!(
+        Notification::from_proto(&third),
+        Some(Notification::ContactRequest {
+            sender_id: user_2.to_proto(),
+        })
+    );
+    assert_eq!(
+        db.get_notifications(user_1, 10, None, None).await.unwrap(),
+        &[third]
+    );
+
+    // Once the notification has been read, a new one is created.
+    db.mark_all_notifications_as_read(user_1).await.unwrap();
+    let fourth = create_notification().await;
+    assert_ne!(fourth.id, first.id);
+    assert_eq!(count(&fourth), None);
+}
+
+#[gpui::test]
+async fn test_notification_events(cx: &mut TestAppContext) {
+    let (events_tx, mut events_rx) = mpsc::unbounded();
+    let test_db = TestDb::sqlite_with_notification_options(
+        cx.executor(),
+        NotificationOptions {
+            event_sender: Some(events_tx),
+            ..Default::default()
+        },
+    );
+    let db = test_db.db();
+
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    let notification = Notification::ContactRequest {
+        sender_id: user_2.to_proto(),
+    };
+
+    let created = create_contact_request(db, user_1, user_2).await;
+    assert_eq!(
+        events_rx.next().await.unwrap(),
+        NotificationEvent {
+            kind: NotificationEventKind::Created,
+            recipient_id: user_1,
+            notification: created.clone(),
+        }
+    );
+
+    db.mark_all_notifications_as_read(user_1).await.unwrap();
+    let event = events_rx.next().await.unwrap();
+    let read = proto::Notification {
+        is_read: true,
+        read_at: event.notification.read_at,
+        ..created
+    };
+    assert!(read.read_at.is_some());
+    assert_eq!(
+        event,
+        NotificationEvent {
+            kind: NotificationEventKind::Read,
+            recipient_id: user_1,
+            notification: read.clone(),
+        }
+    );
+
+    // Marking a notification that was already read doesn't send another event.
+    assert_eq!(
+        db.mark_notification_as_read(user_1, NotificationId::from_proto(read.id))
+            .await
+            .unwrap(),
+        Some(read)
+    );
+    assert!(events_rx.try_next().is_err());
+
+    let created = create_contact_request(db, user_3, user_2).await;
+    events_rx.next().await.unwrap();
+    db.transaction(|tx| {
+        let notification = notification.clone();
+        async move { db.remove_notification(user_3, notification, &tx).await }
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        events_rx.next().await.unwrap(),
+        NotificationEvent {
+            kind: NotificationEventKind::Removed,
+            recipient_id: user_3,
+            notification: created,
+        }
+    );
+
+    // No events are sent for changes that are rolled back.
+    db.transaction(|tx| {
+        let notification = notification.clone();
+        async move {
+            db.create_notification(user_3, notification, false, None, &tx)
+                .await?;
+            Err::<(), _>(anyhow!("rolled back").into())
+        }
+    })
+    .await
+    .unwrap_err();
+    assert!(events_rx.try_next().is_err());
+}
+
+test_both_dbs!(
+    test_remove_notification_any,
+    test_remove_notification_any_postgres,
+    test_remove_notification_any_sqlite
+);
+
+async fn test_remove_notification_any(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let notification = Notification::ContactRequest {
+        sender_id: user_2.to_proto(),
+    };
+    let remove_notification = |include_read: bool| {
+        let notification = notification.clone();
+        async move {
+            db.transaction(|tx| {
+                let notification = notification.clone();
+                async move {
+                    if include_read {
+                        db.remove_notification_any(user_1, notification, &tx).await
+                    } else {
+                        Ok(db
+                            .remove_notification(user_1, notification, &tx)
+                            .await?
+                            .map(|id| proto::Notification {
+                                id: id.to_proto(),
+                                ..Default::default()
+                            }))
+                    }
+                }
+            })
+            .await
+            .unwrap()
+        }
+    };
+
+    let created = create_contact_request(db, user_1, user_2).await;
+    db.mark_all_notifications_as_read(user_1).await.unwrap();
+
+    // Read notifications can only be removed when including them.
+    assert_eq!(remove_notification(false).await, None);
+    let removed = remove_notification(true).await.unwrap();
+    assert_eq!(removed.id, created.id);
+    assert!(removed.is_read);
+    assert_eq!(remove_notification(true).await, None);
+
+    let created = create_contact_request(db, user_1, user_2).await;
+    let removed = remove_notification(true).await.unwrap();
+    assert_eq!(removed.id, created.id);
+    assert!(!removed.is_read);
+    assert!(db
+        .get_notifications(user_1, 10, None, None)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+test_both_dbs!(
+    test_search_notifications,
+    test_search_notifications_postgres,
+    test_search_notifications_sqlite
+);
+
+async fn test_search_notifications(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+
+    let mut ids = Vec::new();
+    for channel_name in ["zed", "zed-docs", "100%_\\done", "other", "Ünïcode"] {
+        let notification = create_notification(
+            db,
+            user_1,
+            Notification::ChannelInvitation {
+                channel_id: 1,
+                channel_name: channel_name.to_string(),
+                inviter_id: user_2.to_proto(),
+            },
+        )
+        .await
+        .inserted()
+        .unwrap();
+        ids.push(notification.id);
+    }
+    // Other recipients' notifications aren't searched.
+    create_notification(
+        db,
+        user_2,
+        Notification::ChannelInvitation {
+            channel_id: 1,
+            channel_name: "zed".to_string(),
+            inviter_id: user_1.to_proto(),
+        },
+    )
+    .await;
+
+    let search = |query: &'static str, limit: usize| async move {
+        db.search_notifications(user_1, query, limit)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|notification| notification.id)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(search("zed", 10).await, &[ids[1], ids[0]]);
+    assert_eq!(search("zed", 1).await, &[ids[1]]);
+    assert_eq!(search("docs", 10).await, &[ids[1]]);
+    assert_eq!(search("%", 10).await, &[ids[2]]);
+    assert_eq!(search("_\\", 10).await, &[ids[2]]);
+    assert_eq!(search("missing", 10).await, Vec::<u64>::new());
+    assert_eq!(search("", 2).await, &[ids[4], ids[3]]);
+
+    // Both backends ignore case in the same way.
+    assert_eq!(search("ZED", 10).await, &[ids[1], ids[0]]);
+    assert_eq!(search("üNÏ", 10).await, &[ids[4]]);
+
+    // The content's keys and ids aren't matched.
+    assert_eq!(search("channel", 10).await, Vec::<u64>::new());
+    assert_eq!(search("1", 10).await, &[ids[2]]);
+}
+
+test_both_dbs!(
+    test_notification_kind_mutes,
+    test_notification_kind_mutes_postgres,
+    test_notification_kind_mutes_sqlite
+);
+
+async fn test_notification_kind_mutes(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    assert_eq!(
+        db.get_muted_notification_kinds(user_1).await.unwrap(),
+        Vec::<&str>::new()
+    );
+
+    db.set_notification_kind_muted(user_1, "ContactRequest", true)
+        .await
+        .unwrap();
+    // Muting a kind twice is allowed.
+    db.set_notification_kind_muted(user_1, "ContactRequest", true)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_muted_notification_kinds(user_1).await.unwrap(),
+        &["ContactRequest"]
+    );
+    assert!(db
+        .set_notification_kind_muted(user_1, "NoSuchKind", true)
+        .await
+        .is_err());
+
+    let create_notification = |recipient_id: UserId, avoid_duplicates: bool| async move {
+        db.transaction(|tx| async move {
+            db.create_notification(
+                recipient_id,
+                Notification::ContactRequest {
+                    sender_id: user_3.to_proto(),
+                },
+                avoid_duplicates,
+                None,
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap()
+    };
+
+    // Muted kinds aren't created for the recipient who muted them.
+    assert_eq!(
+        create_notification(user_1, false).await,
+        CreateNotificationResult::Muted
+    );
+    assert!(create_notification(user_2, false)
+        .await
+        .inserted()
+        .is_some());
+    let batch = db
+        .transaction(|tx| async move {
+            db.create_notifications(
+                &[user_1, user_3],
+                Notification::ContactRequest {
+                    sender_id: user_2.to_proto(),
+                },
+                false,
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap();
+    assert_eq!(batch.len(), 1);
+    assert_eq!(batch[0].0, user_3);
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 0);
+
+    // Duplicates are still avoided for kinds that aren't muted.
+    db.set_notification_kind_muted(user_1, "ContactRequest", false)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_muted_notification_kinds(user_1).await.unwrap(),
+        Vec::<&str>::new()
+    );
+    let notification = create_notification(user_1, true).await.inserted().unwrap();
+    assert_eq!(
+        create_notification(user_1, true).await,
+        CreateNotificationResult::Duplicate(NotificationId::from_proto(notification.id))
+    );
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 1);
+}
+
+#[gpui::test]
+async fn test_notification_rate_limit(cx: &mut TestAppContext) {
+    let test_db = TestDb::sqlite_with_notification_options(
+        cx.executor(),
+        NotificationOptions {
+            rate_limit: Some(NotificationRateLimit {
+                max_notifications: 2,
+                window: Duration::hours(1),
+            }),
+            ..Default::default()
+        },
+    );
+    let db = test_db.db();
+
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    let send_contact_request = |recipient_id: UserId, actor_id: UserId| {
+        create_notification(
+            db,
+            recipient_id,
+            Notification::ContactRequest {
+                sender_id: actor_id.to_proto(),
+            },
+        )
+    };
+
+    // Once an actor reaches the limit, no more notifications are created for the
+    // recipient until the earlier ones fall outside the window.
+    let first = send_contact_request(user_1, user_2)
+        .await
+        .inserted()
+        .unwrap();
+    assert!(send_contact_request(user_1, user_2)
+        .await
+        .inserted()
+        .is_some());
+    assert_eq!(
+        send_contact_request(user_1, user_2).await,
+        CreateNotificationResult::RateLimited
+    );
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 2);
+
+    // Reading the notifications doesn't reset the limit.
+    db.mark_all_notifications_as_read(user_1).await.unwrap();
+    assert_eq!(
+        send_contact_request(user_1, user_2).await,
+        CreateNotificationResult::RateLimited
+    );
+
+    // The limit applies to each pair of actor and recipient separately.
+    assert!(send_contact_request(user_1, user_3)
+        .await
+        .inserted()
+        .is_some());
+    assert!(send_contact_request(user_3, user_2)
+        .await
+        .inserted()
+        .is_some());
+
+    set_created_at(db, &first, OffsetDateTime::now_utc() - Duration::hours(2)).await;
+    let unread = send_contact_request(user_1, user_2)
+        .await
+        .inserted()
+        .unwrap();
+    assert_eq!(
+        send_contact_request(user_1, user_2).await,
+        CreateNotificationResult::RateLimited
+    );
+
+    // The limit also applies to notifications created in batches, but not to those
+    // that are coalesced into an existing notification.
+    let batch = db
+        .transaction(|tx| async move {
+            db.create_notifications(
+                &[user_1, user_3],
+                Notification::ContactRequest {
+                    sender_id: user_2.to_proto(),
+                },
+                false,
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        batch.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+        &[user_3]
+    );
+    let coalesced = db
+        .transaction(|tx| async move {
+            db.create_or_coalesce_notification(
+                user_1,
+                Notification::ContactRequest {
+                    sender_id: user_2.to_proto(),
+                },
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap();
+    let CreateNotificationResult::Coalesced(coalesced) = coalesced else {
+        panic!("expected the notification to be coalesced, got {coalesced:?}");
+    };
+    assert_eq!(coalesced.id, unread.id);
+    assert_eq!(
+        db.get_notifications(user_1, 10, None, None)
+            .await
+            .unwrap()
+            .last(),
+        Some(&coalesced)
+    );
+}
+
+test_both_dbs!(
+    test_create_notification_dedup_window,
+    test_create_notification_dedup_window_postgres,
+    test_create_notification_dedup_window_sqlite
+);
+
+async fn test_create_notification_dedup_window(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let create_notification = |dedup_within: Option<Duration>| async move {
+        db.transaction(|tx| async move {
+            db.create_notification(
+                user_1,
+                Notification::ContactRequest {
+                    sender_id: user_2.to_proto(),
+                },
+                true,
+                dedup_within,
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap()
+        .inserted()
+    };
+    let window = Duration::minutes(10);
+
+    let first = create_notification(Some(window)).await.unwrap();
+    assert_eq!(create_notification(Some(window)).await, None);
+
+    // Once the unread notification is older than the window, it's no longer a duplicate,
+    // though it still is when duplicates are avoided without a window.
+    set_created_at(
+        db,
+        &first,
+        OffsetDateTime::now_utc() - Duration::minutes(20),
+    )
+    .await;
+    assert_eq!(create_notification(None).await, None);
+    let second = create_notification(Some(window)).await.unwrap();
+    assert_ne!(second.id, first.id);
+    assert_eq!(create_notification(Some(window)).await, None);
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 2);
+}
+
+test_both_dbs!(
+    test_create_notification_with_new_kind,
+    test_create_notification_with_new_kind_postgres,
+    test_create_notification_with_new_kind_sqlite
+);
+
+async fn test_create_notification_with_new_kind(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+
+    // Simulate a kind that was added after the notification kinds were initialized.
+    db.transaction(|tx| async move {
+        notification_kind::Entity::delete_many()
+            .filter(notification_kind::Column::Name.eq("ContactRequest"))
+            .exec(&*tx)
+            .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+    let (_, kind_id) = db
+        .notification_kinds_by_name
+        .remove("ContactRequest")
+        .unwrap();
+    db.notification_kinds_by_id.remove(&kind_id);
+
+    // A kind that's added within a transaction that's rolled back isn't cached.
+    db.transaction(|tx| async move {
+        db.create_notification(
+            user_1,
+            Notification::ContactRequest {
+                sender_id: user_2.to_proto(),
+            },
+            false,
+            None,
+            &tx,
+        )
+        .await?;
+        Err::<(), _>(anyhow!("rolled back").into())
+    })
+    .await
+    .unwrap_err();
+    assert!(!db.notification_kinds_by_name.contains_key("ContactRequest"));
+    assert!(!db
+        .notification_kinds_by_id
+        .iter()
+        .any(|entry| *entry.value() == "ContactRequest"));
+
+    // Kinds that haven't been cached yet can still be looked up by name. Since the kind
+    // inserted by the rolled back transaction is gone, it's inserted again, and the id
+    // of the new row is cached.
+    assert_eq!(
+        db.mark_notifications_as_read_by_kind(user_1, "ContactRequest")
+            .await
+            .unwrap(),
+        Vec::new()
+    );
+    let kind_row = db
+        .transaction(|tx| async move {
+            Ok(notification_kind::Entity::find()
+                .filter(notification_kind::Column::Name.eq("ContactRequest"))
+                .one(&*tx)
+                .await?)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        db.notification_kinds_by_name
+            .get("ContactRequest")
+            .map(|id| *id),
+        Some(kind_row.id)
+    );
+    assert_eq!(
+        db.notification_kinds_by_id
+            .get(&kind_row.id)
+            .map(|name| *name),
+        Some("ContactRequest")
+    );
+
+    let notification_1 = create_contact_request(db, user_1, user_2).await;
+    let notification_2 = create_contact_request(db, user_1, user_3).await;
+    let notifications = db
+        .get_notifications(user_1, 10, None, Some(&["ContactRequest"]))
+        .await
+        .unwrap();
+    assert_eq!(
+        notifications
+            .iter()
+            .map(|notification| (notification.id, notification.kind.as_str()))
+            .collect::<Vec<_>>(),
+        &[
+            (notification_1.id, "ContactRequest"),
+            (notification_2.id, "ContactRequest")
+        ]
+    );
+}
+
+test_both_dbs!(
+    test_get_notification,
+    test_get_notification_postgres,
+    test_get_notification_sqlite
+);
+
+async fn test_get_notification(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+
+    let notification = create_contact_request(db, user_1, user_2).await;
+    let id = NotificationId::from_proto(notification.id);
+    assert_eq!(
+        db.get_notification(user_1, id).await.unwrap(),
+        Some(notification.clone())
+    );
+
+    // Other recipients can't see the notification, as if it didn't exist.
+    assert_eq!(db.get_notification(user_2, id).await.unwrap(), None);
+    assert_eq!(
+        db.get_notification(user_1, NotificationId::from_proto(notification.id + 1))
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+test_both_dbs!(
+    test_get_notifications_with_actors,
+    test_get_notifications_with_actors_postgres,
+    test_get_notifications_with_actors_sqlite
+);
+
+async fn test_get_notifications_with_actors(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+
+    let notification_1 = create_contact_request(db, user_1, user_2).await;
+    let notification_2 = create_contact_request(db, user_1, user_3).await;
+    let notification_3 = create_notification(
+        db,
+        user_1,
+        Notification::ChannelInvitation {
+            channel_id: 1,
+            channel_name: "the-channel".into(),
+            inviter_id: user_2.to_proto(),
+        },
+    )
+    .await
+    .inserted()
+    .unwrap();
+
+    // Notifications from deleted users are still returned, without an actor.
+    db.destroy_user(user_3).await.unwrap();
+
+    let user_2_actor = NotificationActor {
+        id: user_2,
+        github_login: "user2".into(),
+    };
+    assert_eq!(
+        db.get_notifications_with_actors(user_1, 10, None)
+            .await
+            .unwrap(),
+        [
+            (notification_1, Some(user_2_actor.clone())),
+            (notification_2.clone(), None),
+            (notification_3.clone(), Some(user_2_actor)),
+        ]
+    );
+    assert_eq!(
+        db.get_notifications_with_actors(
+            user_1,
+            1,
+            Some(NotificationId::from_proto(notification_3.id))
+        )
+        .await
+        .unwrap(),
+        [(notification_2, None)]
+    );
+}
+
+test_both_dbs!(
+    test_get_notifications_by_cursor,
+    test_get_notifications_by_cursor_postgres,
+    test_get_notifications_by_cursor_sqlite
+);
+
+async fn test_get_notifications_by_cursor(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    let user_4 = new_test_user(db, "user4@example.com").await;
+
+    let notification_1 = create_contact_request(db, user_1, user_2).await;
+    let notification_2 = create_contact_request(db, user_1, user_3).await;
+    let notification_3 = create_contact_request(db, user_1, user_4).await;
+
+    let (page, cursor) = db
+        .get_notifications_by_cursor(user_1, 2, None)
+        .await
+        .unwrap();
+    assert_eq!(page, [notification_2.clone(), notification_3]);
+    assert_eq!(
+        cursor,
+        Some(encode_notification_cursor(NotificationId::from_proto(
+            notification_2.id
+        )))
+    );
+
+    let (page, cursor) = db
+        .get_notifications_by_cursor(user_1, 2, cursor)
+        .await
+        .unwrap();
+    assert_eq!(page, [notification_1]);
+    assert_eq!(cursor, None);
+
+    // Cursors round-trip, and invalid cursors are rejected rather than ignored.
+    let id = NotificationId::from_proto(notification_2.id);
+    assert_eq!(
+        decode_notification_cursor(&encode_notification_cursor(id)).unwrap(),
+        id
+    );
+    assert!(decode_notification_cursor("not a cursor").is_err());
+    assert!(db
+        .get_notifications_by_cursor(user_1, 2, Some("AAAA".into()))
+        .await
+        .is_err());
+}
+
+test_both_dbs!(
+    test_create_notification_with_idempotency_key,
+    test_create_notification_with_idempotency_key_postgres,
+    test_create_notification_with_idempotency_key_sqlite
+);
+
+async fn test_create_notification_with_idempotency_key(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+
+    let create = |recipient_id: UserId, idempotency_key: Option<Uuid>| async move {
+        db.transaction(|tx| async move {
+            db.create_notification_with_idempotency_key(
+                recipient_id,
+                Notification::ContactRequest {
+                    sender_id: user_3.to_proto(),
+                },
+                false,
+                None,
+                idempotency_key,
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap()
+    };
+
+    // Retrying with the same key returns the original notification's id.
+    let key = Uuid::new_v4();
+    let notification_1 = create(user_1, Some(key)).await.inserted().unwrap();
+    assert_eq!(
+        create(user_1, Some(key)).await,
+        CreateNotificationResult::Duplicate(NotificationId::from_proto(notification_1.id))
+    );
+    assert_eq!(
+        db.get_notifications(user_1, 10, None, None).await.unwrap(),
+        [notification_1.clone()]
+    );
+
+    // Keys are scoped to each recipient, and notifications without keys are distinct.
+    let notification_2 = create(user_2, Some(key)).await.inserted().unwrap();
+    assert_ne!(notification_2.id, notification_1.id);
+    let notification_3 = create(user_1, None).await.inserted().unwrap();
+    let notification_4 = create(user_1, None).await.inserted().unwrap();
+    assert_eq!(
+        db.get_notifications(user_1, 10, None, None).await.unwrap(),
+        [notification_1, notification_3, notification_4]
+    );
+}
+
+test_both_dbs!(
+    test_invalid_notifications,
+    test_invalid_notifications_postgres,
+    test_invalid_notifications_sqlite
+);
+
+async fn test_invalid_notifications(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+
+    let notification_1 = create_contact_request(db, user_1, user_2).await;
+    let notification_2 = create_contact_request(db, user_1, user_3).await;
+    let corrupt_id = NotificationId::from_proto(notification_2.id);
+    db.transaction(|tx| async move {
+        notification::Entity::update_many()
+            .col_expr(notification::Column::Content, Expr::value("{not json"))
+            .filter(notification::Column::Id.eq(corrupt_id))
+            .exec(&*tx)
+            .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+
+    // Notifications with invalid content are skipped, rather than failing the query.
+    assert_eq!(
+        db.get_notifications(user_1, 10, None, None).await.unwrap(),
+        [notification_1.clone()]
+    );
+    assert_eq!(db.get_notification(user_1, corrupt_id).await.unwrap(), None);
+
+    assert_eq!(db.delete_invalid_notifications().await.unwrap(), 1);
+    assert_eq!(db.delete_invalid_notifications().await.unwrap(), 0);
+    assert_eq!(
+        db.get_notifications(user_1, 10, None, None).await.unwrap(),
+        [notification_1]
+    );
+}
+
+test_both_dbs!(
+    test_notifications_changed_since,
+    test_notifications_changed_since_postgres,
+    test_notifications_changed_since_sqlite
+);
+
+async fn test_notifications_changed_since(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+    let user_4 = new_test_user(db, "user4@example.com").await;
+    let user_5 = new_test_user(db, "user5@example.com").await;
+    let user_6 = new_test_user(db, "user6@example.com").await;
+
+    create_contact_request(db, user_1, user_6).await;
+    let notification_1 = create_contact_request(db, user_1, user_2).await;
+    let notification_2 = create_contact_request(db, user_1, user_3).await;
+    let notification_3 = create_contact_request(db, user_1, user_4).await;
+    let since_id = NotificationId::from_proto(notification_3.id);
+
+    // The client caught up before any of the changes below were made.
+    let an_hour_ago = OffsetDateTime::now_utc() - Duration::hours(1);
+    let an_hour_ago = PrimitiveDateTime::new(an_hour_ago.date(), an_hour_ago.time());
+    db.transaction(|tx| async move {
+        notification::Entity::update_many()
+            .col_expr(notification::Column::CreatedAt, Expr::value(an_hour_ago))
+            .col_expr(notification::Column::UpdatedAt, Expr::value(an_hour_ago))
+            .filter(notification::Column::RecipientId.eq(user_1))
+            .exec(&*tx)
+            .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+    let since = OffsetDateTime::now_utc() - Duration::minutes(30);
+
+    db.mark_notification_as_read(user_1, NotificationId::from_proto(notification_1.id))
+        .await
+        .unwrap();
+    let remove = |sender_id: UserId| async move {
+        db.transaction(|tx| async move {
+            db.remove_notification_any(
+                user_1,
+                Notification::ContactRequest {
+                    sender_id: sender_id.to_proto(),
+                },
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap()
+        .unwrap()
+    };
+    remove(user_3).await;
+    let notification_4 = create_contact_request(db, user_1, user_5).await;
+
+    // Notifications that others are coalesced into are reported as changed.
+    let coalesced = db
+        .transaction(|tx| async move {
+            db.create_or_coalesce_notification(
+                user_1,
+                Notification::ContactRequest {
+                    sender_id: user_6.to_proto(),
+                },
+                &tx,
+            )
+            .await
+        })
+        .await
+        .unwrap()
+        .notification()
+        .unwrap();
+
+    let notification_1 = db
+        .get_notification(user_1, NotificationId::from_proto(notification_1.id))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(notification_1.is_read);
+    assert_eq!(
+        db.notifications_changed_since(user_1, since_id, since)
+            .await
+            .unwrap(),
+        (
+            vec![
+                notification_4.clone(),
+                coalesced.clone(),
+                notification_1.clone()
+            ],
+            vec![NotificationId::from_proto(notification_2.id)]
+        )
+    );
+
+    // Clients can catch up even if the last notification they have was removed.
+    remove(user_4).await;
+    assert_eq!(
+        db.notifications_changed_since(user_1, since_id, since)
+            .await
+            .unwrap(),
+        (
+            vec![
+                notification_4.clone(),
+                coalesced.clone(),
+                notification_1.clone()
+            ],
+            vec![
+                NotificationId::from_proto(notification_2.id),
+                NotificationId::from_proto(notification_3.id)
+            ]
+        )
+    );
+
+    // Or if it has been pruned since.
+    set_created_at(
+        db,
+        &notification_3,
+        OffsetDateTime::now_utc() - Duration::days(2),
+    )
+    .await;
+    assert_eq!(
+        db.delete_notifications_older_than(OffsetDateTime::now_utc() - Duration::days(1))
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        db.notifications_changed_since(user_1, since_id, since)
+            .await
+            .unwrap(),
+        (
+            vec![notification_4, coalesced, notification_1],
+            vec![NotificationId::from_proto(notification_2.id)]
+        )
+    );
+
+    // Only the given recipient's notifications are reported.
+    assert_eq!(
+        db.notifications_changed_since(user_2, since_id, since)
+            .await
+            .unwrap(),
+        (Vec::new(), Vec::new())
+    );
+}
+
+test_both_dbs!(
+    test_notifications_with_identical_timestamps,
+    test_notifications_with_identical_timestamps_postgres,
+    test_notifications_with_identical_timestamps_sqlite
+);
+
+async fn test_notifications_with_identical_timestamps(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let mut ids = Vec::new();
+    let created_at = OffsetDateTime::now_utc();
+    for i in 0..3 {
+        let sender = new_test_user(db, &format!("sender{i}@example.com")).await;
+        let notification = create_contact_request(db, user_1, sender).await;
+        set_created_at(db, &notification, created_at).await;
+        ids.push(notification.id);
+    }
+
+    // Notifications created within the same second are still ordered by their ids.
+    let notifications = db.get_notifications(user_1, 10, None, None).await.unwrap();
+    assert_eq!(
+        notifications
+            .iter()
+            .map(|notification| notification.id)
+            .collect::<Vec<_>>(),
+        ids
+    );
+    assert!(notifications
+        .iter()
+        .all(|notification| notification.timestamp == notifications[0].timestamp));
+}
+
+test_both_dbs!(
+    test_notification_read_at,
+    test_notification_read_at_postgres,
+    test_notification_read_at_sqlite
+);
+
+async fn test_notification_read_at(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let user_3 = new_test_user(db, "user3@example.com").await;
+
+    let notification_1 = create_contact_request(db, user_1, user_2).await;
+    let notification_2 = create_contact_request(db, user_1, user_3).await;
+    assert_eq!(notification_1.read_at, None);
+
+    db.mark_notification_as_read(user_1, NotificationId::from_proto(notification_1.id))
+        .await
+        .unwrap();
+    let read_at = |notifications: &[proto::Notification], id: u64| {
+        notifications
+            .iter()
+            .find(|notification| notification.id == id)
+            .unwrap()
+            .read_at
+    };
+    let notifications = db.get_notifications(user_1, 10, None, None).await.unwrap();
+    assert!(read_at(&notifications, notification_1.id).is_some());
+    assert_eq!(read_at(&notifications, notification_2.id), None);
+
+    // Marking a notification as read again keeps the time it was first read.
+    let first_read_at = OffsetDateTime::now_utc() - Duration::days(1);
+    let first_read_at_timestamp = first_read_at.unix_timestamp() as u64;
+    let first_read_at = PrimitiveDateTime::new(first_read_at.date(), first_read_at.time());
+    db.transaction(|tx| async move {
+        notification::Entity::update_many()
+            .col_expr(notification::Column::ReadAt, Expr::value(first_read_at))
+            .filter(notification::Column::RecipientId.eq(user_1))
+            .filter(notification::Column::IsRead.eq(true))
+            .exec(&*tx)
+            .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+    let notification = db
+        .mark_notification_as_read(user_1, NotificationId::from_proto(notification_1.id))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(notification.read_at, Some(first_read_at_timestamp));
+    db.mark_all_notifications_as_read(user_1).await.unwrap();
+    let notifications = db.get_notifications(user_1, 10, None, None).await.unwrap();
+    assert_eq!(
+        read_at(&notifications, notification_1.id),
+        Some(first_read_at_timestamp)
+    );
+    assert!(read_at(&notifications, notification_2.id).is_some());
+}
+
+test_both_dbs!(
+    test_soft_deleted_notifications,
+    test_soft_deleted_notifications_postgres,
+    test_soft_deleted_notifications_sqlite
+);
+
+async fn test_soft_deleted_notifications(db: &Arc<Database>) {
+    let user_1 = new_test_user(db, "user1@example.com").await;
+    let user_2 = new_test_user(db, "user2@example.com").await;
+    let notification = Notification::ContactRequest {
+        sender_id: user_2.to_proto(),
+    };
+
+    let created = create_contact_request(db, user_1, user_2).await;
+    let id = NotificationId::from_proto(created.id);
+    let removed_id = db
+        .transaction(|tx| {
+            let notification = notification.clone();
+            async move { db.remove_notification(user_1, notification, &tx).await }
+        })
+        .await
+        .unwrap();
+    assert_eq!(removed_id, Some(id));
+
+    // Removed notifications are excluded from every query.
+    assert!(db
+        .get_notifications(user_1, 10, None, None)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(db.get_notification(user_1, id).await.unwrap(), None);
+    assert_eq!(db.unread_notification_count(user_1).await.unwrap(), 0);
+    assert!(db
+        .search_notifications(user_1, "", 10)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        db.mark_notification_as_read(user_1, id).await.unwrap(),
+        None
+    );
+
+    // Other recipients can't restore the notification.
+    assert_eq!(db.restore_notification(user_2, id).await.unwrap(), None);
+    assert_eq!(
+        db.restore_notification(user_1, id).await.unwrap(),
+        Some(created.clone())
+    );
+    assert_eq!(
+        db.get_notifications(user_1, 10, None, None).await.unwrap(),
+        &[created.clone()]
+    );
+    assert_eq!(db.restore_notification(user_1, id).await.unwrap(), None);
+
+    // Removed notifications are deleted permanently once they're pruned.
+    db.transaction(|tx| {
+        let notification = notification.clone();
+        async move { db.remove_notification(user_1, notification, &tx).await }
+    })
+    .await
+    .unwrap();
+    set_created_at(db, &created, OffsetDateTime::now_utc() - Duration::days(30)).await;
+    assert_eq!(
+        db.delete_notifications_older_than(OffsetDateTime::now_utc() - Duration::days(7))
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(db.restore_notification(user_1, id).await.unwrap(), None);
+}
+
+async fn set_created_at(
+    db: &Arc<Database>,
+    notification: &proto::Notification,
+    created_at: OffsetDateTime,
+) {
+    let id = NotificationId::from_proto(notification.id);
+    let created_at = PrimitiveDateTime::new(created_at.date(), created_at.time());
+    db.transaction(|tx| async move {
+        notification::Entity::update_many()
+            .col_expr(notification::Column::CreatedAt, Expr::value(created_at))
+            .filter(notification::Column::Id.eq(id))
+            .exec(&*tx)
+            .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+async fn create_contact_request(
+    db: &Arc<Database>,
+    recipient_id: UserId,
+    sender_id: UserId,
+) -> proto::Notification {
+    create_notification(
+        db,
+        recipient_id,
+        Notification::ContactRequest {
+            sender_id: sender_id.to_proto(),
+        },
+    )
+    .await
+    .inserted()
+    .unwrap()
+}
+
+async fn create_notification(
+    db: &Arc<Database>,
+    recipient_id: UserId,
+    notification: Notification,
+) -> CreateNotificationResult {
+    db.transaction(|tx| {
+        let notification = notification.clone();
+        async move {
+            db.create_notification(recipient_id, notification, false, None, &tx)
+                .await
+        }
+    })
+    .await
+    .unwrap()
+}
@@ -1,7 +1,7 @@
 use anyhow::anyhow;
 use axum::{routing::get, Extension, Router};
 use collab::{db, env, executor::Executor, AppState, Config, MigrateConfig, Result};
-use db::Database;
+use db::{Database, NotificationOptions};
 use std::{
     env::args,
     net::{SocketAddr, TcpListener},
@@ -32,7 +32,12 @@ async fn main() -> Result<()> {
             let config = envy::from_env::<MigrateConfig>().expect("error loading config");
             let mut db_options = db::ConnectOptions::new(config.database_url.clone());
             db_options.max_connections(5);
-            let db = Database::new(db_options, Executor::Production).await?;
+            let db = Database::new(
+                db_options,
+                NotificationOptions::default(),
+                Executor::Production,
+            )
+            .await?;
 
             let migrations_path = config
                 .migrations_path
@@ -61,7 +66,12 @@ async fn main() -> Result<()> {
                 }
                 let migrations_path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"));
                 let db_options = db::ConnectOptions::new(config.database_url.clone());
-                let db = Database::new(db_options, Executor::Production).await?;
+                let db = Database::new(
+                    db_options,
+                    NotificationOptions::default(),
+                    Executor::Production,
+                )
+                .await?;
 
                 let migrations = db.migrate(&migrations_path, false).await?;
                 for (migration, duration) in migrations {
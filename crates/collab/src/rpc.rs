@@ -3190,6 +3190,7 @@ async fn get_notifications(
             request
                 .before_id
                 .map(|id| db::NotificationId::from_proto(id)),
+            None,
         )
         .await?;
     response.send(proto::GetNotificationsResponse {
@@ -3206,8 +3207,8 @@ async fn mark_notification_as_read(
     session: Session,
 ) -> Result<()> {
     let database = &session.db().await;
-    let notifications = database
-        .mark_notification_as_read_by_id(
+    let notification = database
+        .mark_notification_as_read(
             session.user_id,
             NotificationId::from_proto(request.notification_id),
         )
@@ -3215,7 +3216,10 @@ async fn mark_notification_as_read(
     send_notifications(
         &*session.connection_pool().await,
         &session.peer,
-        notifications,
+        notification
+            .into_iter()
+            .map(|notification| (session.user_id, notification))
+            .collect(),
     );
     response.send(proto::Ack {})?;
     Ok(())
@@ -444,10 +444,20 @@ impl ChatPanel {
             .map(|(range, user_id)| rich_text::Mention {
                 range: range.clone(),
                 is_self_mention: *user_id == current_user_id,
+                user_id: Some(*user_id),
             })
             .collect::<Vec<_>>();
 
-        rich_text::render_markdown(message.body.clone(), &mentions, language_registry, None)
+        rich_text::render_markdown_with_options(
+            message.body.clone(),
+            &mentions,
+            language_registry,
+            None,
+            &rich_text::RenderMarkdownOptions {
+                expand_emoji_shortcodes: true,
+                ..Default::default()
+            },
+        )
     }
 
     fn send(&mut self, _: &Confirm, cx: &mut ViewContext<Self>) {
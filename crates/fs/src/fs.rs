@@ -410,6 +410,8 @@ impl FakeFsState {
                 event_id: 0,
                 flags: fsevent::StreamFlags::empty(),
                 path: path.into(),
+                inode: None,
+                root: None,
             }));
 
         if !self.events_paused {
@@ -1,257 +1,325 @@
-#![cfg(target_os = "macos")]
-
 use bitflags::bitflags;
-use fsevent_sys::{self as fs, core_foundation as cf};
 use parking_lot::Mutex;
 use std::{
-    convert::AsRef,
-    ffi::{c_void, CStr, OsStr},
-    os::unix::ffi::OsStrExt,
+    collections::{hash_map, HashMap},
+    mem,
     path::{Path, PathBuf},
-    ptr, slice,
     sync::Arc,
     time::Duration,
 };
 
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "macos")]
+mod mac;
+mod stream;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(target_os = "linux")]
+pub use linux::*;
+#[cfg(target_os = "macos")]
+pub use mac::*;
+pub use stream::*;
+#[cfg(windows)]
+pub use windows::*;
+
+/// The shortest latency that a stream can have. FSEvents doesn't document a minimum, but
+/// shorter latencies would deliver nearly every change in its own batch, and keep the
+/// stream's thread busy during bursts of changes.
+pub const MIN_LATENCY: Duration = Duration::from_millis(10);
+
 #[derive(Clone, Debug)]
 pub struct Event {
+    /// Increases with each event. On macOS, this is the FSEvents id, which can be
+    /// persisted and passed to `EventStream::new_since` to resume watching from this
+    /// event. On other platforms, ids are assigned by each stream, starting from 1.
     pub event_id: u64,
     pub flags: StreamFlags,
     pub path: PathBuf,
+    /// The inode of `path`, if `EventStream::report_inodes` was called. On macOS, this
+    /// comes from FSEvents' extended data, which requires macOS 10.13. On Linux, it's read
+    /// when the event is observed, so it's `None` if the path no longer exists. On Windows,
+    /// this is the file index, which is likewise read when the event is observed.
+    pub inode: Option<u64>,
+    /// The watched path that `path` is within, choosing the longest one if watched paths
+    /// overlap. Paths are compared as given, so this is `None` if the watched path was
+    /// given in a different form than the platform reports, such as through a symlink,
+    /// or if it has since been removed from the stream.
+    pub root: Option<PathBuf>,
 }
 
-pub struct EventStream {
-    lifecycle: Arc<Mutex<Lifecycle>>,
-    state: Box<State>,
+/// Why `EventStream::run` returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The stream's `Handle` was stopped or dropped.
+    Stopped,
+    /// The callback returned false.
+    CallbackReturnedFalse,
+    /// Waiting for changes failed with the given OS error code, so no more events can be
+    /// delivered.
+    Failed(i32),
 }
 
-struct State {
-    latency: Duration,
-    paths: cf::CFMutableArrayRef,
-    callback: Option<Box<dyn FnMut(Vec<Event>) -> bool>>,
-    last_valid_event_id: Option<fs::FSEventStreamEventId>,
-    stream: fs::FSEventStreamRef,
-}
+/// The error returned by [`Handle::add_path`] when a stream is already watching as many
+/// paths as it can, which only happens on Windows. See `MAX_WATCHED_PATHS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TooManyPathsError;
 
-impl Drop for State {
-    fn drop(&mut self) {
-        unsafe {
-            cf::CFRelease(self.paths);
-            fs::FSEventStreamStop(self.stream);
-            fs::FSEventStreamInvalidate(self.stream);
-            fs::FSEventStreamRelease(self.stream);
-        }
+impl std::fmt::Display for TooManyPathsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("the stream is already watching as many paths as it can")
     }
 }
 
-enum Lifecycle {
-    New,
-    Running(cf::CFRunLoopRef),
-    Stopped,
+impl std::error::Error for TooManyPathsError {}
+
+/// A normalized classification of what happened to an event's path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// Historical events have all been reported, and the events that follow are live.
+    /// Only reported by streams created with `EventStream::new_since`.
+    HistoryDone,
+    Created,
+    Modified,
+    MetadataModified,
+    Renamed,
+    Removed,
+    /// Events were dropped, so the event's path must be rescanned, including everything
+    /// within it.
+    MustScanSubdirs,
 }
 
-pub struct Handle(Arc<Mutex<Lifecycle>>);
+impl Event {
+    /// The kinds of this event, ordered by priority, with the actions on the event's path
+    /// in the order they usually happen.
+    ///
+    /// FSEvents coalesces the actions that occur on a path in quick succession into one
+    /// event, without recording their order, so several kinds may be reported at once.
+    pub fn kinds(&self) -> Vec<EventKind> {
+        [
+            (StreamFlags::HISTORY_DONE, EventKind::HistoryDone),
+            (StreamFlags::ITEM_CREATED, EventKind::Created),
+            (StreamFlags::ITEM_MODIFIED, EventKind::Modified),
+            (
+                StreamFlags::INODE_META_MOD
+                    | StreamFlags::FINDER_INFO_MOD
+                    | StreamFlags::ITEM_CHANGE_OWNER
+                    | StreamFlags::ITEM_XATTR_MOD,
+                EventKind::MetadataModified,
+            ),
+            (StreamFlags::ITEM_RENAMED, EventKind::Renamed),
+            (StreamFlags::ITEM_REMOVED, EventKind::Removed),
+            (
+                StreamFlags::MUST_SCAN_SUBDIRS
+                    | StreamFlags::USER_DROPPED
+                    | StreamFlags::KERNEL_DROPPED,
+                EventKind::MustScanSubdirs,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(flags, kind)| self.flags.intersects(flags).then_some(kind))
+        .collect()
+    }
 
-unsafe impl Send for EventStream {}
-unsafe impl Send for Lifecycle {}
+    /// The highest priority kind of this event, which is a request to rescan its path if
+    /// events were dropped, or otherwise the most recent action on its path. Events that
+    /// don't describe a change, such as those for a watched path's root changing, have no
+    /// kind.
+    pub fn kind(&self) -> Option<EventKind> {
+        self.kinds().pop()
+    }
+}
 
 impl EventStream {
-    pub fn new(paths: &[&Path], latency: Duration) -> (Self, Handle) {
-        unsafe {
-            let cf_paths =
-                cf::CFArrayCreateMutable(cf::kCFAllocatorDefault, 0, &cf::kCFTypeArrayCallBacks);
-            assert!(!cf_paths.is_null());
-
-            for path in paths {
-                let path_bytes = path.as_os_str().as_bytes();
-                let cf_url = cf::CFURLCreateFromFileSystemRepresentation(
-                    cf::kCFAllocatorDefault,
-                    path_bytes.as_ptr() as *const i8,
-                    path_bytes.len() as cf::CFIndex,
-                    false,
-                );
-                let cf_path = cf::CFURLCopyFileSystemPath(cf_url, cf::kCFURLPOSIXPathStyle);
-                cf::CFArrayAppendValue(cf_paths, cf_path);
-                cf::CFRelease(cf_path);
-                cf::CFRelease(cf_url);
-            }
+    /// Like `new`, but the events in each batch that have the same path are merged into
+    /// one, as described by [`coalesce_events`], so that each changed path is reported once.
+    pub fn new_coalesced(paths: &[&Path], latency: Duration) -> (Self, Handle) {
+        let (mut stream, handle) = Self::new(paths, latency);
+        stream.pipeline.coalesce = true;
+        (stream, handle)
+    }
 
-            let mut state = Box::new(State {
-                latency,
-                paths: cf_paths,
-                callback: None,
-                last_valid_event_id: None,
-                stream: ptr::null_mut(),
-            });
-            let stream_context = fs::FSEventStreamContext {
-                version: 0,
-                info: state.as_ref() as *const _ as *mut c_void,
-                retain: None,
-                release: None,
-                copy_description: None,
-            };
-            let stream = fs::FSEventStreamCreate(
-                cf::kCFAllocatorDefault,
-                Self::trampoline,
-                &stream_context,
-                cf_paths,
-                FSEventsGetCurrentEventId(),
-                latency.as_secs_f64(),
-                fs::kFSEventStreamCreateFlagFileEvents
-                    | fs::kFSEventStreamCreateFlagNoDefer
-                    | fs::kFSEventStreamCreateFlagWatchRoot,
-            );
-            state.stream = stream;
+    /// Discards the events whose paths match the given predicate, such as those within
+    /// `.git` or `target` directories, before they're passed to the callback given to
+    /// `run`. Batches whose events are all ignored aren't passed to the callback at all.
+    pub fn ignore_paths(&mut self, is_ignored: impl Fn(&Path) -> bool + Send + 'static) {
+        self.pipeline.is_ignored = Some(Box::new(is_ignored));
+    }
 
-            let lifecycle = Arc::new(Mutex::new(Lifecycle::New));
-            (
-                EventStream {
-                    lifecycle: lifecycle.clone(),
-                    state,
-                },
-                Handle(lifecycle),
-            )
-        }
-    }
-
-    pub fn run<F>(mut self, f: F)
-    where
-        F: FnMut(Vec<Event>) -> bool + 'static,
-    {
-        self.state.callback = Some(Box::new(f));
-        unsafe {
-            let run_loop = cf::CFRunLoopGetCurrent();
-            {
-                let mut state = self.lifecycle.lock();
-                match *state {
-                    Lifecycle::New => *state = Lifecycle::Running(run_loop),
-                    Lifecycle::Running(_) => unreachable!(),
-                    Lifecycle::Stopped => return,
-                }
-            }
-            fs::FSEventStreamScheduleWithRunLoop(
-                self.state.stream,
-                run_loop,
-                cf::kCFRunLoopDefaultMode,
-            );
-            fs::FSEventStreamStart(self.state.stream);
-            cf::CFRunLoopRun();
-        }
-    }
-
-    extern "C" fn trampoline(
-        stream_ref: fs::FSEventStreamRef,
-        info: *mut ::std::os::raw::c_void,
-        num: usize,                                 // size_t numEvents
-        event_paths: *mut ::std::os::raw::c_void,   // void *eventPaths
-        event_flags: *const ::std::os::raw::c_void, // const FSEventStreamEventFlags eventFlags[]
-        event_ids: *const ::std::os::raw::c_void,   // const FSEventStreamEventId eventIds[]
-    ) {
-        unsafe {
-            let event_paths = event_paths as *const *const ::std::os::raw::c_char;
-            let e_ptr = event_flags as *mut u32;
-            let i_ptr = event_ids as *mut u64;
-            let state = (info as *mut State).as_mut().unwrap();
-            let callback = if let Some(callback) = state.callback.as_mut() {
-                callback
-            } else {
-                return;
-            };
+    /// Splits batches with more than `max_batch_len` events into several batches, which are
+    /// passed to the callback given to `run` in order. The stream's latency still determines
+    /// how long events are collected before they're delivered, so a burst of changes such as
+    /// a `git checkout` arrives at the same time, but in smaller pieces. The limit applies
+    /// after ignored paths are discarded and events are coalesced.
+    pub fn set_max_batch_len(&mut self, max_batch_len: usize) {
+        self.pipeline.max_batch_len = Some(max_batch_len.max(1));
+    }
+}
 
-            let paths = slice::from_raw_parts(event_paths, num);
-            let flags = slice::from_raw_parts_mut(e_ptr, num);
-            let ids = slice::from_raw_parts_mut(i_ptr, num);
-            let mut stream_restarted = false;
-
-            // Sometimes FSEvents reports a "dropped" event, an indication that either the kernel
-            // or our code couldn't keep up with the sheer volume of file-system events that were
-            // generated. If we observed a valid event before this happens, we'll try to read the
-            // file-system journal by stopping the current stream and creating a new one starting at
-            // such event. Otherwise, we'll let invoke the callback with the dropped event, which
-            // will likely perform a re-scan of one of the root directories.
-            if flags
-                .iter()
-                .copied()
-                .filter_map(StreamFlags::from_bits)
-                .any(|flags| {
-                    flags.contains(StreamFlags::USER_DROPPED)
-                        || flags.contains(StreamFlags::KERNEL_DROPPED)
-                })
-            {
-                if let Some(last_valid_event_id) = state.last_valid_event_id.take() {
-                    fs::FSEventStreamStop(state.stream);
-                    fs::FSEventStreamInvalidate(state.stream);
-                    fs::FSEventStreamRelease(state.stream);
-
-                    let stream_context = fs::FSEventStreamContext {
-                        version: 0,
-                        info,
-                        retain: None,
-                        release: None,
-                        copy_description: None,
-                    };
-                    let stream = fs::FSEventStreamCreate(
-                        cf::kCFAllocatorDefault,
-                        Self::trampoline,
-                        &stream_context,
-                        state.paths,
-                        last_valid_event_id,
-                        state.latency.as_secs_f64(),
-                        fs::kFSEventStreamCreateFlagFileEvents
-                            | fs::kFSEventStreamCreateFlagNoDefer
-                            | fs::kFSEventStreamCreateFlagWatchRoot,
-                    );
-
-                    state.stream = stream;
-                    fs::FSEventStreamScheduleWithRunLoop(
-                        state.stream,
-                        cf::CFRunLoopGetCurrent(),
-                        cf::kCFRunLoopDefaultMode,
-                    );
-                    fs::FSEventStreamStart(state.stream);
-                    stream_restarted = true;
-                }
+impl Handle {
+    /// Starts watching the given path in addition to the stream's other paths, without
+    /// interrupting the delivery of their events. Fails if the stream is already watching
+    /// as many paths as it can.
+    pub fn add_path(&self, path: &Path) -> Result<(), TooManyPathsError> {
+        let mut watched_paths = self.watched_paths.lock();
+        if !watched_paths
+            .paths
+            .iter()
+            .any(|watched_path| watched_path == path)
+        {
+            #[cfg(windows)]
+            if watched_paths.paths.len() >= MAX_WATCHED_PATHS {
+                return Err(TooManyPathsError);
             }
+            watched_paths.paths.push(path.to_path_buf());
+            watched_paths.changed = true;
+            drop(watched_paths);
+            self.notify_changed();
+        }
+        Ok(())
+    }
 
-            if !stream_restarted {
-                let mut events = Vec::with_capacity(num);
-                for p in 0..num {
-                    if let Some(flag) = StreamFlags::from_bits(flags[p]) {
-                        if !flag.contains(StreamFlags::HISTORY_DONE) {
-                            let path_c_str = CStr::from_ptr(paths[p]);
-                            let path = PathBuf::from(OsStr::from_bytes(path_c_str.to_bytes()));
-                            let event = Event {
-                                event_id: ids[p],
-                                flags: flag,
-                                path,
-                            };
-                            state.last_valid_event_id = Some(event.event_id);
-                            events.push(event);
-                        }
-                    } else {
-                        debug_assert!(false, "unknown flag set for fs event: {}", flags[p]);
-                    }
-                }
+    /// Stops watching the given path, which must have been passed to `EventStream::new`
+    /// or `add_path`.
+    pub fn remove_path(&self, path: &Path) {
+        let mut watched_paths = self.watched_paths.lock();
+        let len = watched_paths.paths.len();
+        watched_paths
+            .paths
+            .retain(|watched_path| watched_path != path);
+        if watched_paths.paths.len() < len {
+            watched_paths.changed = true;
+            drop(watched_paths);
+            self.notify_changed();
+        }
+    }
 
-                if !events.is_empty() && !callback(events) {
-                    fs::FSEventStreamStop(stream_ref);
-                    cf::CFRunLoopStop(cf::CFRunLoopGetCurrent());
+    /// Changes how long the stream waits after a change before delivering a batch of
+    /// events, including a batch that's already being collected. Latencies shorter than
+    /// [`MIN_LATENCY`] are clamped to it. On macOS, the FSEvents stream is recreated,
+    /// starting after the last event that was delivered, so that no events are missed.
+    pub fn set_latency(&self, latency: Duration) {
+        self.watched_paths.lock().latency = Some(latency.max(MIN_LATENCY));
+        self.notify_changed();
+    }
+}
+
+/// The paths that a stream should watch, which are shared with its handle so that they
+/// can be changed while it's running, along with a new latency if one has been set.
+pub(crate) struct WatchedPaths {
+    paths: Vec<PathBuf>,
+    changed: bool,
+    latency: Option<Duration>,
+}
+
+impl WatchedPaths {
+    pub(crate) fn new(paths: &[&Path]) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            paths: paths.iter().map(|path| path.to_path_buf()).collect(),
+            changed: false,
+            latency: None,
+        }))
+    }
+
+    #[cfg(target_os = "macos")]
+    pub(crate) fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// The longest of the watched paths that contains the given path.
+    fn root_for(&self, path: &Path) -> Option<&Path> {
+        self.paths
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .map(PathBuf::as_path)
+    }
+
+    /// Returns the paths to watch if they've changed since this was last called.
+    pub(crate) fn take_changed(&mut self) -> Option<Vec<PathBuf>> {
+        mem::take(&mut self.changed).then(|| self.paths.clone())
+    }
+
+    /// Returns the stream's new latency if it's been set since this was last called.
+    pub(crate) fn take_latency(&mut self) -> Option<Duration> {
+        self.latency.take()
+    }
+}
+
+/// The processing that's applied to each batch of events before it's passed to the
+/// callback given to `EventStream::run`.
+#[derive(Default)]
+pub(crate) struct Pipeline {
+    watched_paths: Option<Arc<Mutex<WatchedPaths>>>,
+    coalesce: bool,
+    is_ignored: Option<Box<PathPredicate>>,
+    max_batch_len: Option<usize>,
+}
+
+type PathPredicate = dyn Fn(&Path) -> bool + Send;
+
+impl Pipeline {
+    /// Creates a pipeline that sets the root of each event to one of the given paths.
+    pub(crate) fn new(watched_paths: Arc<Mutex<WatchedPaths>>) -> Self {
+        Self {
+            watched_paths: Some(watched_paths),
+            ..Default::default()
+        }
+    }
+
+    /// Passes the processed batch to the callback, unless it's empty, returning whether
+    /// the stream should keep running. Batches that are too long are split, and the rest
+    /// of a split batch is discarded if the callback returns false.
+    pub(crate) fn deliver(
+        &self,
+        mut events: Vec<Event>,
+        callback: &mut dyn FnMut(Vec<Event>) -> bool,
+    ) -> bool {
+        if let Some(is_ignored) = &self.is_ignored {
+            events.retain(|event| !is_ignored(&event.path));
+        }
+        if let Some(watched_paths) = &self.watched_paths {
+            let watched_paths = watched_paths.lock();
+            for event in &mut events {
+                event.root = watched_paths.root_for(&event.path).map(Path::to_path_buf);
+            }
+        }
+        if self.coalesce {
+            events = coalesce_events(events);
+        }
+        if let Some(max_batch_len) = self.max_batch_len {
+            while events.len() > max_batch_len {
+                let rest = events.split_off(max_batch_len);
+                if !callback(mem::replace(&mut events, rest)) {
+                    return false;
                 }
             }
         }
+        events.is_empty() || callback(events)
     }
 }
 
-impl Drop for Handle {
-    fn drop(&mut self) {
-        let mut state = self.0.lock();
-        if let Lifecycle::Running(run_loop) = *state {
-            unsafe {
-                cf::CFRunLoopStop(run_loop);
+/// Merges the events in a batch that have the same path into one event with all of their
+/// flags, whose kind is the highest priority one as described by [`Event::kind`]. Merged
+/// events keep the position of the first event for their path, and the id of the last.
+pub fn coalesce_events(events: Vec<Event>) -> Vec<Event> {
+    let mut coalesced = Vec::<Event>::with_capacity(events.len());
+    let mut indices_by_path = HashMap::<PathBuf, usize>::new();
+    for event in events {
+        match indices_by_path.entry(event.path.clone()) {
+            hash_map::Entry::Occupied(entry) => {
+                let coalesced_event = &mut coalesced[*entry.get()];
+                coalesced_event.flags |= event.flags;
+                coalesced_event.event_id = coalesced_event.event_id.max(event.event_id);
+            }
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(coalesced.len());
+                coalesced.push(event);
             }
         }
-        *state = Lifecycle::Stopped;
     }
+    coalesced
 }
 
 // Synchronize with
@@ -361,15 +429,250 @@ impl std::fmt::Display for StreamFlags {
     }
 }
 
-#[link(name = "CoreServices", kind = "framework")]
-extern "C" {
-    pub fn FSEventsGetCurrentEventId() -> u64;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_kind() {
+        let event = |flags| Event {
+            event_id: 0,
+            flags,
+            path: PathBuf::new(),
+            inode: None,
+            root: None,
+        };
+
+        let created = event(StreamFlags::ITEM_CREATED | StreamFlags::IS_FILE);
+        assert_eq!(created.kinds(), [EventKind::Created]);
+        assert_eq!(created.kind(), Some(EventKind::Created));
+
+        let saved = event(
+            StreamFlags::ITEM_CREATED
+                | StreamFlags::ITEM_MODIFIED
+                | StreamFlags::ITEM_XATTR_MOD
+                | StreamFlags::ITEM_RENAMED
+                | StreamFlags::IS_FILE,
+        );
+        assert_eq!(
+            saved.kinds(),
+            [
+                EventKind::Created,
+                EventKind::Modified,
+                EventKind::MetadataModified,
+                EventKind::Renamed
+            ]
+        );
+        assert_eq!(saved.kind(), Some(EventKind::Renamed));
+
+        let removed = event(StreamFlags::ITEM_CREATED | StreamFlags::ITEM_REMOVED);
+        assert_eq!(removed.kind(), Some(EventKind::Removed));
+
+        let root_changed = event(StreamFlags::ROOT_CHANGED);
+        assert_eq!(root_changed.kinds(), []);
+        assert_eq!(root_changed.kind(), None);
+    }
+
+    #[test]
+    fn test_event_kind_for_stream_events() {
+        let event = |flags| Event {
+            event_id: 0,
+            flags: StreamFlags::from_bits(flags).unwrap(),
+            path: PathBuf::new(),
+            inode: None,
+            root: None,
+        };
+
+        // kFSEventStreamEventFlagMustScanSubDirs
+        assert_eq!(event(0x1).kind(), Some(EventKind::MustScanSubdirs));
+        // kFSEventStreamEventFlagMustScanSubDirs | kFSEventStreamEventFlagUserDropped
+        assert_eq!(event(0x1 | 0x2).kind(), Some(EventKind::MustScanSubdirs));
+        // kFSEventStreamEventFlagMustScanSubDirs | kFSEventStreamEventFlagKernelDropped
+        assert_eq!(event(0x1 | 0x4).kind(), Some(EventKind::MustScanSubdirs));
+        // kFSEventStreamEventFlagHistoryDone
+        assert_eq!(event(0x10).kind(), Some(EventKind::HistoryDone));
+
+        // A rescan takes priority over any changes coalesced into the same event.
+        assert_eq!(
+            event(0x1 | 0x100 | 0x200).kinds(),
+            [
+                EventKind::Created,
+                EventKind::Removed,
+                EventKind::MustScanSubdirs
+            ]
+        );
+        assert_eq!(
+            event(0x1 | 0x100 | 0x200).kind(),
+            Some(EventKind::MustScanSubdirs)
+        );
+    }
+
+    #[test]
+    fn test_coalesce_events() {
+        let event = |event_id, flags, path: &str| Event {
+            event_id,
+            flags,
+            path: PathBuf::from(path),
+            inode: None,
+            root: None,
+        };
+
+        let coalesced = coalesce_events(vec![
+            event(1, StreamFlags::ITEM_MODIFIED | StreamFlags::IS_FILE, "/a"),
+            event(2, StreamFlags::ITEM_CREATED | StreamFlags::IS_FILE, "/b"),
+            event(3, StreamFlags::ITEM_RENAMED | StreamFlags::IS_FILE, "/a"),
+            event(4, StreamFlags::ITEM_CREATED | StreamFlags::IS_DIR, "/c"),
+            event(5, StreamFlags::ITEM_MODIFIED | StreamFlags::IS_FILE, "/a"),
+        ]);
+        assert_eq!(
+            coalesced
+                .iter()
+                .map(|event| (event.event_id, event.path.to_str().unwrap(), event.kind()))
+                .collect::<Vec<_>>(),
+            [
+                (5, "/a", Some(EventKind::Renamed)),
+                (2, "/b", Some(EventKind::Created)),
+                (4, "/c", Some(EventKind::Created)),
+            ]
+        );
+        assert_eq!(
+            coalesced[0].flags,
+            StreamFlags::ITEM_MODIFIED | StreamFlags::ITEM_RENAMED | StreamFlags::IS_FILE
+        );
+    }
+
+    #[test]
+    fn test_pipeline_ignored_paths() {
+        let event = |path: &str| Event {
+            event_id: 0,
+            flags: StreamFlags::ITEM_MODIFIED,
+            path: PathBuf::from(path),
+            inode: None,
+            root: None,
+        };
+        let pipeline = Pipeline {
+            is_ignored: Some(Box::new(|path| path.starts_with("/repo/.git"))),
+            ..Default::default()
+        };
+
+        let mut batches = Vec::new();
+        let mut callback = |events: Vec<Event>| {
+            batches.push(
+                events
+                    .into_iter()
+                    .map(|event| event.path)
+                    .collect::<Vec<_>>(),
+            );
+            true
+        };
+        assert!(pipeline.deliver(
+            vec![
+                event("/repo/.git/index"),
+                event("/repo/src/main.rs"),
+                event("/repo/.gitignore"),
+            ],
+            &mut callback,
+        ));
+        assert!(pipeline.deliver(vec![event("/repo/.git/HEAD")], &mut callback));
+        assert_eq!(
+            batches,
+            [[
+                PathBuf::from("/repo/src/main.rs"),
+                PathBuf::from("/repo/.gitignore")
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_pipeline_event_roots() {
+        let event = |path: &str| Event {
+            event_id: 0,
+            flags: StreamFlags::ITEM_MODIFIED,
+            path: PathBuf::from(path),
+            inode: None,
+            root: None,
+        };
+        let pipeline = Pipeline::new(WatchedPaths::new(&[
+            Path::new("/a"),
+            Path::new("/a/b"),
+            Path::new("/c"),
+        ]));
+
+        let mut roots = Vec::new();
+        assert!(pipeline.deliver(
+            vec![
+                event("/a/x"),
+                event("/a/b/y"),
+                event("/c"),
+                event("/ab/z"),
+                event("/d"),
+            ],
+            &mut |events| {
+                roots.extend(events.into_iter().map(|event| event.root));
+                true
+            },
+        ));
+        assert_eq!(
+            roots,
+            [
+                Some(PathBuf::from("/a")),
+                Some(PathBuf::from("/a/b")),
+                Some(PathBuf::from("/c")),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pipeline_max_batch_len() {
+        let events = |ids: std::ops::Range<u64>| {
+            ids.map(|event_id| Event {
+                event_id,
+                flags: StreamFlags::ITEM_CREATED,
+                path: PathBuf::from(format!("/repo/file-{event_id}")),
+                inode: None,
+                root: None,
+            })
+            .collect::<Vec<_>>()
+        };
+        let pipeline = Pipeline {
+            max_batch_len: Some(1000),
+            ..Default::default()
+        };
+
+        // A burst of events is delivered in order, in batches of at most 1000 events.
+        let mut batches = Vec::new();
+        assert!(pipeline.deliver(events(0..2500), &mut |events| {
+            batches.push(events);
+            true
+        }));
+        assert_eq!(
+            batches.iter().map(Vec::len).collect::<Vec<_>>(),
+            [1000, 1000, 500]
+        );
+        assert!(batches
+            .iter()
+            .flatten()
+            .map(|event| event.event_id)
+            .eq(0..2500));
+
+        // The rest of the burst is discarded once the callback asks to stop.
+        let mut batch_count = 0;
+        assert!(!pipeline.deliver(events(0..2500), &mut |_| {
+            batch_count += 1;
+            batch_count < 2
+        }));
+        assert_eq!(batch_count, 2);
+    }
 }
 
+/// Tests of the behavior that every platform's `EventStream` shares. Paths are joined one
+/// component at a time, so that they match the paths reported on Windows.
 #[cfg(test)]
-mod tests {
+mod event_stream_tests {
     use super::*;
-    use std::{fs, sync::mpsc, thread, time::Duration};
+    use std::{fs, sync::mpsc, thread};
     use tempdir::TempDir;
 
     #[test]
@@ -377,6 +680,7 @@ mod tests {
         for _ in 0..3 {
             let dir = TempDir::new("test-event-stream").unwrap();
             let path = dir.path().canonicalize().unwrap();
+            fs::create_dir(path.join("a")).unwrap();
             for i in 0..10 {
                 fs::write(path.join(format!("existing-file-{}", i)), "").unwrap();
             }
@@ -386,11 +690,12 @@ mod tests {
             let (stream, handle) = EventStream::new(&[&path], Duration::from_millis(50));
             thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
 
-            fs::write(path.join("new-file"), "").unwrap();
+            fs::write(path.join("a").join("new-file"), "").unwrap();
             let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
-            let event = events.last().unwrap();
-            assert_eq!(event.path, path.join("new-file"));
-            assert!(event.flags.contains(StreamFlags::ITEM_CREATED));
+            assert!(events
+                .iter()
+                .any(|event| event.path == path.join("a").join("new-file")
+                    && event.flags.contains(StreamFlags::ITEM_CREATED)));
 
             fs::remove_file(path.join("existing-file-5")).unwrap();
             let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
@@ -423,9 +728,10 @@ mod tests {
 
             fs::write(path.join("new-file"), "").unwrap();
             let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
-            let event = events.last().unwrap();
-            assert_eq!(event.path, path.join("new-file"));
-            assert!(event.flags.contains(StreamFlags::ITEM_CREATED));
+            assert!(events
+                .iter()
+                .any(|event| event.path == path.join("new-file")
+                    && event.flags.contains(StreamFlags::ITEM_CREATED)));
 
             fs::remove_file(path.join("existing-file-5")).unwrap();
             let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
@@ -475,12 +781,141 @@ mod tests {
         stream.run(|_| true);
     }
 
-    fn flush_historical_events() {
-        let duration = if std::env::var("CI").is_ok() {
-            Duration::from_secs(2)
-        } else {
-            Duration::from_millis(500)
-        };
-        thread::sleep(duration);
+    #[test]
+    fn test_event_stream_add_and_remove_paths() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        let (a, b) = (path.join("a"), path.join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(b.join("c")).unwrap();
+        flush_historical_events();
+
+        let (tx, rx) = mpsc::channel();
+        let (stream, handle) = EventStream::new(&[&a], Duration::from_millis(50));
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+
+        handle.add_path(&b).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        fs::write(a.join("file-1"), "").unwrap();
+        fs::write(b.join("c").join("file-1"), "").unwrap();
+        let mut paths = Vec::new();
+        while paths.len() < 2 {
+            let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+            paths.extend(events.into_iter().map(|event| event.path));
+            paths.dedup();
+        }
+        assert_eq!(paths, [a.join("file-1"), b.join("c").join("file-1")]);
+
+        handle.remove_path(&a);
+        thread::sleep(Duration::from_millis(100));
+        fs::write(a.join("file-2"), "").unwrap();
+        fs::write(b.join("c").join("file-2"), "").unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events
+            .iter()
+            .all(|event| event.path == b.join("c").join("file-2")));
+    }
+
+    #[test]
+    fn test_event_stream_set_latency() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        flush_historical_events();
+
+        let (tx, rx) = mpsc::channel();
+        let (stream, handle) = EventStream::new(&[&path], Duration::from_secs(60));
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+
+        thread::sleep(Duration::from_millis(100));
+        fs::write(path.join("file-1"), "").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        handle.set_latency(Duration::ZERO);
+        fs::write(path.join("file-2"), "").unwrap();
+        let mut paths = Vec::new();
+        while paths.len() < 2 {
+            let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+            paths.extend(events.into_iter().map(|event| event.path));
+            paths.dedup();
+        }
+        assert_eq!(paths, [path.join("file-1"), path.join("file-2")]);
+    }
+
+    #[test]
+    fn test_event_stream_watch_file() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        let file = path.join("file");
+        fs::write(&file, "").unwrap();
+        fs::write(path.join("other"), "").unwrap();
+        flush_historical_events();
+        let (tx, rx) = mpsc::channel();
+        let (stream, _handle) = EventStream::new(&[&file], Duration::from_millis(50));
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+        thread::sleep(Duration::from_millis(100));
+
+        // Changes to the file's siblings aren't reported.
+        fs::write(path.join("other"), "changed").unwrap();
+        fs::write(&file, "changed").unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events
+            .iter()
+            .all(|event| event.path == file && event.root.as_ref() == Some(&file)));
+        assert!(events
+            .iter()
+            .any(|event| event.flags.contains(StreamFlags::ITEM_MODIFIED)));
+
+        fs::remove_file(&file).unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events
+            .iter()
+            .any(|event| event.path == file && event.flags.contains(StreamFlags::ITEM_REMOVED)));
+
+        // The file is still watched after it's recreated.
+        fs::write(&file, "").unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events
+            .iter()
+            .any(|event| event.path == file && event.flags.contains(StreamFlags::ITEM_CREATED)));
+    }
+
+    #[test]
+    fn test_event_stream_stop() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        flush_historical_events();
+
+        let (tx, rx) = mpsc::channel();
+        let (stream, _handle) = EventStream::new(&[&path], Duration::from_millis(50));
+        thread::spawn({
+            let tx = tx.clone();
+            move || tx.send(stream.run(|_| false)).unwrap()
+        });
+        fs::write(path.join("new-file"), "").unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(2)).unwrap(),
+            StopReason::CallbackReturnedFalse
+        );
+
+        let (stream, handle) = EventStream::new(&[&path], Duration::from_millis(50));
+        thread::spawn(move || tx.send(stream.run(|_| true)).unwrap());
+        thread::sleep(Duration::from_millis(100));
+        handle.stop();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(2)).unwrap(),
+            StopReason::Stopped
+        );
+    }
+
+    /// FSEvents reports changes that were made shortly before a stream was created, so on
+    /// macOS, this waits for those changes to be flushed before a test creates its stream.
+    pub(crate) fn flush_historical_events() {
+        if cfg!(target_os = "macos") {
+            let duration = if std::env::var("CI").is_ok() {
+                Duration::from_secs(2)
+            } else {
+                Duration::from_millis(500)
+            };
+            thread::sleep(duration);
+        }
     }
 }
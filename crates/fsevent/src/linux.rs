@@ -0,0 +1,546 @@
+//! An inotify-based implementation of `EventStream`.
+//!
+//! inotify only watches individual directories, so every directory beneath the watched
+//! paths gets its own watch, and directories that are created or moved in while the
+//! stream is running are watched as they appear. Watching a file watches its parent
+//! directory and only reports changes to that file. Event ids are assigned by the stream in
+//! the order events are observed, since inotify has no equivalent of FSEvents' ids.
+
+use crate::{Event, Pipeline, StopReason, StreamFlags, WatchedPaths};
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{CString, OsStr},
+    fs, io, mem,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt, io::RawFd},
+    path::{Path, PathBuf},
+    ptr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MODIFY
+    | libc::IN_ATTRIB
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF;
+
+pub struct EventStream {
+    lifecycle: Arc<Mutex<Lifecycle>>,
+    watched_paths: Arc<Mutex<WatchedPaths>>,
+    state: State,
+    pub(crate) pipeline: Pipeline,
+}
+
+struct State {
+    latency: Duration,
+    inotify_fd: RawFd,
+    wake_fd: RawFd,
+    roots: Vec<PathBuf>,
+    // The roots that are files, whose parent directories are watched instead.
+    file_roots: HashSet<PathBuf>,
+    watches: HashMap<i32, PathBuf>,
+    next_event_id: u64,
+    report_inodes: bool,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.inotify_fd);
+            libc::close(self.wake_fd);
+        }
+    }
+}
+
+enum Lifecycle {
+    New,
+    Running(RawFd),
+    Stopped,
+}
+
+pub struct Handle {
+    lifecycle: Arc<Mutex<Lifecycle>>,
+    pub(crate) watched_paths: Arc<Mutex<WatchedPaths>>,
+}
+
+impl EventStream {
+    /// Creates a stream that reports changes to the given paths. Changes to everything
+    /// beneath a watched directory are reported, while a watched file only has its own
+    /// changes reported. Since a file is watched through its parent directory, its
+    /// removal is reported as such, and changes keep being reported if it's replaced or
+    /// recreated, as editors do when saving by renaming a new file over the old one.
+    pub fn new(paths: &[&Path], latency: Duration) -> (Self, Handle) {
+        let (inotify_fd, wake_fd) = unsafe {
+            (
+                libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC),
+                libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC),
+            )
+        };
+        assert!(inotify_fd >= 0, "{}", io::Error::last_os_error());
+        assert!(wake_fd >= 0, "{}", io::Error::last_os_error());
+
+        // Watches are added here rather than in `run`, so that no events are missed
+        // between creating the stream and running it.
+        let mut state = State {
+            latency,
+            inotify_fd,
+            wake_fd,
+            roots: paths.iter().map(|path| path.to_path_buf()).collect(),
+            file_roots: HashSet::new(),
+            watches: HashMap::new(),
+            next_event_id: 1,
+            report_inodes: false,
+        };
+        for path in paths {
+            state.add_root(path);
+        }
+
+        let lifecycle = Arc::new(Mutex::new(Lifecycle::New));
+        let watched_paths = WatchedPaths::new(paths);
+        (
+            EventStream {
+                lifecycle: lifecycle.clone(),
+                watched_paths: watched_paths.clone(),
+                state,
+                pipeline: Pipeline::new(watched_paths.clone()),
+            },
+            Handle {
+                lifecycle,
+                watched_paths,
+            },
+        )
+    }
+
+    /// Reports the inode of each event's path as `Event::inode`. This has a small cost,
+    /// since each path has to be stat'ed when its event is observed.
+    pub fn report_inodes(&mut self) {
+        self.state.report_inodes = true;
+    }
+
+    pub fn run<F>(mut self, mut f: F) -> StopReason
+    where
+        F: FnMut(Vec<Event>) -> bool + 'static,
+    {
+        {
+            let mut lifecycle = self.lifecycle.lock();
+            match *lifecycle {
+                Lifecycle::New => *lifecycle = Lifecycle::Running(self.state.wake_fd),
+                Lifecycle::Running(_) => unreachable!(),
+                Lifecycle::Stopped => return StopReason::Stopped,
+            }
+        }
+        if let Some(roots) = self.watched_paths.lock().take_changed() {
+            self.state.set_roots(roots);
+        }
+        if let Some(latency) = self.watched_paths.lock().take_latency() {
+            self.state.latency = latency;
+        }
+
+        let mut events = Vec::new();
+        let mut deadline = None;
+        let reason = loop {
+            let timeout = deadline.map_or(-1, |deadline: Instant| {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                remaining.as_millis().min(i32::MAX as u128) as i32
+            });
+            let mut fds = [
+                libc::pollfd {
+                    fd: self.state.inotify_fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.state.wake_fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            let result =
+                unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+            if result < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                break StopReason::Failed(error.raw_os_error().unwrap_or_default());
+            }
+            if fds[1].revents != 0 {
+                unsafe {
+                    libc::eventfd_read(self.state.wake_fd, &mut 0);
+                }
+                if matches!(*self.lifecycle.lock(), Lifecycle::Stopped) {
+                    break StopReason::Stopped;
+                }
+                if let Some(roots) = self.watched_paths.lock().take_changed() {
+                    self.state.set_roots(roots);
+                }
+                if let Some(latency) = self.watched_paths.lock().take_latency() {
+                    deadline = deadline.map(|deadline| deadline - self.state.latency + latency);
+                    self.state.latency = latency;
+                }
+            }
+
+            if fds[0].revents != 0 {
+                let len = events.len();
+                self.state.read_events(&mut events);
+                if deadline.is_none() && events.len() > len {
+                    deadline = Some(Instant::now() + self.state.latency);
+                }
+            }
+
+            if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                deadline = None;
+                if !self.pipeline.deliver(mem::take(&mut events), &mut f) {
+                    break StopReason::CallbackReturnedFalse;
+                }
+            }
+        };
+
+        *self.lifecycle.lock() = Lifecycle::Stopped;
+        reason
+    }
+}
+
+impl State {
+    fn set_roots(&mut self, roots: Vec<PathBuf>) {
+        let old_roots = mem::replace(&mut self.roots, roots);
+        self.file_roots.retain(|root| self.roots.contains(root));
+        let removed_wds = self
+            .watches
+            .iter()
+            .filter(|(_, path)| {
+                !self.roots.iter().any(|root| path.starts_with(root))
+                    && !self
+                        .file_roots
+                        .iter()
+                        .any(|root| root.parent() == Some(path.as_path()))
+            })
+            .map(|(wd, _)| *wd)
+            .collect::<Vec<_>>();
+        for wd in removed_wds {
+            unsafe {
+                libc::inotify_rm_watch(self.inotify_fd, wd);
+            }
+            self.watches.remove(&wd);
+        }
+        for root in self.roots.clone() {
+            if !old_roots.contains(&root) {
+                self.add_root(&root);
+            }
+        }
+    }
+
+    /// Watches the given root. A file is watched through its parent directory, since a
+    /// watch on the file itself would stop reporting changes once the file is removed or
+    /// replaced.
+    fn add_root(&mut self, root: &Path) {
+        let is_file = fs::metadata(root).is_ok_and(|metadata| !metadata.is_dir());
+        match root.parent() {
+            Some(parent) if is_file => {
+                self.file_roots.insert(root.to_path_buf());
+                self.add_watch(parent);
+            }
+            _ => self.add_watches(root, None),
+        }
+    }
+
+    /// Watches the given directory, returning whether it could be watched.
+    fn add_watch(&mut self, path: &Path) -> bool {
+        let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+            return false;
+        };
+        let wd = unsafe { libc::inotify_add_watch(self.inotify_fd, c_path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return false;
+        }
+        self.watches.insert(wd, path.to_path_buf());
+        true
+    }
+
+    /// Watches the given path and, if it's a directory, every directory beneath it. When
+    /// a directory appears while the stream is running, its contents may have been created
+    /// before it was watched, so an event is reported for each entry that's found.
+    fn add_watches(&mut self, path: &Path, mut events: Option<&mut Vec<Event>>) {
+        if !self.add_watch(path) {
+            return;
+        }
+
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            let kind = if file_type.is_dir() {
+                StreamFlags::IS_DIR
+            } else if file_type.is_symlink() {
+                StreamFlags::IS_SYMLINK
+            } else {
+                StreamFlags::IS_FILE
+            };
+            if let Some(events) = events.as_deref_mut() {
+                events.push(self.event(StreamFlags::ITEM_CREATED | kind, path.clone()));
+            }
+            if file_type.is_dir() {
+                self.add_watches(&path, events.as_deref_mut());
+            }
+        }
+    }
+
+    fn read_events(&mut self, events: &mut Vec<Event>) {
+        let mut buffer = [0u8; 16 * 1024];
+        loop {
+            let len = unsafe {
+                libc::read(
+                    self.inotify_fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if len <= 0 {
+                break;
+            }
+
+            let mut offset = 0;
+            while offset < len as usize {
+                let raw_event = unsafe {
+                    ptr::read_unaligned(buffer.as_ptr().add(offset) as *const libc::inotify_event)
+                };
+                let name_start = offset + mem::size_of::<libc::inotify_event>();
+                let name = &buffer[name_start..name_start + raw_event.len as usize];
+                let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+                offset = name_start + raw_event.len as usize;
+                self.handle_event(raw_event.wd, raw_event.mask, name, events);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, wd: i32, mask: u32, name: &[u8], events: &mut Vec<Event>) {
+        if mask & libc::IN_Q_OVERFLOW != 0 {
+            for root in self.roots.clone() {
+                events.push(self.event(StreamFlags::MUST_SCAN_SUBDIRS, root));
+            }
+            return;
+        }
+        if mask & libc::IN_IGNORED != 0 {
+            self.watches.remove(&wd);
+            return;
+        }
+        let Some(watch_path) = self.watches.get(&wd) else {
+            return;
+        };
+
+        // Removals and renames of watched directories are also reported by their parent,
+        // so they're only reported here for the watched paths themselves.
+        if mask & (libc::IN_DELETE_SELF | libc::IN_MOVE_SELF) != 0 {
+            if self.roots.contains(watch_path) {
+                let path = watch_path.clone();
+                events.push(self.event(StreamFlags::ROOT_CHANGED, path));
+            }
+            return;
+        }
+
+        let path = if name.is_empty() {
+            watch_path.clone()
+        } else {
+            watch_path.join(OsStr::from_bytes(name))
+        };
+        // The parent directory of a watched file also reports changes to the file's
+        // siblings.
+        if !self.roots.iter().any(|root| path.starts_with(root)) {
+            return;
+        }
+        let mut flags = if mask & libc::IN_ISDIR != 0 {
+            StreamFlags::IS_DIR
+        } else {
+            StreamFlags::IS_FILE
+        };
+        if mask & libc::IN_CREATE != 0 {
+            flags |= StreamFlags::ITEM_CREATED;
+        }
+        if mask & libc::IN_DELETE != 0 {
+            flags |= StreamFlags::ITEM_REMOVED;
+        }
+        if mask & (libc::IN_MOVED_FROM | libc::IN_MOVED_TO) != 0 {
+            flags |= StreamFlags::ITEM_RENAMED;
+        }
+        if mask & libc::IN_MODIFY != 0 {
+            flags |= StreamFlags::ITEM_MODIFIED;
+        }
+        if mask & libc::IN_ATTRIB != 0 {
+            flags |= StreamFlags::INODE_META_MOD;
+        }
+        events.push(self.event(flags, path.clone()));
+
+        if mask & libc::IN_ISDIR != 0 {
+            if mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                self.add_watches(&path, Some(events));
+            } else if mask & libc::IN_MOVED_FROM != 0 {
+                // If the directory was moved out of the watched paths, its watches would keep
+                // reporting its changes under its old path. If it was moved within them, it's
+                // watched again at its new path when the matching `IN_MOVED_TO` is handled.
+                self.remove_watches(&path);
+            }
+        }
+    }
+
+    /// Stops watching the given directory and every directory beneath it.
+    fn remove_watches(&mut self, path: &Path) {
+        let removed_wds = self
+            .watches
+            .iter()
+            .filter(|(_, watch_path)| watch_path.starts_with(path))
+            .map(|(wd, _)| *wd)
+            .collect::<Vec<_>>();
+        for wd in removed_wds {
+            unsafe {
+                libc::inotify_rm_watch(self.inotify_fd, wd);
+            }
+            self.watches.remove(&wd);
+        }
+    }
+
+    fn event(&mut self, flags: StreamFlags, path: PathBuf) -> Event {
+        let event_id = self.next_event_id;
+        self.next_event_id += 1;
+        let inode = if self.report_inodes {
+            fs::symlink_metadata(&path)
+                .ok()
+                .map(|metadata| metadata.ino())
+        } else {
+            None
+        };
+        Event {
+            event_id,
+            flags,
+            path,
+            inode,
+            root: None,
+        }
+    }
+}
+
+impl Handle {
+    /// Stops the stream, causing `EventStream::run` to return promptly if it's running,
+    /// or as soon as it's called otherwise.
+    pub fn stop(&self) {
+        let mut lifecycle = self.lifecycle.lock();
+        if let Lifecycle::Running(wake_fd) = *lifecycle {
+            unsafe {
+                libc::eventfd_write(wake_fd, 1);
+            }
+        }
+        *lifecycle = Lifecycle::Stopped;
+    }
+
+    pub(crate) fn notify_changed(&self) {
+        if let Lifecycle::Running(wake_fd) = *self.lifecycle.lock() {
+            unsafe {
+                libc::eventfd_write(wake_fd, 1);
+            }
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, sync::mpsc, thread, time::Duration};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_event_stream_recursive() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(path.join("a/b")).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let (stream, handle) = EventStream::new(&[&path], Duration::from_millis(50));
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+
+        // Directories that existed when the stream was created are watched.
+        fs::write(path.join("a/b/new-file"), "").unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        let event = events.first().unwrap();
+        assert_eq!(event.path, path.join("a/b/new-file"));
+        assert!(event.flags.contains(StreamFlags::ITEM_CREATED));
+        assert!(event.flags.contains(StreamFlags::IS_FILE));
+
+        // So are directories that are created afterwards.
+        fs::create_dir(path.join("c")).unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        let event = events.first().unwrap();
+        assert_eq!(event.path, path.join("c"));
+        assert!(event.flags.contains(StreamFlags::ITEM_CREATED));
+        assert!(event.flags.contains(StreamFlags::IS_DIR));
+
+        fs::write(path.join("c/new-file"), "").unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        let event = events.first().unwrap();
+        assert_eq!(event.path, path.join("c/new-file"));
+        assert!(event.flags.contains(StreamFlags::ITEM_CREATED));
+        drop(handle);
+    }
+
+    #[test]
+    fn test_event_stream_directory_moved_out() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(path.join("root/a/b")).unwrap();
+        fs::create_dir_all(path.join("root/c")).unwrap();
+        fs::create_dir(path.join("outside")).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let (stream, _handle) = EventStream::new(&[&path.join("root")], Duration::from_millis(50));
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+        thread::sleep(Duration::from_millis(100));
+
+        // Changes to a directory that's moved out of the watched paths aren't reported
+        // under its old path, while one that's moved within them is still watched.
+        fs::rename(path.join("root/a"), path.join("outside/a")).unwrap();
+        fs::rename(path.join("root/c"), path.join("root/d")).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        fs::write(path.join("outside/a/b/file"), "").unwrap();
+        fs::write(path.join("root/d/file"), "").unwrap();
+        let mut paths = Vec::new();
+        while !paths.contains(&path.join("root/d/file")) {
+            let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+            paths.extend(events.into_iter().map(|event| event.path));
+        }
+        assert!(!paths.contains(&path.join("root/a/b/file")));
+    }
+
+    #[test]
+    fn test_event_stream_report_inodes() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let (mut stream, _handle) = EventStream::new(&[&path], Duration::from_millis(50));
+        stream.report_inodes();
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+
+        fs::write(path.join("new-file"), "").unwrap();
+        let inode = fs::metadata(path.join("new-file")).unwrap().ino();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events
+            .iter()
+            .all(|event| event.path == path.join("new-file") && event.inode == Some(inode)));
+
+        fs::remove_file(path.join("new-file")).unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events.iter().all(|event| event.inode.is_none()));
+    }
+}
@@ -0,0 +1,526 @@
+use crate::{Event, EventKind, Pipeline, StopReason, StreamFlags, WatchedPaths};
+use fsevent_sys::{self as fs, core_foundation as cf};
+use parking_lot::Mutex;
+use std::{
+    convert::AsRef,
+    ffi::{c_char, c_void, CStr, OsStr},
+    mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    ptr, slice,
+    sync::Arc,
+    time::Duration,
+};
+
+pub struct EventStream {
+    lifecycle: Arc<Mutex<Lifecycle>>,
+    watched_paths: Arc<Mutex<WatchedPaths>>,
+    state: Box<State>,
+    pub(crate) pipeline: Pipeline,
+}
+
+struct State {
+    latency: Duration,
+    paths: cf::CFMutableArrayRef,
+    callback: Option<Box<dyn FnMut(Vec<Event>) -> bool>>,
+    last_valid_event_id: Option<fs::FSEventStreamEventId>,
+    latest_event_id: fs::FSEventStreamEventId,
+    report_history_done: bool,
+    report_inodes: bool,
+    callback_returned_false: bool,
+    stream: fs::FSEventStreamRef,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        unsafe {
+            cf::CFRelease(self.paths);
+            fs::FSEventStreamStop(self.stream);
+            fs::FSEventStreamInvalidate(self.stream);
+            fs::FSEventStreamRelease(self.stream);
+        }
+    }
+}
+
+enum Lifecycle {
+    New,
+    Running(cf::CFRunLoopRef),
+    Stopped,
+}
+
+pub struct Handle {
+    lifecycle: Arc<Mutex<Lifecycle>>,
+    pub(crate) watched_paths: Arc<Mutex<WatchedPaths>>,
+}
+
+unsafe impl Send for EventStream {}
+unsafe impl Send for Lifecycle {}
+
+impl EventStream {
+    /// Creates a stream that reports changes to the given paths. Changes to everything
+    /// beneath a watched directory are reported, while a watched file only has its own
+    /// changes reported. When a watched path is removed or renamed, an event with the
+    /// [`StreamFlags::ROOT_CHANGED`] flag is reported for it too. FSEvents watches paths
+    /// rather than files, so changes keep being reported if a watched file is replaced or
+    /// recreated, as editors do when saving by renaming a new file over the old one.
+    pub fn new(paths: &[&Path], latency: Duration) -> (Self, Handle) {
+        let (mut stream, handle) =
+            Self::new_since(paths, latency, unsafe { FSEventsGetCurrentEventId() });
+        stream.state.report_history_done = false;
+        (stream, handle)
+    }
+
+    /// Like `new`, but first reports the changes that occurred after the event with the
+    /// given id, such as one that was persisted before the app quit, followed by an
+    /// [`EventKind::HistoryDone`] event. Event ids are only meaningful for paths on the
+    /// volume they were reported for.
+    pub fn new_since(paths: &[&Path], latency: Duration, since_id: u64) -> (Self, Handle) {
+        unsafe {
+            let cf_paths = create_cf_paths(paths.iter().copied());
+            let mut state = Box::new(State {
+                latency,
+                paths: cf_paths,
+                callback: None,
+                last_valid_event_id: None,
+                latest_event_id: since_id,
+                report_history_done: true,
+                report_inodes: false,
+                callback_returned_false: false,
+                stream: ptr::null_mut(),
+            });
+            let stream_context = fs::FSEventStreamContext {
+                version: 0,
+                info: state.as_ref() as *const _ as *mut c_void,
+                retain: None,
+                release: None,
+                copy_description: None,
+            };
+            let stream = fs::FSEventStreamCreate(
+                cf::kCFAllocatorDefault,
+                Self::trampoline,
+                &stream_context,
+                cf_paths,
+                since_id,
+                latency.as_secs_f64(),
+                state.create_flags(),
+            );
+            state.stream = stream;
+
+            let lifecycle = Arc::new(Mutex::new(Lifecycle::New));
+            let watched_paths = WatchedPaths::new(paths);
+            (
+                EventStream {
+                    lifecycle: lifecycle.clone(),
+                    watched_paths: watched_paths.clone(),
+                    state,
+                    pipeline: Pipeline::new(watched_paths.clone()),
+                },
+                Handle {
+                    lifecycle,
+                    watched_paths,
+                },
+            )
+        }
+    }
+
+    /// Reports the inode of each event's path as `Event::inode`, which requires macOS
+    /// 10.13. This has a small cost, since FSEvents then reports extended data for each
+    /// event using Core Foundation types.
+    pub fn report_inodes(&mut self) {
+        self.state.report_inodes = true;
+        let paths = self.watched_paths.lock().paths().to_vec();
+        unsafe { self.state.recreate_stream(&paths) };
+    }
+
+    pub fn run<F>(mut self, mut f: F) -> StopReason
+    where
+        F: FnMut(Vec<Event>) -> bool + 'static,
+    {
+        let pipeline = mem::take(&mut self.pipeline);
+        self.state.callback = Some(Box::new(move |events| pipeline.deliver(events, &mut f)));
+        unsafe {
+            let run_loop = cf::CFRunLoopGetCurrent();
+            {
+                let mut state = self.lifecycle.lock();
+                match *state {
+                    Lifecycle::New => *state = Lifecycle::Running(run_loop),
+                    Lifecycle::Running(_) => unreachable!(),
+                    Lifecycle::Stopped => return StopReason::Stopped,
+                }
+            }
+            if let Some(paths) = self.take_changes() {
+                self.state.recreate_stream(&paths);
+            }
+
+            let reason = 'run: loop {
+                fs::FSEventStreamScheduleWithRunLoop(
+                    self.state.stream,
+                    run_loop,
+                    cf::kCFRunLoopDefaultMode,
+                );
+                fs::FSEventStreamStart(self.state.stream);
+
+                // The run loop is stopped by the callback returning false, or by the
+                // handle when the stream is stopped or its paths or latency change.
+                let paths = loop {
+                    cf::CFRunLoopRun();
+                    if self.state.callback_returned_false {
+                        break 'run StopReason::CallbackReturnedFalse;
+                    }
+                    if matches!(*self.lifecycle.lock(), Lifecycle::Stopped) {
+                        break 'run StopReason::Stopped;
+                    }
+                    if let Some(paths) = self.take_changes() {
+                        break paths;
+                    }
+                };
+                self.state.recreate_stream(&paths);
+            };
+
+            *self.lifecycle.lock() = Lifecycle::Stopped;
+            reason
+        }
+    }
+
+    /// Applies the latency set by the handle, if any, returning the paths that the stream
+    /// should be recreated with if they or the latency have changed.
+    fn take_changes(&mut self) -> Option<Vec<PathBuf>> {
+        let mut watched_paths = self.watched_paths.lock();
+        let paths = watched_paths.take_changed();
+        let latency = watched_paths.take_latency();
+        if let Some(latency) = latency {
+            self.state.latency = latency;
+        }
+        paths.or_else(|| latency.map(|_| watched_paths.paths().to_vec()))
+    }
+
+    extern "C" fn trampoline(
+        stream_ref: fs::FSEventStreamRef,
+        info: *mut ::std::os::raw::c_void,
+        num: usize,                                 // size_t numEvents
+        event_paths: *mut ::std::os::raw::c_void,   // void *eventPaths
+        event_flags: *const ::std::os::raw::c_void, // const FSEventStreamEventFlags eventFlags[]
+        event_ids: *const ::std::os::raw::c_void,   // const FSEventStreamEventId eventIds[]
+    ) {
+        unsafe {
+            let event_paths = event_paths as *const *const ::std::os::raw::c_char;
+            let e_ptr = event_flags as *mut u32;
+            let i_ptr = event_ids as *mut u64;
+            let state = (info as *mut State).as_mut().unwrap();
+            let callback = if let Some(callback) = state.callback.as_mut() {
+                callback
+            } else {
+                return;
+            };
+
+            let flags = slice::from_raw_parts_mut(e_ptr, num);
+            let ids = slice::from_raw_parts_mut(i_ptr, num);
+            let mut stream_restarted = false;
+
+            // Sometimes FSEvents reports a "dropped" event, an indication that either the kernel
+            // or our code couldn't keep up with the sheer volume of file-system events that were
+            // generated. If we observed a valid event before this happens, we'll try to read the
+            // file-system journal by stopping the current stream and creating a new one starting at
+            // such event. Otherwise, we'll let invoke the callback with the dropped event, which
+            // will likely perform a re-scan of one of the root directories.
+            if flags
+                .iter()
+                .copied()
+                .filter_map(StreamFlags::from_bits)
+                .any(|flags| {
+                    flags.contains(StreamFlags::USER_DROPPED)
+                        || flags.contains(StreamFlags::KERNEL_DROPPED)
+                })
+            {
+                if let Some(last_valid_event_id) = state.last_valid_event_id.take() {
+                    fs::FSEventStreamStop(state.stream);
+                    fs::FSEventStreamInvalidate(state.stream);
+                    fs::FSEventStreamRelease(state.stream);
+
+                    let stream_context = fs::FSEventStreamContext {
+                        version: 0,
+                        info,
+                        retain: None,
+                        release: None,
+                        copy_description: None,
+                    };
+                    let stream = fs::FSEventStreamCreate(
+                        cf::kCFAllocatorDefault,
+                        Self::trampoline,
+                        &stream_context,
+                        state.paths,
+                        last_valid_event_id,
+                        state.latency.as_secs_f64(),
+                        state.create_flags(),
+                    );
+
+                    state.stream = stream;
+                    fs::FSEventStreamScheduleWithRunLoop(
+                        state.stream,
+                        cf::CFRunLoopGetCurrent(),
+                        cf::kCFRunLoopDefaultMode,
+                    );
+                    fs::FSEventStreamStart(state.stream);
+                    stream_restarted = true;
+                }
+            }
+
+            if !stream_restarted {
+                let mut events = Vec::with_capacity(num);
+                for p in 0..num {
+                    if let Some(flag) = StreamFlags::from_bits(flags[p]) {
+                        let is_history_done = flag.contains(StreamFlags::HISTORY_DONE);
+                        if !is_history_done || state.report_history_done {
+                            let (path, inode) = if state.report_inodes {
+                                let extended_data = CFArrayGetValueAtIndex(
+                                    event_paths as *const c_void,
+                                    p as cf::CFIndex,
+                                );
+                                path_and_inode_from_extended_data(extended_data)
+                            } else {
+                                let path_c_str = CStr::from_ptr(*event_paths.add(p));
+                                let path = PathBuf::from(OsStr::from_bytes(path_c_str.to_bytes()));
+                                (path, None)
+                            };
+                            let event = Event {
+                                event_id: ids[p],
+                                flags: flag,
+                                path,
+                                inode,
+                                root: None,
+                            };
+                            if is_history_done {
+                                state.report_history_done = false;
+                            } else {
+                                state.last_valid_event_id = Some(event.event_id);
+                                state.latest_event_id = event.event_id;
+                            }
+                            events.push(event);
+                        }
+                    } else {
+                        debug_assert!(false, "unknown flag set for fs event: {}", flags[p]);
+                    }
+                }
+
+                if !events.is_empty() && !callback(events) {
+                    state.callback_returned_false = true;
+                    fs::FSEventStreamStop(stream_ref);
+                    cf::CFRunLoopStop(cf::CFRunLoopGetCurrent());
+                }
+            }
+        }
+    }
+}
+
+impl State {
+    /// Replaces the stream with one for the given paths. It starts after the latest event
+    /// that was reported, so that no events are missed for the paths that were already
+    /// being watched.
+    unsafe fn recreate_stream(&mut self, paths: &[PathBuf]) {
+        fs::FSEventStreamStop(self.stream);
+        fs::FSEventStreamInvalidate(self.stream);
+        fs::FSEventStreamRelease(self.stream);
+        cf::CFRelease(self.paths);
+
+        self.paths = create_cf_paths(paths.iter().map(PathBuf::as_path));
+        let stream_context = fs::FSEventStreamContext {
+            version: 0,
+            info: self as *mut State as *mut c_void,
+            retain: None,
+            release: None,
+            copy_description: None,
+        };
+        self.stream = fs::FSEventStreamCreate(
+            cf::kCFAllocatorDefault,
+            EventStream::trampoline,
+            &stream_context,
+            self.paths,
+            self.latest_event_id,
+            self.latency.as_secs_f64(),
+            self.create_flags(),
+        );
+    }
+
+    fn create_flags(&self) -> u32 {
+        let mut flags = fs::kFSEventStreamCreateFlagFileEvents
+            | fs::kFSEventStreamCreateFlagNoDefer
+            | fs::kFSEventStreamCreateFlagWatchRoot;
+        if self.report_inodes {
+            flags |= CREATE_FLAG_USE_CF_TYPES | CREATE_FLAG_USE_EXTENDED_DATA;
+        }
+        flags
+    }
+}
+
+/// Reads the `path` and `fileID` entries of an event's extended data dictionary.
+unsafe fn path_and_inode_from_extended_data(
+    extended_data: *const c_void,
+) -> (PathBuf, Option<u64>) {
+    let path_key = CFStringCreateWithCString(
+        cf::kCFAllocatorDefault,
+        b"path\0".as_ptr() as *const c_char,
+        CF_STRING_ENCODING_UTF8,
+    );
+    let file_id_key = CFStringCreateWithCString(
+        cf::kCFAllocatorDefault,
+        b"fileID\0".as_ptr() as *const c_char,
+        CF_STRING_ENCODING_UTF8,
+    );
+
+    let mut path = PathBuf::new();
+    let cf_path = CFDictionaryGetValue(extended_data, path_key);
+    let mut buffer = [0 as c_char; 4096];
+    if !cf_path.is_null()
+        && CFStringGetCString(
+            cf_path,
+            buffer.as_mut_ptr(),
+            buffer.len() as cf::CFIndex,
+            CF_STRING_ENCODING_UTF8,
+        ) != 0
+    {
+        path = PathBuf::from(OsStr::from_bytes(
+            CStr::from_ptr(buffer.as_ptr()).to_bytes(),
+        ));
+    }
+
+    let mut inode = None;
+    let cf_file_id = CFDictionaryGetValue(extended_data, file_id_key);
+    let mut file_id = 0i64;
+    if !cf_file_id.is_null()
+        && CFNumberGetValue(
+            cf_file_id,
+            CF_NUMBER_SINT64_TYPE,
+            &mut file_id as *mut i64 as *mut c_void,
+        ) != 0
+    {
+        inode = Some(file_id as u64);
+    }
+
+    cf::CFRelease(path_key as _);
+    cf::CFRelease(file_id_key as _);
+    (path, inode)
+}
+
+unsafe fn create_cf_paths<'a>(paths: impl Iterator<Item = &'a Path>) -> cf::CFMutableArrayRef {
+    let cf_paths = cf::CFArrayCreateMutable(cf::kCFAllocatorDefault, 0, &cf::kCFTypeArrayCallBacks);
+    assert!(!cf_paths.is_null());
+
+    for path in paths {
+        let path_bytes = path.as_os_str().as_bytes();
+        let cf_url = cf::CFURLCreateFromFileSystemRepresentation(
+            cf::kCFAllocatorDefault,
+            path_bytes.as_ptr() as *const i8,
+            path_bytes.len() as cf::CFIndex,
+            false,
+        );
+        let cf_path = cf::CFURLCopyFileSystemPath(cf_url, cf::kCFURLPOSIXPathStyle);
+        cf::CFArrayAppendValue(cf_paths, cf_path);
+        cf::CFRelease(cf_path);
+        cf::CFRelease(cf_url);
+    }
+    cf_paths
+}
+
+impl Handle {
+    /// Stops the stream, causing `EventStream::run` to return promptly if it's running,
+    /// or as soon as it's called otherwise.
+    pub fn stop(&self) {
+        let mut state = self.lifecycle.lock();
+        if let Lifecycle::Running(run_loop) = *state {
+            unsafe {
+                cf::CFRunLoopStop(run_loop);
+            }
+        }
+        *state = Lifecycle::Stopped;
+    }
+
+    pub(crate) fn notify_changed(&self) {
+        if let Lifecycle::Running(run_loop) = *self.lifecycle.lock() {
+            unsafe {
+                cf::CFRunLoopStop(run_loop);
+            }
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[link(name = "CoreServices", kind = "framework")]
+extern "C" {
+    pub fn FSEventsGetCurrentEventId() -> u64;
+}
+
+// Synchronize with
+// /System/Library/Frameworks/CoreServices.framework/Versions/A/Frameworks/FSEvents.framework/Versions/A/Headers/FSEvents.h
+const CREATE_FLAG_USE_CF_TYPES: u32 = 0x00000001;
+const CREATE_FLAG_USE_EXTENDED_DATA: u32 = 0x00000040;
+
+const CF_STRING_ENCODING_UTF8: u32 = 0x08000100;
+const CF_NUMBER_SINT64_TYPE: cf::CFIndex = 4;
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFArrayGetValueAtIndex(array: *const c_void, index: cf::CFIndex) -> *const c_void;
+    fn CFDictionaryGetValue(dictionary: *const c_void, key: *const c_void) -> *const c_void;
+    fn CFNumberGetValue(number: *const c_void, number_type: cf::CFIndex, value: *mut c_void) -> u8;
+    fn CFStringCreateWithCString(
+        allocator: cf::CFAllocatorRef,
+        c_str: *const c_char,
+        encoding: u32,
+    ) -> *const c_void;
+    fn CFStringGetCString(
+        string: *const c_void,
+        buffer: *mut c_char,
+        buffer_size: cf::CFIndex,
+        encoding: u32,
+    ) -> u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_stream_tests::flush_historical_events;
+    use std::{fs, sync::mpsc, thread, time::Duration};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_event_stream_since() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        flush_historical_events();
+
+        let (tx, rx) = mpsc::channel();
+        let (stream, handle) = EventStream::new(&[&path], Duration::from_millis(50));
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+
+        fs::write(path.join("file-1"), "").unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        let last_event_id = events.last().unwrap().event_id;
+        drop(handle);
+
+        // Changes made while nothing is watching are reported when resuming.
+        fs::write(path.join("file-2"), "").unwrap();
+        flush_historical_events();
+
+        let (tx, rx) = mpsc::channel();
+        let (stream, handle) =
+            EventStream::new_since(&[&path], Duration::from_millis(50), last_event_id);
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+
+        let mut events = Vec::new();
+        while !events
+            .iter()
+            .any(|event: &Event| event.kind() == Some(EventKind::HistoryDone))
+        {
+            events.extend(rx.recv_timeout(Duration::from_secs(2)).unwrap());
+        }
+        assert!(events.iter().all(|event| event.event_id > last_event_id));
+        assert!(events.iter().any(|event| event.path == path.join("file-2")
+            && event.flags.contains(StreamFlags::ITEM_CREATED)));
+        drop(handle);
+    }
+}
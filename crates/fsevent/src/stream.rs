@@ -0,0 +1,94 @@
+use crate::{Event, EventStream, Handle};
+use futures::{
+    channel::mpsc,
+    executor::block_on,
+    task::{Context, Poll},
+    SinkExt, Stream, StreamExt,
+};
+use std::{pin::Pin, thread};
+
+/// What to do with a batch of events when the consumer of an [`AsyncEventStream`] has
+/// fallen behind, and its buffer is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for the consumer to catch up. Events aren't lost, but the platform may drop
+    /// events itself if it can't deliver them for long enough, reporting that a rescan
+    /// is needed.
+    #[default]
+    Block,
+    /// Drop the batch, so that the consumer only sees the batches that fit in its buffer.
+    Drop,
+}
+
+/// The batches of events from an [`EventStream`] that's running on a background thread.
+/// Dropping it stops the underlying stream.
+pub struct AsyncEventStream {
+    batches: mpsc::Receiver<Vec<Event>>,
+    _handle: Handle,
+}
+
+impl EventStream {
+    /// Runs the stream on a background thread, buffering up to `capacity` batches of
+    /// events for the returned stream.
+    pub fn into_async_stream(
+        self,
+        handle: Handle,
+        capacity: usize,
+        backpressure: Backpressure,
+    ) -> AsyncEventStream {
+        let (mut tx, rx) = mpsc::channel(capacity);
+        thread::spawn(move || {
+            self.run(move |events| match backpressure {
+                Backpressure::Block => block_on(tx.send(events)).is_ok(),
+                Backpressure::Drop => match tx.try_send(events) {
+                    Ok(()) => true,
+                    Err(error) => error.is_full(),
+                },
+            })
+        });
+        AsyncEventStream {
+            batches: rx,
+            _handle: handle,
+        }
+    }
+}
+
+impl Stream for AsyncEventStream {
+    type Item = Vec<Event>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.batches.poll_next_unpin(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_stream_tests::flush_historical_events, StreamFlags};
+    use std::{fs, time::Duration};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_async_event_stream() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        flush_historical_events();
+
+        let (stream, handle) = EventStream::new(&[&path], Duration::from_millis(50));
+        let mut batches = stream.into_async_stream(handle, 1, Backpressure::Block);
+
+        fs::write(path.join("new-file"), "").unwrap();
+        let events = block_on(batches.next()).unwrap();
+        assert!(events
+            .iter()
+            .any(|event| event.path == path.join("new-file")
+                && event.flags.contains(StreamFlags::ITEM_CREATED)));
+
+        fs::remove_file(path.join("new-file")).unwrap();
+        let events = block_on(batches.next()).unwrap();
+        assert!(events
+            .iter()
+            .any(|event| event.path == path.join("new-file")
+                && event.flags.contains(StreamFlags::ITEM_REMOVED)));
+    }
+}
@@ -0,0 +1,602 @@
+//! A `ReadDirectoryChangesW`-based implementation of `EventStream`.
+//!
+//! Each watched directory is watched recursively with its own overlapped read. Watching
+//! a file watches its parent directory and only reports changes to that file. Windows
+//! reports the two halves of a rename as separate actions. When both are observed, the
+//! old path is reported as removed and the new path as renamed, so that consumers which
+//! rescan the changed paths see one rename. Event ids are assigned by the stream in the
+//! order events are observed, since Windows has no equivalent of FSEvents' ids.
+
+use crate::{Event, Pipeline, StopReason, StreamFlags, WatchedPaths};
+use parking_lot::Mutex;
+use std::{
+    ffi::{c_void, OsStr, OsString},
+    fs, mem,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    ptr, slice,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use windows_sys::Win32::{
+    Foundation::{
+        CloseHandle, GetLastError, ERROR_NOTIFY_ENUM_DIR, FALSE, HANDLE, INVALID_HANDLE_VALUE,
+        TRUE, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT,
+    },
+    Storage::FileSystem::{
+        CreateFileW, GetFileInformationByHandle, ReadDirectoryChangesW, BY_HANDLE_FILE_INFORMATION,
+        FILE_ACTION_ADDED, FILE_ACTION_MODIFIED, FILE_ACTION_REMOVED, FILE_ACTION_RENAMED_NEW_NAME,
+        FILE_ACTION_RENAMED_OLD_NAME, FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT,
+        FILE_FLAG_OVERLAPPED, FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_ATTRIBUTES,
+        FILE_NOTIFY_CHANGE_CREATION, FILE_NOTIFY_CHANGE_DIR_NAME, FILE_NOTIFY_CHANGE_FILE_NAME,
+        FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_SIZE, FILE_NOTIFY_INFORMATION,
+        FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+    },
+    System::{
+        Threading::{CreateEventW, ResetEvent, SetEvent, WaitForMultipleObjects, INFINITE},
+        IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+    },
+};
+
+const NOTIFY_FILTER: u32 = FILE_NOTIFY_CHANGE_FILE_NAME
+    | FILE_NOTIFY_CHANGE_DIR_NAME
+    | FILE_NOTIFY_CHANGE_ATTRIBUTES
+    | FILE_NOTIFY_CHANGE_SIZE
+    | FILE_NOTIFY_CHANGE_LAST_WRITE
+    | FILE_NOTIFY_CHANGE_CREATION;
+
+/// The size of the buffer each directory's changes are read into, in `u32`s so that
+/// it's aligned as `ReadDirectoryChangesW` requires.
+const BUFFER_LEN: usize = 16 * 1024;
+
+/// The most paths that a stream can watch. Each watched path's reads complete through its
+/// own event, and `WaitForMultipleObjects` waits for at most 64 events, one of which is
+/// used to wake the stream.
+pub const MAX_WATCHED_PATHS: usize = 63;
+
+pub struct EventStream {
+    lifecycle: Arc<Mutex<Lifecycle>>,
+    watched_paths: Arc<Mutex<WatchedPaths>>,
+    state: State,
+    pub(crate) pipeline: Pipeline,
+}
+
+struct State {
+    latency: Duration,
+    wake_event: HANDLE,
+    watches: Vec<Box<Watch>>,
+    next_event_id: u64,
+    report_inodes: bool,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        self.watches.clear();
+        unsafe {
+            CloseHandle(self.wake_event);
+        }
+    }
+}
+
+/// A directory with an outstanding read of its changes. It's boxed so that the
+/// `OVERLAPPED` and the buffer stay put while the read is in flight.
+struct Watch {
+    path: PathBuf,
+    directory: PathBuf,
+    file_name: Option<OsString>,
+    handle: HANDLE,
+    overlapped: OVERLAPPED,
+    buffer: Box<[u32]>,
+    pending: bool,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        unsafe {
+            if self.pending {
+                let mut len = 0;
+                CancelIoEx(self.handle, &self.overlapped);
+                GetOverlappedResult(self.handle, &self.overlapped, &mut len, TRUE);
+            }
+            CloseHandle(self.overlapped.hEvent);
+            CloseHandle(self.handle);
+        }
+    }
+}
+
+enum Lifecycle {
+    New,
+    Running(HANDLE),
+    Stopped,
+}
+
+pub struct Handle {
+    lifecycle: Arc<Mutex<Lifecycle>>,
+    pub(crate) watched_paths: Arc<Mutex<WatchedPaths>>,
+}
+
+unsafe impl Send for EventStream {}
+
+impl EventStream {
+    /// Creates a stream that reports changes to the given paths. Changes to everything
+    /// beneath a watched directory are reported, while a watched file only has its own
+    /// changes reported. Since a file is watched through its parent directory, its
+    /// removal is reported as such, and changes keep being reported if it's replaced or
+    /// recreated, as editors do when saving by renaming a new file over the old one.
+    ///
+    /// Panics if more than [`MAX_WATCHED_PATHS`] paths are given.
+    pub fn new(paths: &[&Path], latency: Duration) -> (Self, Handle) {
+        assert!(
+            paths.len() <= MAX_WATCHED_PATHS,
+            "can't watch more than {MAX_WATCHED_PATHS} paths"
+        );
+        let wake_event = unsafe { CreateEventW(ptr::null(), TRUE, FALSE, ptr::null()) };
+        assert!(wake_event != 0);
+
+        // Reads are started here rather than in `run`, so that no events are missed
+        // between creating the stream and running it.
+        let mut state = State {
+            latency,
+            wake_event,
+            watches: Vec::new(),
+            next_event_id: 1,
+            report_inodes: false,
+        };
+        for path in paths {
+            if let Some(watch) = Watch::new(path) {
+                state.watches.push(watch);
+            }
+        }
+
+        let lifecycle = Arc::new(Mutex::new(Lifecycle::New));
+        let watched_paths = WatchedPaths::new(paths);
+        (
+            EventStream {
+                lifecycle: lifecycle.clone(),
+                watched_paths: watched_paths.clone(),
+                state,
+                pipeline: Pipeline::new(watched_paths.clone()),
+            },
+            Handle {
+                lifecycle,
+                watched_paths,
+            },
+        )
+    }
+
+    /// Reports the file index of each event's path as `Event::inode`. This has a small
+    /// cost, since each path has to be opened when its event is observed.
+    pub fn report_inodes(&mut self) {
+        self.state.report_inodes = true;
+    }
+
+    pub fn run<F>(mut self, mut f: F) -> StopReason
+    where
+        F: FnMut(Vec<Event>) -> bool + 'static,
+    {
+        {
+            let mut lifecycle = self.lifecycle.lock();
+            match *lifecycle {
+                Lifecycle::New => *lifecycle = Lifecycle::Running(self.state.wake_event),
+                Lifecycle::Running(_) => unreachable!(),
+                Lifecycle::Stopped => return StopReason::Stopped,
+            }
+        }
+        if let Some(roots) = self.watched_paths.lock().take_changed() {
+            self.state.set_roots(roots);
+        }
+        if let Some(latency) = self.watched_paths.lock().take_latency() {
+            self.state.latency = latency;
+        }
+
+        let mut events = Vec::new();
+        let mut deadline = None;
+        let reason = loop {
+            let timeout = deadline.map_or(INFINITE, |deadline: Instant| {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                remaining.as_millis().min(INFINITE as u128 - 1) as u32
+            });
+            let handles = Some(self.state.wake_event)
+                .into_iter()
+                .chain(
+                    self.state
+                        .watches
+                        .iter()
+                        .map(|watch| watch.overlapped.hEvent),
+                )
+                .collect::<Vec<_>>();
+            let result = unsafe {
+                WaitForMultipleObjects(handles.len() as u32, handles.as_ptr(), FALSE, timeout)
+            };
+            if result == WAIT_OBJECT_0 {
+                unsafe {
+                    ResetEvent(self.state.wake_event);
+                }
+                if matches!(*self.lifecycle.lock(), Lifecycle::Stopped) {
+                    break StopReason::Stopped;
+                }
+                if let Some(roots) = self.watched_paths.lock().take_changed() {
+                    self.state.set_roots(roots);
+                }
+                if let Some(latency) = self.watched_paths.lock().take_latency() {
+                    deadline = deadline.map(|deadline| deadline - self.state.latency + latency);
+                    self.state.latency = latency;
+                }
+            } else if result == WAIT_FAILED {
+                break StopReason::Failed(unsafe { GetLastError() } as i32);
+            } else if result != WAIT_TIMEOUT {
+                let Some(ix) = (result as usize)
+                    .checked_sub(WAIT_OBJECT_0 as usize + 1)
+                    .filter(|ix| *ix < self.state.watches.len())
+                else {
+                    // Any other result, such as `WAIT_ABANDONED_0`, means waiting failed.
+                    break StopReason::Failed(unsafe { GetLastError() } as i32);
+                };
+                let len = events.len();
+                self.state.read_events(ix, &mut events);
+                if deadline.is_none() && events.len() > len {
+                    deadline = Some(Instant::now() + self.state.latency);
+                }
+            }
+
+            if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                deadline = None;
+                if !self.pipeline.deliver(mem::take(&mut events), &mut f) {
+                    break StopReason::CallbackReturnedFalse;
+                }
+            }
+        };
+
+        *self.lifecycle.lock() = Lifecycle::Stopped;
+        reason
+    }
+}
+
+impl State {
+    fn set_roots(&mut self, roots: Vec<PathBuf>) {
+        self.watches.retain(|watch| roots.contains(&watch.path));
+        for root in roots {
+            if !self.watches.iter().any(|watch| watch.path == root) {
+                if let Some(watch) = Watch::new(&root) {
+                    self.watches.push(watch);
+                }
+            }
+        }
+    }
+
+    fn read_events(&mut self, ix: usize, events: &mut Vec<Event>) {
+        let watch = &mut self.watches[ix];
+        let changes = watch.take_changes();
+        let path = watch.path.clone();
+        let is_watching = watch.read();
+
+        match changes {
+            Ok(changes) => self.push_changes(changes, events),
+            // The buffer overflowed, so changes were dropped.
+            Err(error) if error == ERROR_NOTIFY_ENUM_DIR => {
+                events.push(self.event(StreamFlags::MUST_SCAN_SUBDIRS, path));
+            }
+            // The watched directory is no longer accessible, e.g. because it was removed.
+            Err(_) => {
+                events.push(self.event(StreamFlags::ROOT_CHANGED, path));
+            }
+        }
+        if !is_watching {
+            self.watches.remove(ix);
+        }
+    }
+
+    fn push_changes(&mut self, changes: Vec<(u32, PathBuf)>, events: &mut Vec<Event>) {
+        // A rename is reported as its old name followed by its new name. Both halves are
+        // reported as renames of the same kind of item, which can only be determined from
+        // the new name, since nothing exists at the old one anymore.
+        let mut renamed_from = None;
+        for (action, path) in changes {
+            if action == FILE_ACTION_RENAMED_NEW_NAME {
+                let kind = kind_of_path(&path);
+                if let Some(old_path) = renamed_from.take() {
+                    events.push(self.event(StreamFlags::ITEM_RENAMED | kind, old_path));
+                }
+                events.push(self.event(StreamFlags::ITEM_RENAMED | kind, path));
+                continue;
+            }
+
+            // An old name without a new one was moved out of the watched directory.
+            if let Some(old_path) = renamed_from.take() {
+                events.push(self.event(StreamFlags::ITEM_RENAMED, old_path));
+            }
+            let flags = match action {
+                FILE_ACTION_RENAMED_OLD_NAME => {
+                    renamed_from = Some(path);
+                    continue;
+                }
+                FILE_ACTION_ADDED => StreamFlags::ITEM_CREATED | kind_of_path(&path),
+                FILE_ACTION_REMOVED => StreamFlags::ITEM_REMOVED,
+                FILE_ACTION_MODIFIED => StreamFlags::ITEM_MODIFIED | kind_of_path(&path),
+                _ => continue,
+            };
+            events.push(self.event(flags, path));
+        }
+        if let Some(old_path) = renamed_from {
+            events.push(self.event(StreamFlags::ITEM_RENAMED, old_path));
+        }
+    }
+
+    fn event(&mut self, flags: StreamFlags, path: PathBuf) -> Event {
+        let event_id = self.next_event_id;
+        self.next_event_id += 1;
+        let inode = if self.report_inodes {
+            file_index(&path)
+        } else {
+            None
+        };
+        Event {
+            event_id,
+            flags,
+            path,
+            inode,
+            root: None,
+        }
+    }
+}
+
+impl Watch {
+    fn new(path: &Path) -> Option<Box<Self>> {
+        let (directory, file_name) = if fs::metadata(path).ok()?.is_dir() {
+            (path.to_path_buf(), None)
+        } else {
+            (
+                path.parent()?.to_path_buf(),
+                Some(path.file_name()?.to_os_string()),
+            )
+        };
+
+        let wide_directory = to_wide(directory.as_os_str());
+        unsafe {
+            let handle = CreateFileW(
+                wide_directory.as_ptr(),
+                FILE_LIST_DIRECTORY,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                ptr::null(),
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED,
+                0,
+            );
+            if handle == INVALID_HANDLE_VALUE {
+                return None;
+            }
+
+            let mut overlapped = mem::zeroed::<OVERLAPPED>();
+            overlapped.hEvent = CreateEventW(ptr::null(), TRUE, FALSE, ptr::null());
+            let mut watch = Box::new(Watch {
+                path: path.to_path_buf(),
+                directory,
+                file_name,
+                handle,
+                overlapped,
+                buffer: vec![0; BUFFER_LEN].into_boxed_slice(),
+                pending: false,
+            });
+            watch.read().then_some(watch)
+        }
+    }
+
+    /// Starts reading the next changes, returning whether the directory is still watched.
+    fn read(&mut self) -> bool {
+        unsafe {
+            ResetEvent(self.overlapped.hEvent);
+            self.pending = ReadDirectoryChangesW(
+                self.handle,
+                self.buffer.as_mut_ptr() as *mut c_void,
+                (self.buffer.len() * mem::size_of::<u32>()) as u32,
+                if self.file_name.is_none() {
+                    TRUE
+                } else {
+                    FALSE
+                },
+                NOTIFY_FILTER,
+                ptr::null_mut(),
+                &mut self.overlapped,
+                None,
+            ) != 0;
+        }
+        self.pending
+    }
+
+    /// Returns the actions and paths from the read that completed, or the error that it
+    /// failed with.
+    fn take_changes(&mut self) -> Result<Vec<(u32, PathBuf)>, u32> {
+        let mut len = 0;
+        self.pending = false;
+        unsafe {
+            if GetOverlappedResult(self.handle, &self.overlapped, &mut len, FALSE) == 0 {
+                return Err(GetLastError());
+            }
+        }
+        if len == 0 {
+            return Err(ERROR_NOTIFY_ENUM_DIR);
+        }
+
+        let mut changes = Vec::new();
+        let mut offset = 0;
+        loop {
+            let (next_offset, action, name) = unsafe {
+                let info = (self.buffer.as_ptr() as *const u8).add(offset)
+                    as *const FILE_NOTIFY_INFORMATION;
+                let name = slice::from_raw_parts(
+                    ptr::addr_of!((*info).FileName) as *const u16,
+                    (*info).FileNameLength as usize / mem::size_of::<u16>(),
+                );
+                (
+                    (*info).NextEntryOffset as usize,
+                    (*info).Action,
+                    OsString::from_wide(name),
+                )
+            };
+
+            if self
+                .file_name
+                .as_ref()
+                .map_or(true, |file_name| *file_name == name)
+            {
+                changes.push((action, self.directory.join(name)));
+            }
+            if next_offset == 0 {
+                break;
+            }
+            offset += next_offset;
+        }
+        Ok(changes)
+    }
+}
+
+fn to_wide(s: &OsStr) -> Vec<u16> {
+    s.encode_wide().chain(Some(0)).collect()
+}
+
+/// Returns the file index of the given path, which identifies it within its volume like
+/// an inode. Symlinks are identified themselves, rather than their targets.
+fn file_index(path: &Path) -> Option<u64> {
+    let wide_path = to_wide(path.as_os_str());
+    unsafe {
+        let handle = CreateFileW(
+            wide_path.as_ptr(),
+            0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            ptr::null(),
+            OPEN_EXISTING,
+            FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT,
+            0,
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            return None;
+        }
+        let mut info = mem::zeroed::<BY_HANDLE_FILE_INFORMATION>();
+        let result = GetFileInformationByHandle(handle, &mut info);
+        CloseHandle(handle);
+        (result != 0).then(|| (info.nFileIndexHigh as u64) << 32 | info.nFileIndexLow as u64)
+    }
+}
+
+fn kind_of_path(path: &Path) -> StreamFlags {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_symlink() => StreamFlags::IS_SYMLINK,
+        Ok(metadata) if metadata.is_dir() => StreamFlags::IS_DIR,
+        Ok(_) => StreamFlags::IS_FILE,
+        Err(_) => StreamFlags::empty(),
+    }
+}
+
+impl Handle {
+    /// Stops the stream, causing `EventStream::run` to return promptly if it's running,
+    /// or as soon as it's called otherwise.
+    pub fn stop(&self) {
+        let mut lifecycle = self.lifecycle.lock();
+        if let Lifecycle::Running(wake_event) = *lifecycle {
+            unsafe {
+                SetEvent(wake_event);
+            }
+        }
+        *lifecycle = Lifecycle::Stopped;
+    }
+
+    pub(crate) fn notify_changed(&self) {
+        if let Lifecycle::Running(wake_event) = *self.lifecycle.lock() {
+            unsafe {
+                SetEvent(wake_event);
+            }
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, sync::mpsc, thread, time::Duration};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_event_stream_rename() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        fs::write(path.join("old-name"), "").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let (stream, handle) = EventStream::new(&[&path], Duration::from_millis(50));
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+
+        fs::rename(path.join("old-name"), path.join("new-name")).unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].path, path.join("old-name"));
+        assert_eq!(events[0].flags, StreamFlags::ITEM_REMOVED);
+        assert_eq!(events[1].path, path.join("new-name"));
+        assert!(events[1].flags.contains(StreamFlags::ITEM_RENAMED));
+        drop(handle);
+    }
+
+    #[test]
+    fn test_event_stream_report_inodes() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let (mut stream, _handle) = EventStream::new(&[&path], Duration::from_millis(50));
+        stream.report_inodes();
+        thread::spawn(move || stream.run(move |events| tx.send(events.to_vec()).is_ok()));
+
+        fs::write(path.join("new-file"), "").unwrap();
+        let inode = file_index(&path.join("new-file"));
+        assert!(inode.is_some());
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events
+            .iter()
+            .all(|event| event.path == path.join("new-file") && event.inode == inode));
+
+        // The file index is kept when a file is renamed.
+        fs::rename(path.join("new-file"), path.join("renamed-file")).unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events
+            .iter()
+            .any(|event| event.path == path.join("renamed-file") && event.inode == inode));
+
+        fs::remove_file(path.join("renamed-file")).unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events.iter().all(|event| event.inode.is_none()));
+    }
+
+    #[test]
+    #[should_panic(expected = "can't watch more than 63 paths")]
+    fn test_event_stream_too_many_paths() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        let paths = vec![path.as_path(); MAX_WATCHED_PATHS + 1];
+        EventStream::new(&paths, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_event_stream_add_too_many_paths() {
+        let dir = TempDir::new("test-event-stream").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        let paths = (0..MAX_WATCHED_PATHS)
+            .map(|ix| path.join(ix.to_string()))
+            .collect::<Vec<_>>();
+        let (_stream, handle) = EventStream::new(&[&paths[0]], Duration::from_millis(50));
+        for path in &paths[1..] {
+            handle.add_path(path).unwrap();
+        }
+
+        // Paths that are already watched can still be added again.
+        handle.add_path(&paths[0]).unwrap();
+        assert_eq!(
+            handle.add_path(&path.join("one-too-many")),
+            Err(crate::TooManyPathsError)
+        );
+    }
+}
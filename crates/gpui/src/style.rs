@@ -194,6 +194,9 @@ pub struct TextStyle {
     /// The underline style of the text
     pub underline: Option<UnderlineStyle>,
 
+    /// The strikethrough style of the text
+    pub strikethrough: Option<StrikethroughStyle>,
+
     /// How to handle whitespace in the text
     pub white_space: WhiteSpace,
 }
@@ -211,6 +214,7 @@ impl Default for TextStyle {
             font_style: FontStyle::default(),
             background_color: None,
             underline: None,
+            strikethrough: None,
             white_space: WhiteSpace::Normal,
         }
     }
@@ -243,6 +247,10 @@ impl TextStyle {
             self.underline = Some(underline);
         }
 
+        if let Some(strikethrough) = style.strikethrough {
+            self.strikethrough = Some(strikethrough);
+        }
+
         self
     }
 
@@ -274,6 +282,7 @@ impl TextStyle {
             color: self.color,
             background_color: self.background_color,
             underline: self.underline,
+            strikethrough: self.strikethrough,
         }
     }
 }
@@ -297,6 +306,9 @@ pub struct HighlightStyle {
     /// The underline style of the text
     pub underline: Option<UnderlineStyle>,
 
+    /// The strikethrough style of the text
+    pub strikethrough: Option<StrikethroughStyle>,
+
     /// Similar to the CSS `opacity` property, this will cause the text to be less vibrant.
     pub fade_out: Option<f32>,
 }
@@ -550,6 +562,17 @@ pub struct UnderlineStyle {
     pub wavy: bool,
 }
 
+/// The properties that can be applied to a strikethrough.
+#[derive(Refineable, Copy, Clone, Default, Debug, PartialEq, Eq)]
+#[refineable(Debug)]
+pub struct StrikethroughStyle {
+    /// The thickness of the strikethrough.
+    pub thickness: Pixels,
+
+    /// The color of the strikethrough.
+    pub color: Option<Hsla>,
+}
+
 /// The kinds of fill that can be applied to a shape.
 #[derive(Clone, Debug)]
 pub enum Fill {
@@ -598,6 +621,7 @@ impl From<&TextStyle> for HighlightStyle {
             font_style: Some(other.font_style),
             background_color: other.background_color,
             underline: other.underline,
+            strikethrough: other.strikethrough,
             fade_out: None,
         }
     }
@@ -633,6 +657,10 @@ impl HighlightStyle {
             self.underline = other.underline;
         }
 
+        if other.strikethrough.is_some() {
+            self.strikethrough = other.strikethrough;
+        }
+
         match (other.fade_out, self.fade_out) {
             (Some(source_fade), None) => self.fade_out = Some(source_fade),
             (Some(source_fade), Some(dest_fade)) => {
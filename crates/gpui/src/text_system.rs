@@ -10,7 +10,7 @@ pub use line_wrapper::*;
 
 use crate::{
     px, Bounds, DevicePixels, EntityId, Hsla, Pixels, PlatformTextSystem, Point, Result,
-    SharedString, Size, UnderlineStyle,
+    SharedString, Size, StrikethroughStyle, UnderlineStyle,
 };
 use anyhow::anyhow;
 use collections::{BTreeSet, FxHashMap, FxHashSet};
@@ -294,6 +294,7 @@ impl TextSystem {
             if let Some(last_run) = decoration_runs.last_mut() {
                 if last_run.color == run.color
                     && last_run.underline == run.underline
+                    && last_run.strikethrough == run.strikethrough
                     && last_run.background_color == run.background_color
                 {
                     last_run.len += run.len as u32;
@@ -305,6 +306,7 @@ impl TextSystem {
                 color: run.color,
                 background_color: run.background_color,
                 underline: run.underline,
+                strikethrough: run.strikethrough,
             });
         }
 
@@ -359,6 +361,7 @@ impl TextSystem {
                 if decoration_runs.last().map_or(false, |last_run| {
                     last_run.color == run.color
                         && last_run.underline == run.underline
+                        && last_run.strikethrough == run.strikethrough
                         && last_run.background_color == run.background_color
                 }) {
                     decoration_runs.last_mut().unwrap().len += run_len_within_line as u32;
@@ -368,6 +371,7 @@ impl TextSystem {
                         color: run.color,
                         background_color: run.background_color,
                         underline: run.underline,
+                        strikethrough: run.strikethrough,
                     });
                 }
 
@@ -580,6 +584,8 @@ pub struct TextRun {
     pub background_color: Option<Hsla>,
     /// The underline style (if any)
     pub underline: Option<UnderlineStyle>,
+    /// The strikethrough style (if any)
+    pub strikethrough: Option<StrikethroughStyle>,
 }
 
 /// An identifier for a specific glyph, as returned by [`TextSystem::layout_line`].
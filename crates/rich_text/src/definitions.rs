@@ -0,0 +1,107 @@
+use crate::replacements::{apply_replacements, is_protected, protected_ranges, Replacement};
+
+/// The indentation that definitions are rendered with. Non-breaking spaces are used, since
+/// leading spaces are stripped from the lines of a paragraph.
+const DEFINITION_INDENT: &str = "\u{a0}\u{a0}\u{a0}\u{a0}";
+
+/// Rewrites each definition list in the given markdown, written as a paragraph whose
+/// first line is a term and whose other lines each start with `: ` followed by one of
+/// its definitions, so that the term is bold and each definition is on its own indented
+/// line. Returns the rewritten markdown, along with the replacements that were made.
+///
+/// Paragraphs with any other lines, such as a line that merely contains a colon, as in
+/// `ratio 3:1`, are left untouched, as are those whose markers are within code, HTML, or
+/// links.
+pub fn expand_definition_lists(block: &str) -> (String, Vec<Replacement>) {
+    use pulldown_cmark::{Event, Options, Parser, Tag};
+
+    let protected_ranges = protected_ranges(block);
+    let mut replacements = Vec::new();
+    for (event, range) in Parser::new_ext(block, Options::all()).into_offset_iter() {
+        if !matches!(event, Event::Start(Tag::Paragraph)) {
+            continue;
+        }
+
+        let paragraph = block[range.clone()].trim_end_matches('\n');
+        let mut lines = paragraph.split('\n');
+        let Some(term) = lines.next() else {
+            continue;
+        };
+        let Some(definitions) = lines
+            .map(|line| {
+                let marker_start = line.len() - line.trim_start().len();
+                let rest = line[marker_start..].strip_prefix(':')?;
+                let definition = rest.trim_start_matches([' ', '\t']);
+                (definition.len() < rest.len() && !definition.trim().is_empty()).then_some((
+                    marker_start,
+                    line.len() - definition.len(),
+                    line.len(),
+                ))
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|definitions| !definitions.is_empty())
+        else {
+            continue;
+        };
+
+        let term_end = range.start + term.trim_end().len();
+        let mut paragraph_replacements = Vec::new();
+        paragraph_replacements.push(Replacement {
+            range: range.start..range.start,
+            text: "**",
+        });
+        paragraph_replacements.push(Replacement {
+            range: term_end..range.start + term.len(),
+            text: "**\\",
+        });
+        let mut line_start = range.start + term.len() + 1;
+        for (ix, (marker_start, definition_start, line_len)) in
+            definitions.iter().copied().enumerate()
+        {
+            paragraph_replacements.push(Replacement {
+                range: line_start + marker_start..line_start + definition_start,
+                text: DEFINITION_INDENT,
+            });
+            if ix + 1 < definitions.len() {
+                let line = &block[line_start..line_start + line_len];
+                paragraph_replacements.push(Replacement {
+                    range: line_start + line.trim_end().len()..line_start + line_len,
+                    text: "\\",
+                });
+            }
+            line_start += line_len + 1;
+        }
+        if !paragraph_replacements
+            .iter()
+            .any(|replacement| is_protected(&replacement.range, &protected_ranges))
+        {
+            replacements.extend(paragraph_replacements);
+        }
+    }
+
+    (apply_replacements(block, &replacements), replacements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_definition_lists() {
+        let (text, _) = expand_definition_lists("Apple\n: A fruit\n:   A company\n");
+        assert_eq!(
+            text,
+            "**Apple**\\\n\u{a0}\u{a0}\u{a0}\u{a0}A fruit\\\n\u{a0}\u{a0}\u{a0}\u{a0}A company\n"
+        );
+
+        for block in [
+            "ratio 3:1",
+            "Apple\n:no space",
+            "Apple\n: A fruit\nthat is red",
+            "```\nApple\n: A fruit\n```",
+            "Apple `a\n: b`",
+        ] {
+            assert_eq!(expand_definition_lists(block), (block.to_string(), vec![]));
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::replacements::{apply_replacements, is_protected, protected_ranges, Replacement};
+
+/// Emoji for commonly used `:shortcode:` names, sorted by name.
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("angry", "😠"),
+    ("blush", "😊"),
+    ("boom", "💥"),
+    ("broken_heart", "💔"),
+    ("bug", "🐛"),
+    ("clap", "👏"),
+    ("confused", "😕"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("grinning", "😀"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("joy", "😂"),
+    ("laughing", "😆"),
+    ("muscle", "💪"),
+    ("ok_hand", "👌"),
+    ("pray", "🙏"),
+    ("raised_hands", "🙌"),
+    ("rocket", "🚀"),
+    ("rofl", "🤣"),
+    ("sad", "😞"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("slightly_smiling_face", "🙂"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("sunglasses", "😎"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("upside_down_face", "🙃"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("x", "❌"),
+    ("zap", "⚡"),
+];
+
+pub fn emoji_for_shortcode(name: &str) -> Option<&'static str> {
+    SHORTCODES
+        .binary_search_by_key(&name, |(name, _)| *name)
+        .ok()
+        .map(|ix| SHORTCODES[ix].1)
+}
+
+/// Replaces every recognized `:shortcode:` in the given markdown with its emoji, leaving
+/// text within code, HTML, or links untouched.
+pub fn expand_shortcodes(block: &str) -> (String, Vec<Replacement>) {
+    let protected_ranges = protected_ranges(block);
+
+    let mut replacements = Vec::new();
+    let mut search_start = 0;
+    while let Some(open) = block[search_start..].find(':').map(|ix| search_start + ix) {
+        let Some(close) = block[open + 1..].find(':').map(|ix| open + 1 + ix) else {
+            break;
+        };
+        let range = open..close + 1;
+        let emoji = emoji_for_shortcode(&block[open + 1..close])
+            .filter(|_| !is_protected(&range, &protected_ranges));
+        if let Some(emoji) = emoji {
+            replacements.push(Replacement { range, text: emoji });
+            search_start = close + 1;
+        } else {
+            // The closing colon may be the start of another shortcode, as in `a:b:smile:`.
+            search_start = close;
+        }
+    }
+
+    (apply_replacements(block, &replacements), replacements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shortcodes_are_sorted() {
+        for pair in SHORTCODES.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "{:?} is out of order", pair[1].0);
+        }
+    }
+
+    #[test]
+    fn test_expand_shortcodes() {
+        let (text, _) = expand_shortcodes("a :tada: b :x:");
+        assert_eq!(text, "a 🎉 b ❌");
+
+        for block in [
+            "`:x:`",
+            "<a title=\":x:\">",
+            "[:x:](https://example.com/:x:)",
+        ] {
+            assert_eq!(expand_shortcodes(block), (block.to_string(), vec![]));
+        }
+    }
+}
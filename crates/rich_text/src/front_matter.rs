@@ -0,0 +1,143 @@
+use collections::HashMap;
+
+/// Parses the YAML front matter at the very start of the given markdown, which is
+/// delimited by `---` lines, as in `---\ntitle: Intro\n---`. Returns its top-level
+/// `key: value` pairs, with any quotes around the values removed, along with the length
+/// of the front matter, including its delimiters. Keys whose values are nested, such as
+/// lists, are skipped.
+///
+/// Returns `None` if the markdown doesn't start with front matter, including when any
+/// of its top-level lines isn't a `key: value` pair, so that markdown that merely starts
+/// with a horizontal rule isn't mistaken for front matter.
+pub fn parse_front_matter(block: &str) -> Option<(HashMap<String, String>, usize)> {
+    match scan_front_matter(block) {
+        FrontMatter::Closed(pairs, len) => Some((pairs, len)),
+        FrontMatter::Unclosed | FrontMatter::Invalid => None,
+    }
+}
+
+/// Returns whether the given markdown starts with front matter that hasn't been closed
+/// yet, such that more text could still complete it. Its last line is ignored if it's
+/// incomplete, since a partial line such as `tit` could still become a `key: value` pair.
+pub fn is_unclosed_front_matter(block: &str) -> bool {
+    matches!(scan_front_matter(block), FrontMatter::Unclosed)
+}
+
+enum FrontMatter {
+    Closed(HashMap<String, String>, usize),
+    Unclosed,
+    Invalid,
+}
+
+fn scan_front_matter(block: &str) -> FrontMatter {
+    let mut lines = block.split_inclusive('\n');
+    let Some(opening) = lines.next() else {
+        return FrontMatter::Unclosed;
+    };
+    if !opening.ends_with('\n') {
+        return if "---".starts_with(opening) {
+            FrontMatter::Unclosed
+        } else {
+            FrontMatter::Invalid
+        };
+    }
+    if opening.trim_end() != "---" {
+        return FrontMatter::Invalid;
+    }
+
+    let mut len = opening.len();
+    let mut pairs = HashMap::default();
+    for line in lines {
+        len += line.len();
+        let is_complete = line.ends_with('\n');
+        let line = line.trim_end();
+        if line == "---" || line == "..." {
+            return FrontMatter::Closed(pairs, len);
+        }
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("- ")
+            || line.starts_with(char::is_whitespace)
+        {
+            continue;
+        }
+
+        let key_value = line
+            .split_once(':')
+            .filter(|(key, _)| !key.trim().is_empty());
+        let Some((key, value)) = key_value else {
+            return if is_complete {
+                FrontMatter::Invalid
+            } else {
+                FrontMatter::Unclosed
+            };
+        };
+        let value = unquote(value.trim());
+        if !value.is_empty() {
+            pairs.insert(key.trim().to_string(), value.to_string());
+        }
+    }
+    FrontMatter::Unclosed
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(unquoted) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return unquoted;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_front_matter() {
+        let block = "---\ntitle: \"Intro: Part 1\"\nslug: 'intro'\ntags:\n  - a\n- b\n# comment\n\n---\n# Intro";
+        let (pairs, len) = parse_front_matter(block).unwrap();
+        assert_eq!(&block[len..], "# Intro");
+        assert_eq!(
+            pairs,
+            HashMap::from_iter([
+                ("title".to_string(), "Intro: Part 1".to_string()),
+                ("slug".to_string(), "intro".to_string()),
+            ])
+        );
+
+        for block in [
+            "# Intro\n---\ntitle: Intro\n---",
+            "---\nA heading\n---\ntext",
+            "---\ntitle: Intro",
+            " ---\ntitle: Intro\n---",
+        ] {
+            assert_eq!(parse_front_matter(block), None);
+        }
+    }
+
+    #[test]
+    fn test_is_unclosed_front_matter() {
+        for block in [
+            "",
+            "--",
+            "---\n",
+            "---\ntitle: x\n",
+            "---\ntit",
+            "---\ntitle: x\n--",
+        ] {
+            assert!(is_unclosed_front_matter(block), "{block:?}");
+        }
+        for block in [
+            "---\ntitle: x\n---\n",
+            "-- ",
+            "# Intro\n",
+            "---\nA heading\n",
+        ] {
+            assert!(!is_unclosed_front_matter(block), "{block:?}");
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::replacements::{is_protected, protected_ranges};
+use std::ops::Range;
+
+/// Rewrites each `$inline$` and `$$display$$` math span in the given markdown as a code
+/// span, so that the markdown within it, such as the `_` in `$x_1$`, isn't rendered.
+/// Returns the rewritten markdown, which has the same length, along with the range of
+/// each math span, including its delimiters.
+///
+/// As in Pandoc, inline math can't start or end with whitespace, or be followed by a
+/// digit, so that prices such as `$5 and $10` aren't mistaken for math. Dollar signs
+/// escaped as `\$` and those within code, HTML, or links are left untouched.
+pub fn protect_math(block: &str) -> (String, Vec<Range<usize>>) {
+    let protected_ranges = protected_ranges(block);
+
+    let mut math_ranges = Vec::new();
+    let mut search_start = 0;
+    while let Some(open) = find_unescaped(block, search_start, "$") {
+        let delimiter = if block[open..].starts_with("$$") {
+            "$$"
+        } else {
+            "$"
+        };
+        let content_start = open + delimiter.len();
+        let Some(close) = find_unescaped(block, content_start, delimiter) else {
+            break;
+        };
+        let content = &block[content_start..close];
+        let range = open..close + delimiter.len();
+        let is_valid = if delimiter == "$" {
+            !content.is_empty()
+                && !content.contains('\n')
+                && !content.starts_with(char::is_whitespace)
+                && !content.ends_with(char::is_whitespace)
+                && !block[range.end..].starts_with(|c: char| c.is_ascii_digit())
+        } else {
+            !content.trim().is_empty()
+        };
+        let is_valid = is_valid
+            && !content.contains('`')
+            && !block[..open].ends_with('`')
+            && !block[range.end..].starts_with('`')
+            && !is_protected(&range, &protected_ranges);
+        if is_valid {
+            search_start = range.end;
+            math_ranges.push(range);
+        } else {
+            // The closing delimiter may be the start of another math span.
+            search_start = close;
+        }
+    }
+
+    let mut text = String::with_capacity(block.len());
+    let mut offset = 0;
+    for range in &math_ranges {
+        let delimiter_len = if block[range.clone()].starts_with("$$") {
+            2
+        } else {
+            1
+        };
+        let backticks = &"``"[..delimiter_len];
+        text.push_str(&block[offset..range.start]);
+        text.push_str(backticks);
+        text.push_str(&block[range.start + delimiter_len..range.end - delimiter_len]);
+        text.push_str(backticks);
+        offset = range.end;
+    }
+    text.push_str(&block[offset..]);
+    (text, math_ranges)
+}
+
+/// Finds the next occurrence of the given delimiter at or after `start` that isn't
+/// escaped with a backslash.
+fn find_unescaped(text: &str, mut start: usize, delimiter: &str) -> Option<usize> {
+    while let Some(ix) = text[start..].find(delimiter).map(|ix| start + ix) {
+        let backslash_count = text[..ix]
+            .bytes()
+            .rev()
+            .take_while(|byte| *byte == b'\\')
+            .count();
+        if backslash_count % 2 == 0 {
+            return Some(ix);
+        }
+        start = ix + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protect_math() {
+        assert_eq!(
+            protect_math("if $x_1 = y_2$, then $$\\sum_i x_i$$"),
+            (
+                "if `x_1 = y_2`, then ``\\sum_i x_i``".to_string(),
+                vec![3..14, 21..35]
+            )
+        );
+
+        // Prices, escaped dollar signs, and dollar signs within code aren't math.
+        for block in [
+            "from $5 to $10",
+            "costs $5, or $ 6 $",
+            "\\$x$ and $y\\$",
+            "`$x$` and\n```\n$y$\n```",
+            "[$x$](https://$y$.dev)",
+        ] {
+            assert_eq!(protect_math(block), (block.to_string(), vec![]));
+        }
+    }
+}
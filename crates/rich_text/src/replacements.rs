@@ -0,0 +1,95 @@
+use std::ops::Range;
+
+/// A part of the markdown that was replaced by one of the passes that rewrite it before
+/// it's rendered, such as a `:shortcode:` that was replaced with its emoji.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replacement {
+    /// The range of the replaced text in the original text.
+    pub range: Range<usize>,
+    pub text: &'static str,
+}
+
+/// Returns the ranges of the markdown that the passes leave untouched: code, HTML, and
+/// links and images, including their URLs.
+pub fn protected_ranges(block: &str) -> Vec<Range<usize>> {
+    use pulldown_cmark::{Event, Options, Parser, Tag};
+
+    let mut protected_ranges = Vec::new();
+    for (event, range) in Parser::new_ext(block, Options::all()).into_offset_iter() {
+        match event {
+            Event::Code(_)
+            | Event::Html(_)
+            | Event::Start(Tag::CodeBlock(_))
+            | Event::Start(Tag::Link(..))
+            | Event::Start(Tag::Image(..)) => protected_ranges.push(range),
+            _ => {}
+        }
+    }
+    protected_ranges
+}
+
+/// Returns whether the given range overlaps any of the protected ranges.
+pub fn is_protected(range: &Range<usize>, protected_ranges: &[Range<usize>]) -> bool {
+    protected_ranges
+        .iter()
+        .any(|protected| protected.start < range.end && range.start < protected.end)
+}
+
+/// Applies the given replacements, which must be sorted and disjoint, to the text.
+pub fn apply_replacements(block: &str, replacements: &[Replacement]) -> String {
+    let mut text = String::with_capacity(block.len());
+    let mut offset = 0;
+    for replacement in replacements {
+        text.push_str(&block[offset..replacement.range.start]);
+        text.push_str(replacement.text);
+        offset = replacement.range.end;
+    }
+    text.push_str(&block[offset..]);
+    text
+}
+
+/// Maps an offset in the original text to the corresponding offset in the text with the
+/// given replacements applied. Offsets within replaced text are clamped to the end of
+/// its replacement.
+pub fn map_offset(offset: usize, replacements: &[Replacement]) -> usize {
+    let mut mapped = offset;
+    for replacement in replacements {
+        if replacement.range.start >= offset {
+            break;
+        } else if replacement.range.end <= offset {
+            mapped = mapped - replacement.range.len() + replacement.text.len();
+        } else {
+            return mapped - (offset - replacement.range.start) + replacement.text.len();
+        }
+    }
+    mapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emoji::expand_shortcodes;
+
+    #[test]
+    fn test_map_offset() {
+        let (text, replacements) = expand_shortcodes("a :tada: b :x:");
+        assert_eq!(text, "a 🎉 b ❌");
+        assert_eq!(map_offset(0, &replacements), 0);
+        assert_eq!(map_offset(2, &replacements), 2);
+        assert_eq!(map_offset(5, &replacements), 6);
+        assert_eq!(map_offset(9, &replacements), 7);
+        assert_eq!(map_offset(14, &replacements), text.len());
+    }
+
+    #[test]
+    fn test_protected_ranges() {
+        let block = "a `b` <i>c</i> [d](e) ![f](g)\n\n```\nh\n```\n";
+        assert_eq!(
+            protected_ranges(block)
+                .into_iter()
+                .map(|range| &block[range])
+                .collect::<Vec<_>>(),
+            ["`b`", "<i>", "</i>", "[d](e)", "![f](g)", "```\nh\n```"]
+        );
+    }
+}
@@ -1,14 +1,31 @@
+mod definitions;
+mod emoji;
+mod front_matter;
+mod math;
+mod replacements;
+mod scripts;
+mod streaming;
+
+use collections::HashMap;
 use futures::FutureExt;
 use gpui::{
-    AnyElement, ElementId, FontStyle, FontWeight, HighlightStyle, InteractiveText, IntoElement,
-    SharedString, StyledText, UnderlineStyle, WindowContext,
+    AnyElement, AnyView, ClipboardItem, ElementId, FontStyle, FontWeight, HighlightStyle, Hsla,
+    InteractiveText, IntoElement, SharedString, StrikethroughStyle, StyledText, UnderlineStyle,
+    WindowContext,
 };
 use language::{HighlightId, Language, LanguageRegistry};
-use std::{ops::Range, sync::Arc};
-use theme::ActiveTheme;
-use ui::LinkPreview;
+use std::{fmt, iter, mem, ops::Range, sync::Arc};
+use theme::{ActiveTheme, StatusColors};
+use ui::{LinkPreview, Tooltip};
 use util::RangeExt;
 
+pub use pulldown_cmark::HeadingLevel;
+pub use streaming::StreamingRichText;
+
+/// The number of characters used to render a horizontal rule.
+const RULE_WIDTH: usize = 40;
+const ZERO_WIDTH_SPACE: char = '\u{200B}';
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Highlight {
     Code,
@@ -16,6 +33,86 @@ pub enum Highlight {
     Highlight(HighlightStyle),
     Mention,
     SelfMention,
+    Quote,
+    /// The text of a `<kbd>` tag, which is rendered as a keyboard key in addition to the
+    /// given style, and muted like the rest of a quote if it's within one.
+    Key {
+        style: HighlightStyle,
+        in_quote: bool,
+    },
+    /// A line added in a fenced `diff` block.
+    DiffAdded,
+    /// A line removed in a fenced `diff` block.
+    DiffRemoved,
+    /// A hunk header, such as `@@ -1,2 +1,3 @@`, in a fenced `diff` block.
+    DiffHunk,
+    /// Text marked with `==`, which is given a highlighted background in addition to the
+    /// given style.
+    Mark(HighlightStyle),
+    /// The line number prefixed to a line of a code block, which isn't copied with it.
+    LineNumber,
+    /// Text within a GitHub-style alert, such as a quote starting with `[!NOTE]`, which is
+    /// given the alert's background in addition to the given style.
+    Alert(AlertKind, HighlightStyle),
+    /// The icon and title that an alert's `[!KIND]` marker is rendered as.
+    AlertTitle(AlertKind),
+}
+
+/// The kinds of GitHub-style alerts, which are written as a quote whose first line is
+/// `[!NOTE]`, `[!TIP]`, `[!IMPORTANT]`, `[!WARNING]`, or `[!CAUTION]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertKind {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl AlertKind {
+    fn from_marker(marker: &str) -> Option<Self> {
+        let kind = marker.strip_prefix("[!")?.strip_suffix(']')?;
+        [
+            Self::Note,
+            Self::Tip,
+            Self::Important,
+            Self::Warning,
+            Self::Caution,
+        ]
+        .into_iter()
+        .find(|alert_kind| alert_kind.title().eq_ignore_ascii_case(kind))
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Note => "Note",
+            Self::Tip => "Tip",
+            Self::Important => "Important",
+            Self::Warning => "Warning",
+            Self::Caution => "Caution",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            Self::Note => "ⓘ",
+            Self::Tip => "💡",
+            Self::Important => "❗",
+            Self::Warning => "⚠",
+            Self::Caution => "⛔",
+        }
+    }
+
+    /// Returns the color of the alert's title and the background of its text.
+    fn colors(self, status: &StatusColors) -> (Hsla, Hsla) {
+        match self {
+            Self::Note => (status.info, status.info_background),
+            Self::Tip => (status.success, status.success_background),
+            Self::Important => (status.hint, status.hint_background),
+            Self::Warning => (status.warning, status.warning_background),
+            Self::Caution => (status.error, status.error_background),
+        }
+    }
 }
 
 impl From<HighlightStyle> for Highlight {
@@ -30,12 +127,216 @@ impl From<HighlightId> for Highlight {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct RichText {
     pub text: SharedString,
     pub highlights: Vec<(Range<usize>, Highlight)>,
     pub link_ranges: Vec<Range<usize>>,
     pub link_urls: Arc<[String]>,
+    /// The ranges of rendered task list checkboxes, along with whether each one is checked.
+    pub checkbox_ranges: Vec<(Range<usize>, bool)>,
+    /// The ranges of rendered code blocks, which can be clicked to copy their contents.
+    pub code_block_ranges: Vec<Range<usize>>,
+    /// The ranges of rendered mentions, along with the mention each one came from.
+    pub mention_ranges: Vec<(Range<usize>, MentionMeta)>,
+    /// The level and range of each heading, in the order they appear.
+    pub headings: Vec<(HeadingLevel, Range<usize>)>,
+    /// The background color of code spans and blocks, which defaults to the theme's
+    /// surface background. Override this when rendering on a surface of the same color.
+    pub code_background: Option<Hsla>,
+    /// The top-level `key: value` pairs of the front matter that was stripped from the
+    /// start of the markdown, if [`RenderMarkdownOptions::strip_front_matter`] was set.
+    pub front_matter: Option<HashMap<String, String>>,
+    mention_tooltip_fn: Option<Arc<dyn Fn(&MentionMeta, &mut WindowContext) -> Option<AnyView>>>,
+    mention_click_fn: Option<Arc<dyn Fn(&MentionMeta, &mut WindowContext)>>,
+    link_click_fn: Option<Arc<dyn Fn(&str, &mut WindowContext)>>,
+}
+
+impl fmt::Debug for RichText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RichText")
+            .field("text", &self.text)
+            .field("highlights", &self.highlights)
+            .field("link_ranges", &self.link_ranges)
+            .field("link_urls", &self.link_urls)
+            .field("checkbox_ranges", &self.checkbox_ranges)
+            .field("code_block_ranges", &self.code_block_ranges)
+            .field("mention_ranges", &self.mention_ranges)
+            .field("headings", &self.headings)
+            .field("code_background", &self.code_background)
+            .field("front_matter", &self.front_matter)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Controls how HTML embedded in the markdown is rendered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HtmlRendering {
+    /// Drop HTML tags, keeping only the text between them.
+    #[default]
+    Strip,
+    /// Show HTML as literal text, including its angle brackets.
+    Literal,
+    /// Render the inline `<b>`, `<strong>`, `<i>`, `<em>` and `<code>` tags, and strip
+    /// any others.
+    SafeTags,
+}
+
+/// Options that control how markdown is rendered into [`RichText`].
+#[derive(Debug, Clone)]
+pub struct RenderMarkdownOptions {
+    pub html: HtmlRendering,
+    /// Whether to render the text of inline `<kbd>` tags as keyboard keys, unless HTML is
+    /// rendered literally.
+    pub render_kbd_tags: bool,
+    /// Whether to render text surrounded with `==`, as in `==this==`, as highlighted text.
+    pub render_marks: bool,
+    /// Whether to render `$inline$` and `$$display$$` math as code, rather than as
+    /// markdown, since math often contains characters such as `_` and `*`.
+    pub render_math_as_code: bool,
+    /// Whether to render paragraphs made of a term followed by lines starting with `: `,
+    /// each giving one of its definitions, as definition lists.
+    pub render_definition_lists: bool,
+    /// Whether to replace recognized `:shortcode:`s, such as `:tada:`, with their emoji.
+    pub expand_emoji_shortcodes: bool,
+    /// Whether to render text surrounded with `^`, as in `x^2^`, as superscript, and text
+    /// surrounded with a single `~`, as in `H~2~O`, as subscript, using Unicode's
+    /// superscript and subscript characters. This is off by default, since tildes and
+    /// carets are often used on their own in plain text.
+    pub render_sub_superscripts: bool,
+    /// Whether to strip the `---`-delimited YAML front matter at the very start of the
+    /// rendered text, such as that of a docs page, rather than rendering it as a rule
+    /// followed by its lines. Its pairs are exposed as [`RichText::front_matter`].
+    pub strip_front_matter: bool,
+    /// Whether to underline links. Links are always shown in the theme's accent color.
+    pub underline_links: bool,
+    /// Whether to syntax highlight inline code using the default language passed to the
+    /// renderer, as is done for code blocks.
+    pub highlight_inline_code: bool,
+    /// The bullets used for unordered list items, by nesting depth. Lists nested deeper
+    /// than the number of bullets cycle through them again.
+    pub list_bullets: Vec<String>,
+    /// Whether ordered lists start at the number the author gave them, rather than at 1.
+    pub preserve_list_start: bool,
+    /// Whether to render soft line breaks as spaces, rather than newlines. Hard line breaks
+    /// are always rendered as newlines.
+    pub soft_break_as_space: bool,
+    /// The length in bytes above which code isn't syntax highlighted, to avoid blocking
+    /// on very large code blocks.
+    pub max_highlighted_code_len: usize,
+    /// The number of characters after which long runs of code block text without any
+    /// whitespace, such as base64 blobs, are given a break opportunity so that they wrap
+    /// rather than overflowing. This is done by inserting zero-width spaces, which are
+    /// removed when the code block is copied. If `None`, code blocks aren't wrapped.
+    pub code_wrap_width: Option<usize>,
+    /// Whether to prefix each line of code blocks with its line number. The line numbers
+    /// are left out when a code block is copied.
+    pub code_block_line_numbers: bool,
+    /// Finds mentions in text that weren't passed to the renderer explicitly, which are
+    /// rendered as mentions that link to the URL the matcher returns for them.
+    pub mention_matcher: Option<MentionMatcher>,
+    /// Links references to locations in files within text, excluding code, such as the
+    /// `src/main.rs:42:5` in a stack trace, to the URL the matcher returns for them.
+    pub file_location_matcher: Option<FileLocationMatcher>,
+}
+
+impl Default for RenderMarkdownOptions {
+    fn default() -> Self {
+        Self {
+            html: HtmlRendering::default(),
+            render_kbd_tags: false,
+            render_marks: false,
+            render_math_as_code: false,
+            render_definition_lists: false,
+            expand_emoji_shortcodes: false,
+            render_sub_superscripts: false,
+            strip_front_matter: false,
+            underline_links: true,
+            highlight_inline_code: false,
+            list_bullets: vec!["-".into()],
+            preserve_list_start: true,
+            soft_break_as_space: false,
+            max_highlighted_code_len: 100 * 1024,
+            code_wrap_width: None,
+            code_block_line_numbers: false,
+            mention_matcher: None,
+            file_location_matcher: None,
+        }
+    }
+}
+
+/// Scans runs of text, excluding code, for mentions such as `#channel-name`, returning
+/// the range of each mention within the text along with the URL it links to.
+#[derive(Clone)]
+pub struct MentionMatcher(Arc<dyn Fn(&str) -> Vec<(Range<usize>, String)> + Send + Sync>);
+
+impl MentionMatcher {
+    pub fn new(f: impl Fn(&str) -> Vec<(Range<usize>, String)> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// Returns the valid, non-overlapping matches in the given text, in order.
+    fn matches(&self, text: &str) -> Vec<(Range<usize>, String)> {
+        let mut matches = (self.0)(text);
+        matches.retain(|(range, _)| {
+            !range.is_empty()
+                && range.end <= text.len()
+                && text.is_char_boundary(range.start)
+                && text.is_char_boundary(range.end)
+        });
+        matches.sort_by_key(|(range, _)| range.start);
+        let mut end = 0;
+        matches.retain(|(range, _)| {
+            let is_disjoint = range.start >= end;
+            if is_disjoint {
+                end = range.end;
+            }
+            is_disjoint
+        });
+        matches
+    }
+}
+
+impl fmt::Debug for MentionMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MentionMatcher").finish_non_exhaustive()
+    }
+}
+
+/// A reference to a line, and optionally a column, within a file, written as
+/// `path:line` or `path:line:column`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileLocation {
+    pub path: String,
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+/// Returns the URL that a [`FileLocation`] found in text links to, such as one with
+/// a `file://` or internal scheme that the consumer handles by opening the file.
+/// Locations for which it returns `None` aren't linked.
+#[derive(Clone)]
+pub struct FileLocationMatcher(Arc<dyn Fn(&FileLocation) -> Option<String> + Send + Sync>);
+
+impl FileLocationMatcher {
+    pub fn new(f: impl Fn(&FileLocation) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// Returns the linked file locations in the given text, in order.
+    fn matches(&self, text: &str) -> Vec<(Range<usize>, String)> {
+        find_file_locations(text)
+            .into_iter()
+            .filter_map(|(range, location)| Some((range, (self.0)(&location)?)))
+            .collect()
+    }
+}
+
+impl fmt::Debug for FileLocationMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileLocationMatcher")
+            .finish_non_exhaustive()
+    }
 }
 
 /// Allows one to specify extra links to the rendered markdown, which can be used
@@ -44,56 +345,393 @@ pub struct RichText {
 pub struct Mention {
     pub range: Range<usize>,
     pub is_self_mention: bool,
+    /// An identifier for the mentioned user, which is passed back in [`MentionMeta`].
+    pub user_id: Option<u64>,
+}
+
+/// Describes the [`Mention`] that a range of rendered text came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MentionMeta {
+    pub is_self_mention: bool,
+    pub user_id: Option<u64>,
 }
 
 impl RichText {
+    /// Returns the rendered text, without any of its styling.
+    pub fn plain_text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns whether the text contains any links.
+    pub fn has_links(&self) -> bool {
+        !self.link_ranges.is_empty()
+    }
+
+    /// Returns whether the text contains any mentions, whether or not they mention the
+    /// current user.
+    pub fn has_mentions(&self) -> bool {
+        self.highlights
+            .iter()
+            .any(|(_, highlight)| matches!(highlight, Highlight::Mention | Highlight::SelfMention))
+    }
+
+    /// Returns whether any of the text responds to clicks or hovers, in which case it
+    /// needs to be rendered with [`RichText::element`] rather than as static text.
+    pub fn is_interactive(&self) -> bool {
+        self.has_links() || self.has_mentions() || !self.code_block_ranges.is_empty()
+    }
+
+    /// Sorts the highlights by their start, merging the ranges of identical highlights
+    /// that overlap or are adjacent.
+    pub fn coalesce_highlights(&mut self) {
+        self.highlights.sort_by_key(|(range, _)| range.start);
+        let mut highlights: Vec<(Range<usize>, Highlight)> =
+            Vec::with_capacity(self.highlights.len());
+        for (range, highlight) in self.highlights.drain(..) {
+            if let Some((last_range, last_highlight)) = highlights.last_mut() {
+                if *last_highlight == highlight && range.start <= last_range.end {
+                    last_range.end = last_range.end.max(range.end);
+                    continue;
+                }
+            }
+            highlights.push((range, highlight));
+        }
+        self.highlights = highlights;
+    }
+
+    /// Removes any whitespace from the start of the text, such as the blank line that a
+    /// message starting with an empty paragraph is rendered with. Whitespace within a code
+    /// block, such as the indentation of its first line, is kept. Styling and links are
+    /// shifted along with the text, and those entirely within the whitespace are removed.
+    pub fn trim_start(&mut self) {
+        let mut len = self.text.len() - self.text.trim_start().len();
+        if let Some(code_block_range) = self.code_block_ranges.first() {
+            len = len.min(code_block_range.start);
+        }
+        if len == 0 {
+            return;
+        }
+        let shift = |range: &Range<usize>| {
+            (range.end > len).then(|| range.start.saturating_sub(len)..range.end - len)
+        };
+
+        self.text = self.text[len..].to_string().into();
+        let mut link_ranges = Vec::new();
+        let mut link_urls = Vec::new();
+        for (range, url) in self.link_ranges.iter().zip(self.link_urls.iter()) {
+            if let Some(range) = shift(range) {
+                link_ranges.push(range);
+                link_urls.push(url.clone());
+            }
+        }
+        self.link_ranges = link_ranges;
+        self.link_urls = link_urls.into();
+        self.highlights = mem::take(&mut self.highlights)
+            .into_iter()
+            .filter_map(|(range, highlight)| Some((shift(&range)?, highlight)))
+            .collect();
+        self.checkbox_ranges = mem::take(&mut self.checkbox_ranges)
+            .into_iter()
+            .filter(|(range, _)| range.start >= len)
+            .filter_map(|(range, checked)| Some((shift(&range)?, checked)))
+            .collect();
+        self.code_block_ranges = self.code_block_ranges.iter().filter_map(shift).collect();
+        self.mention_ranges = mem::take(&mut self.mention_ranges)
+            .into_iter()
+            .filter_map(|(range, mention)| Some((shift(&range)?, mention)))
+            .collect();
+        self.headings = mem::take(&mut self.headings)
+            .into_iter()
+            .filter_map(|(level, range)| Some((level, shift(&range)?)))
+            .collect();
+    }
+
+    /// Appends text that was rendered after a placeholder of the given length, which
+    /// stood in for this text so that the two are separated as if they'd been rendered
+    /// together, replacing the placeholder with this text. The callbacks of this text are
+    /// kept, as is its front matter, unless it has none.
+    pub(crate) fn append(&mut self, other: RichText, placeholder_len: usize) {
+        if self.front_matter.is_none() {
+            self.front_matter = other.front_matter;
+        }
+        if other.text.len() <= placeholder_len {
+            return;
+        }
+        let offset = self.text.len() - placeholder_len;
+        let shift = |range: Range<usize>| range.start + offset..range.end + offset;
+
+        let mut text = String::with_capacity(offset + other.text.len());
+        text.push_str(&self.text);
+        text.push_str(&other.text[placeholder_len..]);
+        self.text = text.into();
+        self.highlights.extend(
+            other
+                .highlights
+                .into_iter()
+                .map(|(range, highlight)| (shift(range), highlight)),
+        );
+        self.link_ranges
+            .extend(other.link_ranges.into_iter().map(shift));
+        self.link_urls = self
+            .link_urls
+            .iter()
+            .chain(other.link_urls.iter())
+            .cloned()
+            .collect();
+        self.checkbox_ranges.extend(
+            other
+                .checkbox_ranges
+                .into_iter()
+                .map(|(range, checked)| (shift(range), checked)),
+        );
+        self.code_block_ranges
+            .extend(other.code_block_ranges.into_iter().map(shift));
+        self.mention_ranges.extend(
+            other
+                .mention_ranges
+                .into_iter()
+                .map(|(range, mention)| (shift(range), mention)),
+        );
+        self.headings.extend(
+            other
+                .headings
+                .into_iter()
+                .map(|(level, range)| (level, shift(range))),
+        );
+    }
+
+    /// Returns a copy of this text cut to at most `max_chars` characters, followed by an
+    /// ellipsis. Styling and links that extend past the cut are clipped to it.
+    pub fn truncate(&self, max_chars: usize) -> RichText {
+        let Some((cut, _)) = self.text.char_indices().nth(max_chars) else {
+            return self.clone();
+        };
+        let len = self.text[..cut].trim_end().len();
+        let clip =
+            |range: &Range<usize>| (range.start < len).then(|| range.start..range.end.min(len));
+
+        let mut text = self.text[..len].to_string();
+        text.push('…');
+
+        let mut link_ranges = Vec::new();
+        let mut link_urls = Vec::new();
+        for (range, url) in self.link_ranges.iter().zip(self.link_urls.iter()) {
+            if let Some(range) = clip(range) {
+                link_ranges.push(range);
+                link_urls.push(url.clone());
+            }
+        }
+
+        RichText {
+            text: text.into(),
+            highlights: self
+                .highlights
+                .iter()
+                .filter_map(|(range, highlight)| Some((clip(range)?, highlight.clone())))
+                .collect(),
+            link_ranges,
+            link_urls: link_urls.into(),
+            checkbox_ranges: self
+                .checkbox_ranges
+                .iter()
+                .filter(|(range, _)| range.end <= len)
+                .cloned()
+                .collect(),
+            code_block_ranges: self.code_block_ranges.iter().filter_map(clip).collect(),
+            mention_ranges: self
+                .mention_ranges
+                .iter()
+                .filter_map(|(range, mention)| Some((clip(range)?, *mention)))
+                .collect(),
+            headings: self
+                .headings
+                .iter()
+                .filter_map(|(level, range)| Some((*level, clip(range)?)))
+                .collect(),
+            code_background: self.code_background,
+            front_matter: self.front_matter.clone(),
+            mention_tooltip_fn: self.mention_tooltip_fn.clone(),
+            mention_click_fn: self.mention_click_fn.clone(),
+            link_click_fn: self.link_click_fn.clone(),
+        }
+    }
+
+    /// Sets a function that builds the tooltip shown when hovering over a mention.
+    pub fn set_mention_tooltip_builder(
+        &mut self,
+        f: impl Fn(&MentionMeta, &mut WindowContext) -> Option<AnyView> + 'static,
+    ) {
+        self.mention_tooltip_fn = Some(Arc::new(f));
+    }
+
+    /// Sets a function that is called with a link's URL when it's clicked, instead of
+    /// opening the URL.
+    pub fn set_link_click_handler(&mut self, f: impl Fn(&str, &mut WindowContext) + 'static) {
+        self.link_click_fn = Some(Arc::new(f));
+    }
+
+    /// Sets a function that is called when a mention is clicked.
+    pub fn set_mention_click_handler(
+        &mut self,
+        f: impl Fn(&MentionMeta, &mut WindowContext) + 'static,
+    ) {
+        self.mention_click_fn = Some(Arc::new(f));
+    }
+
+    /// Returns the listener called with the index of the clicked range, in the order that the
+    /// ranges are passed to [`InteractiveText::on_click`]: links, then code blocks, then mentions.
+    fn click_listener(&self) -> impl Fn(usize, &mut WindowContext) + 'static {
+        let text = self.text.clone();
+        let link_urls = self.link_urls.clone();
+        let code_block_ranges = self.code_block_ranges.clone();
+        let line_number_ranges = self
+            .highlights
+            .iter()
+            .filter(|(_, highlight)| *highlight == Highlight::LineNumber)
+            .map(|(range, _)| range.clone())
+            .collect::<Vec<_>>();
+        let mention_ranges = self.mention_ranges.clone();
+        let mention_click_fn = self.mention_click_fn.clone();
+        let link_click_fn = self.link_click_fn.clone();
+        move |ix, cx| {
+            if let Some(url) = link_urls.get(ix) {
+                if let Some(link_click_fn) = link_click_fn.as_ref() {
+                    link_click_fn(url, cx);
+                } else {
+                    cx.open_url(url);
+                }
+            } else if let Some(range) = code_block_ranges.get(ix - link_urls.len()) {
+                cx.write_to_clipboard(ClipboardItem::new(copied_code(
+                    &text,
+                    range.clone(),
+                    &line_number_ranges,
+                )));
+            } else if let Some(mention_click_fn) = mention_click_fn.as_ref() {
+                let (_, mention) = &mention_ranges[ix - link_urls.len() - code_block_ranges.len()];
+                mention_click_fn(mention, cx);
+            }
+        }
+    }
+
     pub fn element(&self, id: ElementId, cx: &mut WindowContext) -> AnyElement {
         let theme = cx.theme();
-        let code_background = theme.colors().surface_background;
+        let code_background = self
+            .code_background
+            .unwrap_or(theme.colors().surface_background);
 
         InteractiveText::new(
             id,
             StyledText::new(self.text.clone()).with_highlights(
                 &cx.text_style(),
-                self.highlights.iter().map(|(range, highlight)| {
-                    (
-                        range.clone(),
-                        match highlight {
-                            Highlight::Code => HighlightStyle {
-                                background_color: Some(code_background),
-                                ..Default::default()
-                            },
-                            Highlight::Id(id) => HighlightStyle {
-                                background_color: Some(code_background),
-                                ..id.style(theme.syntax()).unwrap_or_default()
-                            },
-                            Highlight::Highlight(highlight) => *highlight,
-                            Highlight::Mention => HighlightStyle {
-                                font_weight: Some(FontWeight::BOLD),
-                                ..Default::default()
-                            },
-                            Highlight::SelfMention => HighlightStyle {
-                                font_weight: Some(FontWeight::BOLD),
-                                ..Default::default()
+                color_links(
+                    self.highlights.iter().map(|(range, highlight)| {
+                        (
+                            range.clone(),
+                            match highlight {
+                                Highlight::Code => HighlightStyle {
+                                    background_color: Some(code_background),
+                                    ..Default::default()
+                                },
+                                Highlight::Id(id) => HighlightStyle {
+                                    background_color: Some(code_background),
+                                    ..id.style(theme.syntax()).unwrap_or_default()
+                                },
+                                Highlight::Highlight(highlight) => *highlight,
+                                Highlight::Mention => mention_style(false, theme.status()),
+                                Highlight::SelfMention => mention_style(true, theme.status()),
+                                Highlight::Quote => HighlightStyle {
+                                    color: Some(theme.colors().text_muted),
+                                    ..Default::default()
+                                },
+                                Highlight::Mark(style) => HighlightStyle {
+                                    background_color: Some(theme.status().warning_background),
+                                    ..*style
+                                },
+                                Highlight::LineNumber => HighlightStyle {
+                                    background_color: Some(code_background),
+                                    color: Some(theme.colors().editor_line_number),
+                                    ..Default::default()
+                                },
+                                // Give keys a bottom edge, so that they look like keycaps.
+                                Highlight::Key { style, in_quote } => HighlightStyle {
+                                    color: style
+                                        .color
+                                        .or_else(|| in_quote.then_some(theme.colors().text_muted)),
+                                    background_color: Some(code_background),
+                                    underline: Some(UnderlineStyle {
+                                        thickness: 2.0.into(),
+                                        color: Some(theme.colors().border),
+                                        wavy: false,
+                                    }),
+                                    ..*style
+                                },
+                                Highlight::DiffAdded => HighlightStyle {
+                                    background_color: Some(code_background),
+                                    color: Some(theme.status().created),
+                                    ..Default::default()
+                                },
+                                Highlight::DiffRemoved => HighlightStyle {
+                                    background_color: Some(code_background),
+                                    color: Some(theme.status().deleted),
+                                    ..Default::default()
+                                },
+                                Highlight::DiffHunk => HighlightStyle {
+                                    background_color: Some(code_background),
+                                    color: Some(theme.status().info),
+                                    ..Default::default()
+                                },
+                                Highlight::Alert(kind, style) => HighlightStyle {
+                                    background_color: Some(kind.colors(theme.status()).1),
+                                    ..*style
+                                },
+                                Highlight::AlertTitle(kind) => {
+                                    let (color, background) = kind.colors(theme.status());
+                                    HighlightStyle {
+                                        color: Some(color),
+                                        background_color: Some(background),
+                                        font_weight: Some(FontWeight::BOLD),
+                                        ..Default::default()
+                                    }
+                                }
                             },
-                        },
-                    )
-                }),
+                        )
+                    }),
+                    &self.link_ranges,
+                    theme.colors().text_accent,
+                ),
             ),
         )
-        .on_click(self.link_ranges.clone(), {
-            let link_urls = self.link_urls.clone();
-            move |ix, cx| cx.open_url(&link_urls[ix])
-        })
+        .on_click(
+            self.link_ranges
+                .iter()
+                .chain(&self.code_block_ranges)
+                .chain(self.mention_ranges.iter().map(|(range, _)| range))
+                .cloned()
+                .collect(),
+            self.click_listener(),
+        )
         .tooltip({
             let link_ranges = self.link_ranges.clone();
             let link_urls = self.link_urls.clone();
+            let code_block_ranges = self.code_block_ranges.clone();
+            let mention_ranges = self.mention_ranges.clone();
+            let mention_tooltip_fn = self.mention_tooltip_fn.clone();
             move |idx, cx| {
                 for (ix, range) in link_ranges.iter().enumerate() {
                     if range.contains(&idx) {
                         return Some(LinkPreview::new(&link_urls[ix], cx));
                     }
                 }
+                if code_block_ranges.iter().any(|range| range.contains(&idx)) {
+                    return Some(Tooltip::text("Click to copy", cx));
+                }
+                if let Some(mention_tooltip_fn) = mention_tooltip_fn.as_ref() {
+                    for (range, mention) in &mention_ranges {
+                        if range.contains(&idx) {
+                            return mention_tooltip_fn(mention, cx);
+                        }
+                    }
+                }
                 None
             }
         })
@@ -101,40 +739,352 @@ impl RichText {
     }
 }
 
+/// Renders the given markdown, appending its text to `text` and its highlights, links,
+/// checkboxes, code blocks, mentions, and headings to the given vectors, with ranges
+/// relative to the start of `text`. This is the low-level API that [`RichTextBuilder`]
+/// is built on, for callers that manage their own buffers. Unlike [`render_markdown`],
+/// it doesn't trim trailing whitespace, collapse blank lines, or coalesce highlights.
 pub fn render_markdown_mut(
     block: &str,
-    mut mentions: &[Mention],
+    mentions: &[Mention],
     language_registry: &Arc<LanguageRegistry>,
     language: Option<&Arc<Language>>,
+    options: &RenderMarkdownOptions,
+    text: &mut String,
+    highlights: &mut Vec<(Range<usize>, Highlight)>,
+    link_ranges: &mut Vec<Range<usize>>,
+    link_urls: &mut Vec<String>,
+    checkbox_ranges: &mut Vec<(Range<usize>, bool)>,
+    code_block_ranges: &mut Vec<Range<usize>>,
+    mention_ranges: &mut Vec<(Range<usize>, MentionMeta)>,
+    headings: &mut Vec<(HeadingLevel, Range<usize>)>,
+) {
+    render_markdown_mut_internal(
+        block,
+        mentions,
+        Some(language_registry),
+        language,
+        options,
+        &HashMap::default(),
+        text,
+        highlights,
+        link_ranges,
+        link_urls,
+        checkbox_ranges,
+        code_block_ranges,
+        mention_ranges,
+        headings,
+    )
+}
+
+/// Renders the markdown, using `fenced_languages` for any fenced code blocks whose
+/// language has already been resolved, and falling back to the languages that are
+/// currently loaded in the registry otherwise. Without a registry, code blocks aren't
+/// syntax highlighted, and are rendered as a single `Highlight::Code` span instead.
+#[allow(clippy::too_many_arguments)]
+fn render_markdown_mut_internal(
+    block: &str,
+    mentions: &[Mention],
+    language_registry: Option<&Arc<LanguageRegistry>>,
+    language: Option<&Arc<Language>>,
+    options: &RenderMarkdownOptions,
+    fenced_languages: &HashMap<String, Arc<Language>>,
     text: &mut String,
     highlights: &mut Vec<(Range<usize>, Highlight)>,
     link_ranges: &mut Vec<Range<usize>>,
     link_urls: &mut Vec<String>,
+    checkbox_ranges: &mut Vec<(Range<usize>, bool)>,
+    code_block_ranges: &mut Vec<Range<usize>>,
+    mention_ranges: &mut Vec<(Range<usize>, MentionMeta)>,
+    headings: &mut Vec<(HeadingLevel, Range<usize>)>,
 ) {
     use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
 
-    let mut bold_depth = 0;
-    let mut italic_depth = 0;
+    let remap_mentions = |mentions: &[Mention], replacements: &[replacements::Replacement]| {
+        mentions
+            .iter()
+            .map(|mention| Mention {
+                range: replacements::map_offset(mention.range.start, replacements)
+                    ..replacements::map_offset(mention.range.end, replacements),
+                is_self_mention: mention.is_self_mention,
+                user_id: mention.user_id,
+            })
+            .collect::<Vec<_>>()
+    };
+    // Math is protected first, so that the other passes leave its code spans untouched.
+    // It's rewritten as code spans of the same length, so mentions don't need remapping.
+    let protected_block;
+    let (block, mut math_ranges) = if options.render_math_as_code {
+        let (block, math_ranges) = math::protect_math(block);
+        protected_block = block;
+        (protected_block.as_str(), math_ranges)
+    } else {
+        (block, Vec::new())
+    };
+    // Each math span starts with a backtick, which is never replaced, so mapping the
+    // offset after it also accounts for any text inserted right before the span.
+    let remap_math_ranges =
+        |math_ranges: &mut Vec<Range<usize>>, replacements: &[replacements::Replacement]| {
+            for range in math_ranges {
+                *range = replacements::map_offset(range.start + 1, replacements) - 1
+                    ..replacements::map_offset(range.end, replacements);
+            }
+        };
+    let definitions_block;
+    let definitions_mentions;
+    let (block, mentions) = if options.render_definition_lists {
+        let (block, replacements) = definitions::expand_definition_lists(block);
+        definitions_block = block;
+        definitions_mentions = remap_mentions(mentions, &replacements);
+        remap_math_ranges(&mut math_ranges, &replacements);
+        (definitions_block.as_str(), definitions_mentions.as_slice())
+    } else {
+        (block, mentions)
+    };
+    let expanded_block;
+    let expanded_mentions;
+    let (block, mentions) = if options.expand_emoji_shortcodes {
+        let (block, replacements) = emoji::expand_shortcodes(block);
+        expanded_block = block;
+        expanded_mentions = remap_mentions(mentions, &replacements);
+        remap_math_ranges(&mut math_ranges, &replacements);
+        (expanded_block.as_str(), expanded_mentions.as_slice())
+    } else {
+        (block, mentions)
+    };
+    let scripts_block;
+    let scripts_mentions;
+    let (block, mut mentions) = if options.render_sub_superscripts {
+        let (block, replacements) = scripts::expand_scripts(block);
+        scripts_block = block;
+        scripts_mentions = remap_mentions(mentions, &replacements);
+        remap_math_ranges(&mut math_ranges, &replacements);
+        (scripts_block.as_str(), scripts_mentions.as_slice())
+    } else {
+        (block, mentions)
+    };
+
+    let mut bold_depth: usize = 0;
+    let mut heading_level = None;
+    let mut heading_start = 0;
+    let mut italic_depth: usize = 0;
+    let mut strikethrough_depth: usize = 0;
+    let mut quote_depth: usize = 0;
     let mut link_url = None;
+    // Where the label of the link being rendered starts, so that the label gets a single
+    // link range even if it mixes text with inline code.
+    let mut link_start = 0;
     let mut current_language = None;
     let mut list_stack = Vec::new();
+    let mut bullet_range = None;
+    // The indentation of the content of each list item being rendered, in characters.
+    let mut item_indents = Vec::new();
+    let mut table: Option<Table> = None;
+    let mut in_code_block = false;
+    // Whether the code block being rendered is a fenced `diff` block with no grammar to
+    // highlight it, in which case its lines are colored by whether they're added or removed.
+    let mut in_diff_block = false;
+    let mut code_block_start = None;
+    // The indentation of the first line of the code block being rendered, in characters.
+    let mut code_block_indent = 0;
+    // The number of the next line of the code block being rendered, and the number of
+    // digits its line numbers are padded to.
+    let mut code_block_line = 1;
+    let mut code_block_line_number_width = 0;
+    let mut in_html_block = false;
+    let mut html_code_depth: usize = 0;
+    let mut kbd_depth: usize = 0;
+    // The URL, title, and alt text of the image being rendered, if any.
+    let mut image: Option<(String, String, String)> = None;
+    let mut is_footnote_definition_start = false;
+    // The kind of the alert being rendered, if any, along with the source range of its
+    // `[!KIND]` marker, the depth of its quote, and where its highlights start.
+    let mut alert: Option<(AlertKind, Range<usize>, usize, usize)> = None;
+    let mut is_after_alert_title = false;
+    let link_underline = options.underline_links.then(|| UnderlineStyle {
+        thickness: 1.0.into(),
+        ..Default::default()
+    });
+
+    let (events, footnote_numbers) =
+        move_footnote_definitions_to_end(Parser::new_ext(block, Options::all()).into_offset_iter());
+    let mut code_block_line_counts = if options.code_block_line_numbers {
+        count_code_block_lines(&events).into_iter()
+    } else {
+        Vec::new().into_iter()
+    };
+    for (event, source_range) in events {
+        // An alert's marker is rendered as its title, which is always on its own line.
+        if let Some((kind, marker_range, _, _)) = &alert {
+            if matches!(event, Event::Text(_)) && marker_range.contains(&source_range.start) {
+                if source_range.start == marker_range.start {
+                    let start = text.len();
+                    text.push_str(&format!("{} {}", kind.icon(), kind.title()));
+                    highlights.push((start..text.len(), Highlight::AlertTitle(*kind)));
+                }
+                is_after_alert_title = true;
+                continue;
+            }
+        }
+        let event = match event {
+            Event::SoftBreak if mem::take(&mut is_after_alert_title) => Event::HardBreak,
+            event => {
+                is_after_alert_title = false;
+                event
+            }
+        };
+        let event = match event {
+            Event::Html(html) => {
+                // Block-level HTML is emitted one line at a time, with each line keeping
+                // its trailing newline, whereas inline HTML is emitted one tag at a time.
+                let is_block = html.ends_with('\n');
+                let was_in_html_block = mem::replace(&mut in_html_block, is_block);
+                if options.render_kbd_tags && !is_block && options.html != HtmlRendering::Literal {
+                    match parse_safe_html_tag(html.as_ref()) {
+                        Some((SafeHtmlTag::Kbd, true)) => {
+                            kbd_depth += 1;
+                            continue;
+                        }
+                        Some((SafeHtmlTag::Kbd, false)) => {
+                            kbd_depth = kbd_depth.saturating_sub(1);
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+                match options.html {
+                    HtmlRendering::Strip => continue,
+                    HtmlRendering::Literal => {
+                        if is_block {
+                            if !was_in_html_block {
+                                new_paragraph(text, &mut list_stack);
+                            }
+                            push_quote_prefix(text, highlights, quote_depth);
+                        }
+                        Event::Text(html)
+                    }
+                    HtmlRendering::SafeTags => {
+                        if !is_block {
+                            match parse_safe_html_tag(html.as_ref()) {
+                                Some((SafeHtmlTag::Bold, true)) => bold_depth += 1,
+                                Some((SafeHtmlTag::Bold, false)) => {
+                                    bold_depth = bold_depth.saturating_sub(1)
+                                }
+                                Some((SafeHtmlTag::Italic, true)) => italic_depth += 1,
+                                Some((SafeHtmlTag::Italic, false)) => {
+                                    italic_depth = italic_depth.saturating_sub(1)
+                                }
+                                Some((SafeHtmlTag::Code, true)) => html_code_depth += 1,
+                                Some((SafeHtmlTag::Code, false)) => {
+                                    html_code_depth = html_code_depth.saturating_sub(1)
+                                }
+                                Some((SafeHtmlTag::Kbd, _)) | None => {}
+                            }
+                        }
+                        continue;
+                    }
+                }
+            }
+            Event::Text(t) if kbd_depth > 0 && !in_code_block => {
+                in_html_block = false;
+                let start = text.len();
+                text.push_str(t.as_ref());
+                highlights.push((
+                    start..text.len(),
+                    Highlight::Key {
+                        style: inline_style(
+                            bold_depth,
+                            italic_depth,
+                            strikethrough_depth,
+                            heading_level,
+                        ),
+                        in_quote: quote_depth > 0,
+                    },
+                ));
+                continue;
+            }
+            Event::Text(t) if html_code_depth > 0 && !in_code_block => {
+                in_html_block = false;
+                Event::Code(t)
+            }
+            // Inline tags can't span blocks, so one that isn't closed ends with its block.
+            event @ Event::End(Tag::Paragraph | Tag::Heading(..) | Tag::TableCell | Tag::Item) => {
+                in_html_block = false;
+                kbd_depth = 0;
+                html_code_depth = 0;
+                event
+            }
+            event => {
+                in_html_block = false;
+                event
+            }
+        };
+
+        // Within lists and quotes, code blocks are emitted one line at a time with the
+        // container's indentation stripped, so indent each line to match the first one.
+        if in_code_block
+            && matches!(event, Event::Text(_))
+            && code_block_start.map_or(false, |start| text.len() > start)
+            && text.ends_with('\n')
+        {
+            push_quote_prefix(text, highlights, quote_depth);
+            text.extend(iter::repeat(' ').take(code_block_indent));
+        }
 
-    let options = Options::all();
-    for (event, source_range) in Parser::new_ext(block, options).into_offset_iter() {
         let prev_len = text.len();
         match event {
+            Event::Text(t) | Event::Code(t) if image.is_some() => {
+                if let Some((_, _, alt)) = image.as_mut() {
+                    alt.push_str(t.as_ref());
+                }
+            }
             Event::Text(t) => {
                 if let Some(language) = &current_language {
-                    render_code(text, highlights, t.as_ref(), language);
+                    render_code(
+                        text,
+                        highlights,
+                        t.as_ref(),
+                        language,
+                        options.max_highlighted_code_len,
+                        false,
+                    );
+                } else if in_diff_block {
+                    render_diff(text, highlights, t.as_ref());
+                } else if in_code_block && language_registry.is_none() {
+                    text.push_str(t.as_ref());
+                    let end = prev_len + t.trim_end_matches('\n').len();
+                    highlights.push((prev_len..end, Highlight::Code));
                 } else {
+                    let highlights_start = highlights.len().saturating_sub(1);
+                    let marks = if options.render_marks && !in_code_block {
+                        find_marks(t.as_ref())
+                    } else {
+                        Vec::new()
+                    };
+                    let t = if marks.is_empty() {
+                        t
+                    } else {
+                        strip_marks(t.as_ref(), &marks).into()
+                    };
+
+                    let explicit_mentions_start = mention_ranges.len();
+                    let mut explicit_mentions = Vec::new();
                     while let Some(mention) = mentions.first() {
                         if !source_range.contains_inclusive(&mention.range) {
                             break;
                         }
                         mentions = &mentions[1..];
-                        let range = (prev_len + mention.range.start - source_range.start)
-                            ..(prev_len + mention.range.end - source_range.start);
-                        highlights.push((
+                        let range = (prev_len
+                            + map_offset_past_marks(
+                                mention.range.start - source_range.start,
+                                &marks,
+                            ))
+                            ..(prev_len
+                                + map_offset_past_marks(
+                                    mention.range.end - source_range.start,
+                                    &marks,
+                                ));
+                        explicit_mentions.push((
                             range.clone(),
                             if mention.is_self_mention {
                                 Highlight::SelfMention
@@ -142,115 +1092,473 @@ pub fn render_markdown_mut(
                                 Highlight::Mention
                             },
                         ));
+                        mention_ranges.push((
+                            range,
+                            MentionMeta {
+                                is_self_mention: mention.is_self_mention,
+                                user_id: mention.user_id,
+                            },
+                        ));
                     }
 
                     text.push_str(t.as_ref());
-                    let mut style = HighlightStyle::default();
-                    if bold_depth > 0 {
-                        style.font_weight = Some(FontWeight::BOLD);
-                    }
-                    if italic_depth > 0 {
-                        style.font_style = Some(FontStyle::Italic);
-                    }
-                    if let Some(link_url) = link_url.clone() {
-                        link_ranges.push(prev_len..text.len());
-                        link_urls.push(link_url);
-                        style.underline = Some(UnderlineStyle {
-                            thickness: 1.0.into(),
-                            ..Default::default()
+                    let style =
+                        inline_style(bold_depth, italic_depth, strikethrough_depth, heading_level);
+                    let link_style = HighlightStyle {
+                        underline: link_underline,
+                        ..style
+                    };
+                    if link_url.is_some() {
+                        push_text_highlight_around_mentions(
+                            highlights,
+                            prev_len..text.len(),
+                            link_style,
+                            quote_depth,
+                            &explicit_mentions,
+                        );
+                    } else if in_code_block {
+                        push_text_highlight_around_mentions(
+                            highlights,
+                            prev_len..text.len(),
+                            style,
+                            quote_depth,
+                            &explicit_mentions,
+                        );
+                    } else {
+                        let url_ranges = find_bare_urls(t.as_ref());
+                        let mut matched_mentions = options
+                            .mention_matcher
+                            .as_ref()
+                            .map(|matcher| matcher.matches(t.as_ref()))
+                            .unwrap_or_default();
+                        matched_mentions.retain(|(range, _)| {
+                            let range = prev_len + range.start..prev_len + range.end;
+                            !url_ranges
+                                .iter()
+                                .map(|url_range| {
+                                    prev_len + url_range.start..prev_len + url_range.end
+                                })
+                                .chain(
+                                    mention_ranges[explicit_mentions_start..]
+                                        .iter()
+                                        .map(|(range, _)| range.clone()),
+                                )
+                                .any(|other| other.start < range.end && range.start < other.end)
                         });
-                    }
 
-                    if style != HighlightStyle::default() {
-                        let mut new_highlight = true;
-                        if let Some((last_range, last_style)) = highlights.last_mut() {
-                            if last_range.end == prev_len
-                                && last_style == &Highlight::Highlight(style)
-                            {
-                                last_range.end = text.len();
-                                new_highlight = false;
+                        let mut file_locations = options
+                            .file_location_matcher
+                            .as_ref()
+                            .map(|matcher| matcher.matches(t.as_ref()))
+                            .unwrap_or_default();
+                        file_locations.retain(|(range, _)| {
+                            let range = prev_len + range.start..prev_len + range.end;
+                            !url_ranges
+                                .iter()
+                                .chain(matched_mentions.iter().map(|(range, _)| range))
+                                .map(|other| prev_len + other.start..prev_len + other.end)
+                                .chain(
+                                    mention_ranges[explicit_mentions_start..]
+                                        .iter()
+                                        .map(|(range, _)| range.clone()),
+                                )
+                                .any(|other| other.start < range.end && range.start < other.end)
+                        });
+
+                        let mut links = url_ranges
+                            .into_iter()
+                            .map(|range| (range, TextLink::Url))
+                            .chain(
+                                matched_mentions
+                                    .into_iter()
+                                    .map(|(range, url)| (range, TextLink::Mention(url))),
+                            )
+                            .chain(
+                                file_locations
+                                    .into_iter()
+                                    .map(|(range, url)| (range, TextLink::FileLocation(url))),
+                            )
+                            .collect::<Vec<_>>();
+                        links.sort_by_key(|(range, _)| range.start);
+
+                        let mut offset = prev_len;
+                        for (range, link) in links {
+                            let range = prev_len + range.start..prev_len + range.end;
+                            push_text_highlight_around_mentions(
+                                highlights,
+                                offset..range.start,
+                                style,
+                                quote_depth,
+                                &explicit_mentions,
+                            );
+                            if let TextLink::Mention(mention_url) = link {
+                                // Within styled text, such as a heading, the mention keeps
+                                // that style and is made bold like a plain mention.
+                                let highlight = if style == HighlightStyle::default() {
+                                    Highlight::Mention
+                                } else {
+                                    Highlight::Highlight(HighlightStyle {
+                                        font_weight: style
+                                            .font_weight
+                                            .filter(|weight| weight.0 > FontWeight::BOLD.0)
+                                            .or(Some(FontWeight::BOLD)),
+                                        ..style
+                                    })
+                                };
+                                highlights.push((range.clone(), highlight));
+                                link_urls.push(mention_url);
+                            } else {
+                                push_text_highlight_around_mentions(
+                                    highlights,
+                                    range.clone(),
+                                    link_style,
+                                    quote_depth,
+                                    &explicit_mentions,
+                                );
+                                link_urls.push(match link {
+                                    TextLink::FileLocation(url) => url,
+                                    _ => text[range.clone()].to_string(),
+                                });
                             }
+                            offset = range.end;
+                            link_ranges.push(range);
                         }
-                        if new_highlight {
-                            highlights.push((prev_len..text.len(), Highlight::Highlight(style)));
-                        }
+                        push_text_highlight_around_mentions(
+                            highlights,
+                            offset..text.len(),
+                            style,
+                            quote_depth,
+                            &explicit_mentions,
+                        );
                     }
+
+                    if !marks.is_empty() {
+                        let mark_ranges = marks
+                            .iter()
+                            .map(|mark| {
+                                prev_len + map_offset_past_marks(mark.start, &marks)
+                                    ..prev_len + map_offset_past_marks(mark.end, &marks)
+                            })
+                            .collect::<Vec<_>>();
+                        apply_marks(highlights, highlights_start, &mark_ranges);
+                    }
+                }
+
+                if in_code_block && code_block_line_number_width > 0 {
+                    code_block_line += push_line_numbers(
+                        text,
+                        highlights,
+                        prev_len,
+                        code_block_line,
+                        code_block_line_number_width,
+                    );
                 }
             }
-            Event::Code(t) => {
+            Event::Code(t)
+                if math_ranges
+                    .binary_search_by_key(&source_range.start, |range| range.start)
+                    .is_ok() =>
+            {
                 text.push_str(t.as_ref());
+                highlights.push((prev_len..text.len(), Highlight::Code));
+            }
+            Event::Code(t) => {
+                match language {
+                    Some(language) if options.highlight_inline_code && link_url.is_none() => {
+                        render_code(
+                            text,
+                            highlights,
+                            t.as_ref(),
+                            language,
+                            options.max_highlighted_code_len,
+                            false,
+                        )
+                    }
+                    _ => text.push_str(t.as_ref()),
+                }
                 if link_url.is_some() {
-                    highlights.push((
+                    push_text_highlight(
+                        highlights,
                         prev_len..text.len(),
-                        Highlight::Highlight(HighlightStyle {
-                            underline: Some(UnderlineStyle {
-                                thickness: 1.0.into(),
-                                ..Default::default()
-                            }),
+                        HighlightStyle {
+                            underline: link_underline,
                             ..Default::default()
-                        }),
-                    ));
-                }
-                if let Some(link_url) = link_url.clone() {
-                    link_ranges.push(prev_len..text.len());
-                    link_urls.push(link_url);
+                        },
+                        quote_depth,
+                    );
                 }
             }
             Event::Start(tag) => match tag {
-                Tag::Paragraph => new_paragraph(text, &mut list_stack),
-                Tag::Heading(_, _, _) => {
+                Tag::Paragraph => {
+                    // A footnote definition's first paragraph goes on the same line as its marker.
+                    if !mem::take(&mut is_footnote_definition_start) {
+                        new_paragraph(text, &mut list_stack);
+                        push_quote_prefix(text, highlights, quote_depth);
+                    }
+                }
+                Tag::FootnoteDefinition(label) => {
+                    new_paragraph(text, &mut list_stack);
+                    if let Some(number) = footnote_numbers.get(label.as_ref()) {
+                        text.push_str(&format!("[{}] ", number));
+                    }
+                    is_footnote_definition_start = true;
+                }
+                Tag::Heading(level, _, _) => {
+                    new_paragraph(text, &mut list_stack);
+                    push_quote_prefix(text, highlights, quote_depth);
+                    heading_level = Some(level);
+                    heading_start = text.len();
+                }
+                Tag::BlockQuote => {
+                    quote_depth += 1;
+                    if alert.is_none() {
+                        alert = parse_alert_marker(&block[source_range.clone()]).map(
+                            |(kind, marker_range)| {
+                                (
+                                    kind,
+                                    source_range.start + marker_range.start
+                                        ..source_range.start + marker_range.end,
+                                    quote_depth,
+                                    highlights.len(),
+                                )
+                            },
+                        );
+                    }
+                }
+                Tag::Table(alignments) => {
                     new_paragraph(text, &mut list_stack);
+                    table = Some(Table {
+                        alignments,
+                        start: text.len(),
+                        rows: Vec::new(),
+                    });
+                }
+                Tag::TableHead => {
                     bold_depth += 1;
+                    if let Some(table) = table.as_mut() {
+                        table.rows.push(Vec::new());
+                    }
+                }
+                Tag::TableRow => {
+                    if let Some(table) = table.as_mut() {
+                        table.rows.push(Vec::new());
+                    }
+                }
+                Tag::TableCell => {
+                    if let Some(row) = table.as_mut().and_then(|table| table.rows.last_mut()) {
+                        row.push(text.len()..text.len());
+                    }
                 }
                 Tag::CodeBlock(kind) => {
                     new_paragraph(text, &mut list_stack);
-                    current_language = if let CodeBlockKind::Fenced(language) = kind {
-                        language_registry
-                            .language_for_name(language.as_ref())
-                            .now_or_never()
-                            .and_then(Result::ok)
+                    in_code_block = true;
+                    code_block_indent = text.len() - text.rfind('\n').map_or(0, |ix| ix + 1);
+                    push_quote_prefix(text, highlights, quote_depth);
+                    code_block_start = Some(text.len());
+                    current_language = if let CodeBlockKind::Fenced(language) = &kind {
+                        fenced_languages
+                            .get(language.as_ref())
+                            .cloned()
+                            .or_else(|| {
+                                language_registry?
+                                    .language_for_name(language.as_ref())
+                                    .now_or_never()
+                                    .and_then(Result::ok)
+                            })
                     } else {
                         language.cloned()
-                    }
+                    };
+                    in_diff_block = current_language.is_none()
+                        && matches!(&kind, CodeBlockKind::Fenced(info) if info.as_ref() == "diff");
+                    code_block_line = 1;
+                    code_block_line_number_width =
+                        code_block_line_counts.next().map_or(0, line_number_width);
                 }
                 Tag::Emphasis => italic_depth += 1,
                 Tag::Strong => bold_depth += 1,
-                Tag::Link(_, url, _) => link_url = Some(url.to_string()),
+                Tag::Strikethrough => strikethrough_depth += 1,
+                Tag::Link(_, url, _) => {
+                    link_url = Some(url.to_string());
+                    link_start = text.len();
+                }
+                Tag::Image(_, url, title) => {
+                    image = Some((url.to_string(), title.to_string(), String::new()))
+                }
                 Tag::List(number) => {
+                    let number = if options.preserve_list_start {
+                        number
+                    } else {
+                        number.map(|_| 1)
+                    };
                     list_stack.push((number, false));
                 }
                 Tag::Item => {
-                    let len = list_stack.len();
-                    if let Some((list_number, has_content)) = list_stack.last_mut() {
-                        *has_content = false;
+                    if !list_stack.is_empty() {
                         if !text.is_empty() && !text.ends_with('\n') {
                             text.push('\n');
                         }
-                        for _ in 0..len - 1 {
-                            text.push_str("  ");
-                        }
-                        if let Some(number) = list_number {
-                            text.push_str(&format!("{}. ", number));
-                            *number += 1;
-                            *has_content = false;
-                        } else {
-                            text.push_str("- ");
-                        }
+                        push_quote_prefix(text, highlights, quote_depth);
+                        let prefix_start = text.len();
+                        bullet_range =
+                            push_list_item_prefix(text, &mut list_stack, &options.list_bullets);
+                        item_indents.push(text[prefix_start..].chars().count());
                     }
                 }
-                _ => {}
             },
             Event::End(tag) => match tag {
-                Tag::Heading(_, _, _) => bold_depth -= 1,
-                Tag::CodeBlock(_) => current_language = None,
-                Tag::Emphasis => italic_depth -= 1,
-                Tag::Strong => bold_depth -= 1,
-                Tag::Link(_, _, _) => link_url = None,
+                Tag::Heading(level, _, _) => {
+                    heading_level = None;
+                    headings.push((level, heading_start..text.len()));
+                }
+                Tag::BlockQuote => {
+                    if let Some(&(kind, _, depth, highlights_start)) = alert.as_ref() {
+                        if depth == quote_depth {
+                            alert = None;
+                            for (_, highlight) in &mut highlights[highlights_start..] {
+                                match highlight {
+                                    Highlight::Quote => {
+                                        *highlight =
+                                            Highlight::Alert(kind, HighlightStyle::default())
+                                    }
+                                    Highlight::Highlight(style) => {
+                                        *highlight = Highlight::Alert(kind, *style)
+                                    }
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
+                    quote_depth = quote_depth.saturating_sub(1);
+                }
+                Tag::Table(_) => {
+                    if let Some(table) = table.take() {
+                        layout_table(table, text, highlights, link_ranges, mention_ranges);
+                    }
+                }
+                Tag::TableHead => bold_depth = bold_depth.saturating_sub(1),
+                Tag::TableCell => {
+                    if let Some(cell) = table
+                        .as_mut()
+                        .and_then(|table| table.rows.last_mut())
+                        .and_then(|row| row.last_mut())
+                    {
+                        cell.end = text.len();
+                    }
+                }
+                Tag::CodeBlock(_) => {
+                    current_language = None;
+                    in_code_block = false;
+                    in_diff_block = false;
+                    if let Some(start) = code_block_start.take() {
+                        if let Some(width) = options.code_wrap_width {
+                            insert_break_opportunities(
+                                text,
+                                start,
+                                width,
+                                highlights,
+                                link_ranges,
+                                checkbox_ranges,
+                                code_block_ranges,
+                                mention_ranges,
+                                headings,
+                            );
+                        }
+                        let end = start.max(text.trim_end_matches('\n').len());
+                        code_block_ranges.push(start..end);
+                    }
+                }
+                // Saturate, so that an unbalanced end tag can't affect the rest of the text.
+                Tag::Emphasis => italic_depth = italic_depth.saturating_sub(1),
+                Tag::Strong => bold_depth = bold_depth.saturating_sub(1),
+                Tag::Strikethrough => strikethrough_depth = strikethrough_depth.saturating_sub(1),
+                Tag::Link(_, _, _) => {
+                    if let Some(url) = link_url.take() {
+                        if link_start < text.len() {
+                            link_ranges.push(link_start..text.len());
+                            link_urls.push(url);
+                        }
+                    }
+                }
+                Tag::Image(_, _, _) => {
+                    // Images can't be displayed, so render them as a link to the image.
+                    if let Some((url, title, alt)) = image.take() {
+                        let label = [alt.trim(), title.trim()]
+                            .into_iter()
+                            .find(|label| !label.is_empty())
+                            .unwrap_or(&url);
+                        text.push_str(label);
+                        // An image inside a link is part of the link's label.
+                        if link_url.is_none() {
+                            link_ranges.push(prev_len..text.len());
+                            link_urls.push(url.clone());
+                        }
+                        push_text_highlight(
+                            highlights,
+                            prev_len..text.len(),
+                            HighlightStyle {
+                                underline: link_underline,
+                                ..Default::default()
+                            },
+                            quote_depth,
+                        );
+                    }
+                }
                 Tag::List(_) => drop(list_stack.pop()),
+                Tag::Item => drop(item_indents.pop()),
                 _ => {}
             },
-            Event::HardBreak => text.push('\n'),
-            Event::SoftBreak => text.push('\n'),
+            Event::Rule => {
+                new_paragraph(text, &mut list_stack);
+                push_quote_prefix(text, highlights, quote_depth);
+                text.extend(iter::repeat('─').take(RULE_WIDTH));
+            }
+            Event::FootnoteReference(label) => {
+                if let Some(number) = footnote_numbers.get(label.as_ref()) {
+                    text.push_str(&format!("[{}]", number));
+                }
+            }
+            Event::TaskListMarker(checked) => {
+                // Replace the bullet that was just pushed for this item with the checkbox,
+                // so that the item doesn't end up with both.
+                let start = match bullet_range.take() {
+                    Some(range) if range.end == text.len() => {
+                        text.truncate(range.start);
+                        range.start
+                    }
+                    _ => text.len(),
+                };
+                let checkbox = if checked { "☑" } else { "☐" };
+                text.push_str(checkbox);
+                text.push(' ');
+                checkbox_ranges.push((start..start + checkbox.len(), checked));
+            }
+            // Breaks are styled like the text around them, so that a styled run that spans
+            // several lines gets a single highlight.
+            Event::SoftBreak if options.soft_break_as_space => {
+                if !text.ends_with(char::is_whitespace) {
+                    let break_start = text.len();
+                    text.push(' ');
+                    let style = HighlightStyle {
+                        underline: link_underline.filter(|_| link_url.is_some()),
+                        ..inline_style(bold_depth, italic_depth, strikethrough_depth, heading_level)
+                    };
+                    push_text_highlight(highlights, break_start..text.len(), style, quote_depth);
+                }
+            }
+            Event::HardBreak | Event::SoftBreak => {
+                let break_start = text.len();
+                text.push('\n');
+                let style = HighlightStyle {
+                    underline: link_underline.filter(|_| link_url.is_some()),
+                    ..inline_style(bold_depth, italic_depth, strikethrough_depth, heading_level)
+                };
+                push_text_highlight(highlights, break_start..text.len(), style, quote_depth);
+                push_quote_prefix(text, highlights, quote_depth);
+                // Align continuation lines with the content of the list item they belong to.
+                if let Some(indent) = item_indents.last() {
+                    text.extend(iter::repeat(' ').take(*indent));
+                }
+            }
             _ => {}
         }
     }
@@ -262,75 +1570,3256 @@ pub fn render_markdown(
     language_registry: &Arc<LanguageRegistry>,
     language: Option<&Arc<Language>>,
 ) -> RichText {
-    let mut text = String::new();
-    let mut highlights = Vec::new();
-    let mut link_ranges = Vec::new();
-    let mut link_urls = Vec::new();
-    render_markdown_mut(
-        &block,
+    render_markdown_with_options(
+        block,
         mentions,
         language_registry,
         language,
-        &mut text,
-        &mut highlights,
-        &mut link_ranges,
-        &mut link_urls,
-    );
-    text.truncate(text.trim_end().len());
+        &RenderMarkdownOptions::default(),
+    )
+}
 
-    RichText {
-        text: SharedString::from(text),
-        link_urls: link_urls.into(),
-        link_ranges,
-        highlights,
-    }
+pub fn render_markdown_with_options(
+    block: String,
+    mentions: &[Mention],
+    language_registry: &Arc<LanguageRegistry>,
+    language: Option<&Arc<Language>>,
+    options: &RenderMarkdownOptions,
+) -> RichText {
+    let mut builder = RichTextBuilder::new();
+    builder.push_markdown(&block, mentions, language_registry, language, options);
+    builder.build()
 }
 
-pub fn render_code(
-    text: &mut String,
+/// Like [`render_markdown`], but without syntax highlighting, so that no language
+/// registry is needed. Code blocks are rendered as a single `Highlight::Code` span.
+pub fn render_markdown_plain(block: String, mentions: &[Mention]) -> RichText {
+    let mut builder = RichTextBuilder::new();
+    builder.push_markdown_internal(
+        &block,
+        mentions,
+        None,
+        None,
+        &RenderMarkdownOptions::default(),
+        &HashMap::default(),
+    );
+    builder.build()
+}
+
+/// Like [`render_markdown`], but waits for the languages of any fenced code blocks to
+/// load before rendering, so that they are syntax highlighted even if their grammars
+/// weren't loaded yet.
+pub async fn render_markdown_async(
+    block: String,
+    mentions: &[Mention],
+    language_registry: &Arc<LanguageRegistry>,
+    language: Option<&Arc<Language>>,
+    options: &RenderMarkdownOptions,
+) -> RichText {
+    use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+
+    let mut fenced_languages = HashMap::default();
+    for event in Parser::new_ext(&block, Options::all()) {
+        if let Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(name))) = event {
+            if name.is_empty() || fenced_languages.contains_key(name.as_ref()) {
+                continue;
+            }
+            if let Ok(language) = language_registry.language_for_name(name.as_ref()).await {
+                fenced_languages.insert(name.to_string(), language);
+            }
+        }
+    }
+
+    let mut builder = RichTextBuilder::new();
+    builder.push_markdown_internal(
+        &block,
+        mentions,
+        Some(language_registry),
+        language,
+        options,
+        &fenced_languages,
+    );
+    builder.build()
+}
+
+/// Accumulates several markdown blocks and runs of plain text into a single [`RichText`],
+/// keeping the ranges of each block's highlights, links, and mentions relative to the
+/// combined text.
+#[derive(Clone, Default)]
+pub struct RichTextBuilder {
+    text: String,
+    highlights: Vec<(Range<usize>, Highlight)>,
+    link_ranges: Vec<Range<usize>>,
+    link_urls: Vec<String>,
+    checkbox_ranges: Vec<(Range<usize>, bool)>,
+    code_block_ranges: Vec<Range<usize>>,
+    mention_ranges: Vec<(Range<usize>, MentionMeta)>,
+    headings: Vec<(HeadingLevel, Range<usize>)>,
+    front_matter: Option<HashMap<String, String>>,
+}
+
+impl RichTextBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The length of the text that has been pushed so far.
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Renders the given markdown, starting a new paragraph if any text has already been
+    /// pushed. The ranges of the mentions are relative to the start of `block`.
+    pub fn push_markdown(
+        &mut self,
+        block: &str,
+        mentions: &[Mention],
+        language_registry: &Arc<LanguageRegistry>,
+        language: Option<&Arc<Language>>,
+        options: &RenderMarkdownOptions,
+    ) {
+        self.push_markdown_internal(
+            block,
+            mentions,
+            Some(language_registry),
+            language,
+            options,
+            &HashMap::default(),
+        )
+    }
+
+    fn push_markdown_internal(
+        &mut self,
+        block: &str,
+        mentions: &[Mention],
+        language_registry: Option<&Arc<LanguageRegistry>>,
+        language: Option<&Arc<Language>>,
+        options: &RenderMarkdownOptions,
+        fenced_languages: &HashMap<String, Arc<Language>>,
+    ) {
+        // Front matter is only recognized at the very start of the rendered text.
+        let front_matter = if options.strip_front_matter && self.text.is_empty() {
+            front_matter::parse_front_matter(block)
+        } else {
+            None
+        };
+        let shifted_mentions;
+        let (block, mentions) = match front_matter {
+            Some((front_matter, len)) => {
+                self.front_matter = Some(front_matter);
+                shifted_mentions = mentions
+                    .iter()
+                    .filter(|mention| mention.range.start >= len)
+                    .map(|mention| Mention {
+                        range: mention.range.start - len..mention.range.end - len,
+                        is_self_mention: mention.is_self_mention,
+                        user_id: mention.user_id,
+                    })
+                    .collect::<Vec<_>>();
+                (&block[len..], shifted_mentions.as_slice())
+            }
+            None => (block, mentions),
+        };
+        render_markdown_mut_internal(
+            block,
+            mentions,
+            language_registry,
+            language,
+            options,
+            fenced_languages,
+            &mut self.text,
+            &mut self.highlights,
+            &mut self.link_ranges,
+            &mut self.link_urls,
+            &mut self.checkbox_ranges,
+            &mut self.code_block_ranges,
+            &mut self.mention_ranges,
+            &mut self.headings,
+        );
+    }
+
+    /// Appends the given text as-is, without any styling.
+    pub fn push_plain(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    pub fn build(mut self) -> RichText {
+        self.text.truncate(self.text.trim_end().len());
+        self.collapse_blank_lines();
+
+        let mut rich_text = RichText {
+            text: SharedString::from(self.text),
+            link_urls: self.link_urls.into(),
+            link_ranges: self.link_ranges,
+            highlights: self.highlights,
+            checkbox_ranges: self.checkbox_ranges,
+            code_block_ranges: self.code_block_ranges,
+            mention_ranges: self.mention_ranges,
+            headings: self.headings,
+            code_background: None,
+            front_matter: self.front_matter,
+            mention_tooltip_fn: None,
+            mention_click_fn: None,
+            link_click_fn: None,
+        };
+        rich_text.coalesce_highlights();
+        rich_text
+    }
+}
+
+impl RichTextBuilder {
+    /// Collapses each run of three or more newlines outside of code blocks into two, such
+    /// as those left by empty blocks, so that paragraphs are never separated by more than
+    /// one blank line. Ranges after each collapsed run are shifted to match.
+    fn collapse_blank_lines(&mut self) {
+        let mut removed_ranges = Vec::new();
+        let bytes = self.text.as_bytes();
+        let mut ix = 0;
+        while ix < bytes.len() {
+            if bytes[ix] != b'\n' {
+                ix += 1;
+                continue;
+            }
+            let start = ix;
+            while bytes.get(ix) == Some(&b'\n') {
+                ix += 1;
+            }
+            let in_code_block = self
+                .code_block_ranges
+                .iter()
+                .any(|range| range.start.max(start) < range.end.min(ix));
+            if ix - start > 2 && !in_code_block {
+                removed_ranges.push(start + 2..ix);
+            }
+        }
+        if removed_ranges.is_empty() {
+            return;
+        }
+
+        for range in removed_ranges.iter().rev() {
+            self.text.replace_range(range.clone(), "");
+        }
+        let adjust = |range: &mut Range<usize>| {
+            let adjust_offset = |offset: usize| {
+                offset
+                    - removed_ranges
+                        .iter()
+                        .take_while(|removed| removed.start < offset)
+                        .map(|removed| removed.end.min(offset) - removed.start)
+                        .sum::<usize>()
+            };
+            *range = adjust_offset(range.start)..adjust_offset(range.end);
+        };
+        self.highlights
+            .iter_mut()
+            .for_each(|(range, _)| adjust(range));
+        self.link_ranges.iter_mut().for_each(adjust);
+        self.checkbox_ranges
+            .iter_mut()
+            .for_each(|(range, _)| adjust(range));
+        self.code_block_ranges.iter_mut().for_each(adjust);
+        self.mention_ranges
+            .iter_mut()
+            .for_each(|(range, _)| adjust(range));
+        self.headings
+            .iter_mut()
+            .for_each(|(_, range)| adjust(range));
+    }
+}
+
+/// Renders the given markdown as plain text, without computing any highlights or links,
+/// and without syntax highlighting code blocks. This is useful for previews and search
+/// indexing, where styling isn't needed.
+pub fn markdown_to_plain_text(block: &str) -> String {
+    use pulldown_cmark::{Event, Options, Parser, Tag};
+
+    let mut text = String::new();
+    let mut list_stack = Vec::new();
+    for event in Parser::new_ext(block, Options::all()) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(t.as_ref()),
+            Event::Start(Tag::Paragraph)
+            | Event::Start(Tag::Heading(_, _, _))
+            | Event::Start(Tag::CodeBlock(_))
+            | Event::Start(Tag::Table(_)) => new_paragraph(&mut text, &mut list_stack),
+            Event::Start(Tag::List(number)) => list_stack.push((number, false)),
+            Event::End(Tag::List(_)) => drop(list_stack.pop()),
+            Event::Start(Tag::Item) => {
+                if !list_stack.is_empty() {
+                    if !text.is_empty() && !text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                    push_list_item_prefix(&mut text, &mut list_stack, &["-".into()]);
+                }
+            }
+            Event::Start(Tag::TableRow) | Event::End(Tag::TableHead) => text.push('\n'),
+            Event::End(Tag::TableCell) => text.push(' '),
+            Event::Rule => {
+                new_paragraph(&mut text, &mut list_stack);
+                text.extend(iter::repeat('─').take(RULE_WIDTH));
+            }
+            Event::HardBreak | Event::SoftBreak => text.push('\n'),
+            _ => {}
+        }
+    }
+    text.truncate(text.trim_end().len());
+    text
+}
+
+/// Renders the given code, syntax highlighting it with the given language unless it's
+/// longer than `max_highlighted_len` bytes. If `show_line_numbers` is true, each line is
+/// prefixed with its line number, which doesn't affect the highlighting of the code.
+pub fn render_code(
+    text: &mut String,
     highlights: &mut Vec<(Range<usize>, Highlight)>,
     content: &str,
     language: &Arc<Language>,
+    max_highlighted_len: usize,
+    show_line_numbers: bool,
 ) {
     let prev_len = text.len();
     text.push_str(content);
+    push_code_highlights(highlights, prev_len, content, language, max_highlighted_len);
+    if show_line_numbers {
+        let width = line_number_width(content.split_inclusive('\n').count());
+        push_line_numbers(text, highlights, prev_len, 1, width);
+    }
+}
+
+/// Prefixes each line of the text after `start` with its line number, counting from
+/// `first_line` and right-aligned to `width` digits, and returns the number of lines.
+/// The highlights after `start` are shifted past the line numbers, and split where they
+/// span several lines, so that they still cover the same code.
+fn push_line_numbers(
+    text: &mut String,
+    highlights: &mut Vec<(Range<usize>, Highlight)>,
+    start: usize,
+    first_line: usize,
+    width: usize,
+) -> usize {
+    let content = text.split_off(start);
+    let content_highlights_start = highlights
+        .iter()
+        .position(|(range, _)| range.start >= start)
+        .unwrap_or(highlights.len());
+    let mut content_highlights = highlights
+        .drain(content_highlights_start..)
+        .collect::<Vec<_>>();
+    // A highlight that was extended over the start of the text, such as that of a quote's
+    // prefix, is split so that it doesn't cover the first line number.
+    if let Some((range, highlight)) = highlights.last_mut() {
+        if range.end > start {
+            content_highlights.insert(0, (start..range.end, highlight.clone()));
+            range.end = start;
+        }
+    }
+
+    let mut line_count = 0;
+    let mut line_start = start;
+    for line in content.split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        let prefix_start = text.len();
+        text.push_str(&format!("{:>width$} ", first_line + line_count));
+        highlights.push((prefix_start..text.len(), Highlight::LineNumber));
+        let shift = text.len() - line_start;
+        text.push_str(line);
+        for (range, highlight) in &content_highlights {
+            let range = range.start.max(line_start)..range.end.min(line_end);
+            if !range.is_empty() {
+                highlights.push((range.start + shift..range.end + shift, highlight.clone()));
+            }
+        }
+        line_start = line_end;
+        line_count += 1;
+    }
+    line_count
+}
+
+/// Returns the number of digits needed for the line numbers of the given number of lines.
+fn line_number_width(line_count: usize) -> usize {
+    line_count.max(1).to_string().len()
+}
+
+/// Returns the number of lines in each code block in the given events, in order.
+fn count_code_block_lines(events: &[(pulldown_cmark::Event, Range<usize>)]) -> Vec<usize> {
+    use pulldown_cmark::{Event, Tag};
+
+    let mut line_counts = Vec::new();
+    let mut in_code_block = false;
+    for (event, _) in events {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                line_counts.push(0);
+            }
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Text(t) if in_code_block => {
+                if let Some(line_count) = line_counts.last_mut() {
+                    *line_count += t.split_inclusive('\n').count();
+                }
+            }
+            _ => {}
+        }
+    }
+    line_counts
+}
+
+/// Returns the text of the code block in the given range as it's copied, without the
+/// given line numbers or the zero-width spaces inserted to wrap long lines.
+fn copied_code(text: &str, range: Range<usize>, line_number_ranges: &[Range<usize>]) -> String {
+    let mut copied = String::new();
+    let mut offset = range.start;
+    for line_number_range in line_number_ranges {
+        if line_number_range.start >= offset && line_number_range.end <= range.end {
+            copied.push_str(&text[offset..line_number_range.start]);
+            offset = line_number_range.end;
+        }
+    }
+    copied.push_str(&text[offset..range.end]);
+    copied.replace(ZERO_WIDTH_SPACE, "")
+}
+
+/// Syntax highlights text that has already been rendered, such as the text of a
+/// [`RichText`], without parsing it as markdown again. The highlights are the same as
+/// those of a code block in the named language containing the text, so they can be
+/// recomputed when the theme changes. If the language isn't loaded yet, the text is
+/// highlighted as plain code.
+pub fn compute_highlights(
+    text: &str,
+    language_registry: &Arc<LanguageRegistry>,
+    language: &str,
+) -> Vec<(Range<usize>, Highlight)> {
+    let mut highlights = Vec::new();
+    match language_registry
+        .language_for_name(language)
+        .now_or_never()
+        .and_then(Result::ok)
+    {
+        Some(language) => push_code_highlights(
+            &mut highlights,
+            0,
+            text,
+            &language,
+            RenderMarkdownOptions::default().max_highlighted_code_len,
+        ),
+        None if text.is_empty() => {}
+        None => highlights.push((0..text.len(), Highlight::Code)),
+    }
+    highlights
+}
+
+fn push_code_highlights(
+    highlights: &mut Vec<(Range<usize>, Highlight)>,
+    start: usize,
+    content: &str,
+    language: &Arc<Language>,
+    max_highlighted_len: usize,
+) {
+    if content.len() > max_highlighted_len {
+        highlights.push((start..start + content.len(), Highlight::Code));
+        return;
+    }
+
     let mut offset = 0;
     for (range, highlight_id) in language.highlight_text(&content.into(), 0..content.len()) {
         if range.start > offset {
-            highlights.push((prev_len + offset..prev_len + range.start, Highlight::Code));
+            highlights.push((start + offset..start + range.start, Highlight::Code));
         }
         highlights.push((
-            prev_len + range.start..prev_len + range.end,
+            start + range.start..start + range.end,
             Highlight::Id(highlight_id),
         ));
         offset = range.end;
     }
     if offset < content.len() {
-        highlights.push((prev_len + offset..prev_len + content.len(), Highlight::Code));
+        highlights.push((start + offset..start + content.len(), Highlight::Code));
     }
 }
 
-pub fn new_paragraph(text: &mut String, list_stack: &mut Vec<(Option<u64>, bool)>) {
-    let mut is_subsequent_paragraph_of_list = false;
-    if let Some((_, has_content)) = list_stack.last_mut() {
-        if *has_content {
-            is_subsequent_paragraph_of_list = true;
+/// Appends the lines of a unified diff, highlighting added and removed lines and hunk
+/// headers. File headers (`---` and `+++`) and context lines are rendered as plain code.
+fn render_diff(text: &mut String, highlights: &mut Vec<(Range<usize>, Highlight)>, content: &str) {
+    for line in content.split_inclusive('\n') {
+        let start = text.len();
+        text.push_str(line);
+        let line = line.trim_end_matches('\n');
+        let highlight = if line.starts_with("@@") {
+            Highlight::DiffHunk
+        } else if line.starts_with('+') && !line.starts_with("+++") {
+            Highlight::DiffAdded
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            Highlight::DiffRemoved
         } else {
-            *has_content = true;
+            Highlight::Code
+        };
+        if !line.is_empty() {
+            highlights.push((start..start + line.len(), highlight));
+        }
+    }
+}
+
+/// Inserts a zero-width space after every `width` characters of each run of text without
+/// whitespace after `start`, so that long tokens can wrap. The highlights, links, and other
+/// ranges that contain each inserted space are extended over it, and those after it are
+/// shifted.
+#[allow(clippy::too_many_arguments)]
+fn insert_break_opportunities(
+    text: &mut String,
+    start: usize,
+    width: usize,
+    highlights: &mut [(Range<usize>, Highlight)],
+    link_ranges: &mut [Range<usize>],
+    checkbox_ranges: &mut [(Range<usize>, bool)],
+    code_block_ranges: &mut [Range<usize>],
+    mention_ranges: &mut [(Range<usize>, MentionMeta)],
+    headings: &mut [(HeadingLevel, Range<usize>)],
+) {
+    let width = width.max(1);
+    let mut break_offsets = Vec::new();
+    let mut run_len = 0;
+    for (ix, ch) in text[start..].char_indices() {
+        if ch.is_whitespace() {
+            run_len = 0;
+        } else {
+            if run_len == width {
+                break_offsets.push(start + ix);
+                run_len = 0;
+            }
+            run_len += 1;
+        }
+    }
+
+    for offset in break_offsets.iter().rev() {
+        text.insert(*offset, ZERO_WIDTH_SPACE);
+    }
+    let shift = |offset: usize, is_start: bool| {
+        offset
+            + ZERO_WIDTH_SPACE.len_utf8()
+                * break_offsets
+                    .iter()
+                    .take_while(|&&break_offset| {
+                        break_offset < offset || (is_start && break_offset == offset)
+                    })
+                    .count()
+    };
+    let adjust = |range: &mut Range<usize>| {
+        *range = shift(range.start, true)..shift(range.end, false);
+    };
+    highlights.iter_mut().for_each(|(range, _)| adjust(range));
+    link_ranges.iter_mut().for_each(adjust);
+    checkbox_ranges
+        .iter_mut()
+        .for_each(|(range, _)| adjust(range));
+    code_block_ranges.iter_mut().for_each(adjust);
+    mention_ranges
+        .iter_mut()
+        .for_each(|(range, _)| adjust(range));
+    headings.iter_mut().for_each(|(_, range)| adjust(range));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SafeHtmlTag {
+    Bold,
+    Italic,
+    Code,
+    Kbd,
+}
+
+/// Parses an inline HTML tag such as `<b>` or `</em>`, returning the tag if it is one
+/// that can be safely rendered, along with whether it opens or closes it.
+fn parse_safe_html_tag(html: &str) -> Option<(SafeHtmlTag, bool)> {
+    let tag = html.trim().strip_prefix('<')?.strip_suffix('>')?;
+    let (tag, is_open) = match tag.strip_prefix('/') {
+        Some(tag) => (tag, false),
+        None => (tag, true),
+    };
+    let name = tag
+        .split(|c: char| c.is_whitespace() || c == '/')
+        .next()
+        .unwrap_or_default();
+    let tag = if name.eq_ignore_ascii_case("b") || name.eq_ignore_ascii_case("strong") {
+        SafeHtmlTag::Bold
+    } else if name.eq_ignore_ascii_case("i") || name.eq_ignore_ascii_case("em") {
+        SafeHtmlTag::Italic
+    } else if name.eq_ignore_ascii_case("code") {
+        SafeHtmlTag::Code
+    } else if name.eq_ignore_ascii_case("kbd") {
+        SafeHtmlTag::Kbd
+    } else {
+        return None;
+    };
+    Some((tag, is_open))
+}
+
+/// The style of a mention, which is highlighted with the theme's info background when
+/// it mentions the current user, so that those mentions stand out.
+fn mention_style(is_self_mention: bool, status: &StatusColors) -> HighlightStyle {
+    HighlightStyle {
+        font_weight: Some(FontWeight::BOLD),
+        background_color: is_self_mention.then_some(status.info_background),
+        ..Default::default()
+    }
+}
+
+/// Gives the text within the given link ranges the link color, splitting the highlights
+/// at the links' boundaries. The highlights must be sorted and non-overlapping.
+fn color_links(
+    highlights: impl IntoIterator<Item = (Range<usize>, HighlightStyle)>,
+    link_ranges: &[Range<usize>],
+    link_color: Hsla,
+) -> Vec<(Range<usize>, HighlightStyle)> {
+    let highlights = highlights.into_iter().collect::<Vec<_>>();
+    let mut link_ranges = link_ranges
+        .iter()
+        .filter(|range| !range.is_empty())
+        .cloned()
+        .collect::<Vec<_>>();
+    if link_ranges.is_empty() {
+        return highlights;
+    }
+    link_ranges.sort_unstable_by_key(|range| range.start);
+
+    let mut boundaries = highlights
+        .iter()
+        .map(|(range, _)| range)
+        .chain(&link_ranges)
+        .flat_map(|range| [range.start, range.end])
+        .collect::<Vec<_>>();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut result: Vec<(Range<usize>, HighlightStyle)> = Vec::new();
+    let mut highlight_ix = 0;
+    let mut link_ix = 0;
+    for boundary in boundaries.windows(2) {
+        let range = boundary[0]..boundary[1];
+        while highlights
+            .get(highlight_ix)
+            .map_or(false, |(highlight_range, _)| {
+                highlight_range.end <= range.start
+            })
+        {
+            highlight_ix += 1;
+        }
+        while link_ranges
+            .get(link_ix)
+            .map_or(false, |link_range| link_range.end <= range.start)
+        {
+            link_ix += 1;
+        }
+
+        let style = highlights
+            .get(highlight_ix)
+            .filter(|(highlight_range, _)| highlight_range.start <= range.start)
+            .map(|(_, style)| *style);
+        let is_link = link_ranges
+            .get(link_ix)
+            .map_or(false, |link_range| link_range.start <= range.start);
+        let style = match (style, is_link) {
+            (style, true) => HighlightStyle {
+                color: Some(link_color),
+                ..style.unwrap_or_default()
+            },
+            (Some(style), false) => style,
+            (None, false) => continue,
+        };
+
+        if let Some((last_range, last_style)) = result.last_mut() {
+            if last_range.end == range.start && *last_style == style {
+                last_range.end = range.end;
+                continue;
+            }
+        }
+        result.push((range, style));
+    }
+    result
+}
+
+/// The style of text within the given number of nested emphasis, strong, and
+/// strikethrough spans, and within a heading of the given level, if any.
+fn inline_style(
+    bold_depth: usize,
+    italic_depth: usize,
+    strikethrough_depth: usize,
+    heading_level: Option<HeadingLevel>,
+) -> HighlightStyle {
+    let mut style = HighlightStyle::default();
+    if bold_depth > 0 {
+        style.font_weight = Some(FontWeight::BOLD);
+    }
+    if let Some(level) = heading_level {
+        let heading_weight = heading_font_weight(level);
+        if style
+            .font_weight
+            .map_or(true, |weight| weight < heading_weight)
+        {
+            style.font_weight = Some(heading_weight);
+        }
+    }
+    if italic_depth > 0 {
+        style.font_style = Some(FontStyle::Italic);
+    }
+    if strikethrough_depth > 0 {
+        style.strikethrough = Some(StrikethroughStyle {
+            thickness: 1.0.into(),
+            ..Default::default()
+        });
+    }
+    style
+}
+
+/// Pushes a highlight for a run of text with the given style, extending the previous
+/// highlight if it has the same style and ends where this run starts.
+fn push_text_highlight(
+    highlights: &mut Vec<(Range<usize>, Highlight)>,
+    range: Range<usize>,
+    style: HighlightStyle,
+    quote_depth: usize,
+) {
+    if range.is_empty() {
+        return;
+    }
+
+    let highlight = if style != HighlightStyle::default() {
+        Highlight::Highlight(style)
+    } else if quote_depth > 0 {
+        Highlight::Quote
+    } else {
+        return;
+    };
+    if let Some((last_range, last_highlight)) = highlights.last_mut() {
+        if last_range.end == range.start && *last_highlight == highlight {
+            last_range.end = range.end;
             return;
         }
     }
+    highlights.push((range, highlight));
+}
 
-    if !text.is_empty() {
-        if !text.ends_with('\n') {
+/// Pushes highlights for a run of text like [`push_text_highlight`], except that the
+/// parts of the run within the given sorted mention ranges get the mentions' highlights
+/// instead, so that the run's highlights don't overlap the mentions'.
+fn push_text_highlight_around_mentions(
+    highlights: &mut Vec<(Range<usize>, Highlight)>,
+    range: Range<usize>,
+    style: HighlightStyle,
+    quote_depth: usize,
+    mentions: &[(Range<usize>, Highlight)],
+) {
+    let mut offset = range.start;
+    for (mention_range, highlight) in mentions {
+        let mention_range = mention_range.start.max(offset)..mention_range.end.min(range.end);
+        if mention_range.is_empty() {
+            continue;
+        }
+        push_text_highlight(highlights, offset..mention_range.start, style, quote_depth);
+        highlights.push((mention_range.clone(), highlight.clone()));
+        offset = mention_range.end;
+    }
+    push_text_highlight(highlights, offset..range.end, style, quote_depth);
+}
+
+/// Finds the ranges of `==marked==` text, including the `==` around it. Marked text
+/// can't start or end with whitespace, and a `==` that isn't paired is left as is.
+fn find_marks(text: &str) -> Vec<Range<usize>> {
+    let mut marks = Vec::new();
+    let mut search_start = 0;
+    while let Some(start) = text[search_start..].find("==").map(|ix| search_start + ix) {
+        let content_start = start + 2;
+        let Some(content_end) = text[content_start..]
+            .find("==")
+            .map(|ix| content_start + ix)
+        else {
+            break;
+        };
+        let content = &text[content_start..content_end];
+        if content.is_empty()
+            || content.starts_with(|c: char| c.is_whitespace() || c == '=')
+            || content.ends_with(char::is_whitespace)
+        {
+            search_start = content_start;
+        } else {
+            marks.push(start..content_end + 2);
+            search_start = content_end + 2;
+        }
+    }
+    marks
+}
+
+/// Removes the `==` around each of the given marks.
+fn strip_marks(text: &str, marks: &[Range<usize>]) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut offset = 0;
+    for mark in marks {
+        stripped.push_str(&text[offset..mark.start]);
+        stripped.push_str(&text[mark.start + 2..mark.end - 2]);
+        offset = mark.end;
+    }
+    stripped.push_str(&text[offset..]);
+    stripped
+}
+
+/// Maps an offset in text containing the given marks to the corresponding offset once
+/// the `==` around them is removed. Offsets within a `==` are clamped to its start.
+fn map_offset_past_marks(offset: usize, marks: &[Range<usize>]) -> usize {
+    let mut removed = 0;
+    for mark in marks {
+        if offset >= mark.end {
+            removed += 4;
+        } else if offset > mark.end - 2 {
+            removed += 2 + offset - (mark.end - 2);
+        } else if offset >= mark.start + 2 {
+            removed += 2;
+        } else if offset > mark.start {
+            removed += offset - mark.start;
+        }
+    }
+    offset - removed
+}
+
+/// Replaces the highlights from `start` onwards with ones where the text within the given
+/// mark ranges is marked, keeping the style of that text. Mentions and code are left as
+/// they are, since they have their own backgrounds.
+fn apply_marks(
+    highlights: &mut Vec<(Range<usize>, Highlight)>,
+    start: usize,
+    mark_ranges: &[Range<usize>],
+) {
+    let tail = highlights.split_off(start.min(highlights.len()));
+    let mut boundaries = tail
+        .iter()
+        .map(|(range, _)| range)
+        .chain(mark_ranges)
+        .flat_map(|range| [range.start, range.end])
+        .collect::<Vec<_>>();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    for segment in boundaries.windows(2) {
+        let segment = segment[0]..segment[1];
+        let highlight = tail
+            .iter()
+            .find(|(range, _)| range.start <= segment.start && segment.end <= range.end)
+            .map(|(_, highlight)| highlight);
+        let is_marked = mark_ranges
+            .iter()
+            .any(|range| range.start <= segment.start && segment.end <= range.end);
+        let highlight = match (highlight, is_marked) {
+            (Some(Highlight::Highlight(style)), true) => Highlight::Mark(*style),
+            (None | Some(Highlight::Quote), true) => Highlight::Mark(HighlightStyle::default()),
+            (Some(highlight), _) => highlight.clone(),
+            (None, false) => continue,
+        };
+        if let Some((last_range, last_highlight)) = highlights.last_mut() {
+            if last_range.end == segment.start && *last_highlight == highlight {
+                last_range.end = segment.end;
+                continue;
+            }
+        }
+        highlights.push((segment, highlight));
+    }
+}
+
+/// A link found within a run of text.
+enum TextLink {
+    Url,
+    Mention(String),
+    FileLocation(String),
+}
+
+/// Finds references to file locations, such as `src/main.rs:42:5`, in the given text.
+///
+/// To avoid mistaking times such as `10:30` for locations, the path must contain a
+/// path separator or end with a file extension.
+fn find_file_locations(text: &str) -> Vec<(Range<usize>, FileLocation)> {
+    let is_separator = |c: char| {
+        c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '<' | '>' | '"' | '\'' | '`')
+    };
+
+    let mut locations = Vec::new();
+    let mut offset = 0;
+    for word in text.split(is_separator) {
+        let start = offset;
+        offset += word.len()
+            + text[start + word.len()..]
+                .chars()
+                .next()
+                .map_or(0, char::len_utf8);
+
+        let word = word.trim_end_matches(|c| matches!(c, '.' | ',' | ':' | ';' | '!' | '?'));
+        let Some((rest, last)) = word.rsplit_once(':') else {
+            continue;
+        };
+        let Some(last) = parse_location_number(last) else {
+            continue;
+        };
+        let (path, line, column) = match rest.rsplit_once(':') {
+            Some((path, line)) => match parse_location_number(line) {
+                Some(line) => (path, line, Some(last)),
+                None => (rest, last, None),
+            },
+            None => (rest, last, None),
+        };
+        if is_file_path(path) {
+            locations.push((
+                start..start + word.len(),
+                FileLocation {
+                    path: path.to_string(),
+                    line,
+                    column,
+                },
+            ));
+        }
+    }
+    locations
+}
+
+fn parse_location_number(text: &str) -> Option<u32> {
+    if text.is_empty() || !text.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok().filter(|number| *number > 0)
+}
+
+fn is_file_path(path: &str) -> bool {
+    if path.is_empty() || path.contains("://") {
+        return false;
+    }
+    let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    let has_extension = file_name
+        .rsplit_once('.')
+        .map_or(false, |(stem, extension)| {
+            !stem.is_empty()
+                && extension.chars().all(|c| c.is_ascii_alphanumeric())
+                && extension.chars().any(|c| c.is_ascii_alphabetic())
+        });
+    has_extension || (path.contains(['/', '\\']) && !file_name.is_empty())
+}
+
+/// Finds the ranges of bare `http://` and `https://` URLs in the given text. Trailing
+/// punctuation is excluded, since it most likely belongs to the surrounding sentence.
+fn find_bare_urls(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut search_start = 0;
+    while let Some(ix) = text[search_start..].find("http") {
+        let start = search_start + ix;
+        search_start = start + "http".len();
+
+        let candidate = &text[start..];
+        let scheme_len = if candidate.starts_with("https://") {
+            "https://".len()
+        } else if candidate.starts_with("http://") {
+            "http://".len()
+        } else {
+            continue;
+        };
+        if text[..start]
+            .chars()
+            .next_back()
+            .map_or(false, |c| c.is_alphanumeric())
+        {
+            continue;
+        }
+
+        let mut url = &candidate[..candidate
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '`'))
+            .unwrap_or(candidate.len())];
+        while let Some(last) = url.chars().next_back() {
+            let is_trailing_punctuation = matches!(
+                last,
+                '.' | ',' | ':' | ';' | '!' | '?' | '\'' | '’' | '”' | ']'
+            ) || (last == ')' && !url.contains('('));
+            if !is_trailing_punctuation {
+                break;
+            }
+            url = &url[..url.len() - last.len_utf8()];
+        }
+
+        if url.len() > scheme_len {
+            ranges.push(start..start + url.len());
+            search_start = start + url.len();
+        }
+    }
+    ranges
+}
+
+/// Text can't be rendered at different sizes within the same block, so heading levels
+/// are distinguished by their weight instead.
+fn heading_font_weight(level: HeadingLevel) -> FontWeight {
+    match level {
+        HeadingLevel::H1 => FontWeight::BLACK,
+        HeadingLevel::H2 => FontWeight::EXTRA_BOLD,
+        HeadingLevel::H3 => FontWeight::BOLD,
+        HeadingLevel::H4 => FontWeight::SEMIBOLD,
+        HeadingLevel::H5 => FontWeight::MEDIUM,
+        HeadingLevel::H6 => FontWeight::NORMAL,
+    }
+}
+
+struct Table {
+    alignments: Vec<pulldown_cmark::Alignment>,
+    /// The offset in the text at which the table's cells start.
+    start: usize,
+    /// The ranges of each row's cells, as they were originally rendered.
+    rows: Vec<Vec<Range<usize>>>,
+}
+
+/// Lays out the cells of a table, which were rendered one after the other, into
+/// aligned columns, moving each cell's highlights and links along with it.
+fn layout_table(
+    table: Table,
+    text: &mut String,
+    highlights: &mut Vec<(Range<usize>, Highlight)>,
+    link_ranges: &mut Vec<Range<usize>>,
+    mention_ranges: &mut Vec<(Range<usize>, MentionMeta)>,
+) {
+    use pulldown_cmark::Alignment;
+
+    let cell_text = text.split_off(table.start);
+    let cell_content =
+        |cell: &Range<usize>| &cell_text[cell.start - table.start..cell.end - table.start];
+    let highlight_ix = highlights.partition_point(|(range, _)| range.start < table.start);
+    let cell_highlights = highlights.split_off(highlight_ix);
+    let link_ix = link_ranges.partition_point(|range| range.start < table.start);
+    let cell_links = link_ranges.split_off(link_ix);
+    let mention_ix = mention_ranges.partition_point(|(range, _)| range.start < table.start);
+    let cell_mentions = mention_ranges.split_off(mention_ix);
+
+    let column_count = table
+        .rows
+        .iter()
+        .map(Vec::len)
+        .max()
+        .unwrap_or(0)
+        .max(table.alignments.len());
+    let mut column_widths = vec![0; column_count];
+    for row in &table.rows {
+        for (column, cell) in row.iter().enumerate() {
+            column_widths[column] = column_widths[column].max(cell_content(cell).chars().count());
+        }
+    }
+
+    let mut moved_cells = Vec::new();
+    for (row_ix, row) in table.rows.iter().enumerate() {
+        if row_ix > 0 {
             text.push('\n');
         }
-        text.push('\n');
+        for (column, width) in column_widths.iter().enumerate() {
+            if column > 0 {
+                text.push_str(" │ ");
+            }
+            let content = row.get(column).map_or("", |cell| cell_content(cell));
+            let padding = width - content.chars().count();
+            let (left_padding, right_padding) = match table.alignments.get(column) {
+                Some(Alignment::Right) => (padding, 0),
+                Some(Alignment::Center) => (padding / 2, padding - padding / 2),
+                _ => (0, padding),
+            };
+
+            text.extend(iter::repeat(' ').take(left_padding));
+            if let Some(cell) = row.get(column) {
+                moved_cells.push((cell.clone(), text.len()));
+            }
+            text.push_str(content);
+            if column + 1 < column_count {
+                text.extend(iter::repeat(' ').take(right_padding));
+            }
+        }
+
+        // The first row is always the table's header.
+        if row_ix == 0 {
+            text.push('\n');
+            for (column, width) in column_widths.iter().enumerate() {
+                if column > 0 {
+                    text.push_str("─┼─");
+                }
+                text.extend(iter::repeat('─').take(*width));
+            }
+        }
     }
-    for _ in 0..list_stack.len().saturating_sub(1) {
-        text.push_str("  ");
+
+    // Adjacent cells' highlights may have been merged together, so split them at cell boundaries.
+    for (cell, new_start) in &moved_cells {
+        for (range, highlight) in &cell_highlights {
+            let start = range.start.max(cell.start);
+            let end = range.end.min(cell.end);
+            if start < end {
+                highlights.push((
+                    new_start + start - cell.start..new_start + end - cell.start,
+                    highlight.clone(),
+                ));
+            }
+        }
     }
-    if is_subsequent_paragraph_of_list {
-        text.push_str("  ");
+    let move_range = |range: &Range<usize>| {
+        moved_cells
+            .iter()
+            .find(|(cell, _)| cell.start <= range.start && range.end <= cell.end)
+            .map(|(cell, new_start)| {
+                new_start + range.start - cell.start..new_start + range.end - cell.start
+            })
+    };
+    // Link ranges correspond to link URLs by index, so they can't be dropped.
+    link_ranges.extend(
+        cell_links
+            .iter()
+            .map(|range| move_range(range).unwrap_or(0..0)),
+    );
+    mention_ranges.extend(
+        cell_mentions
+            .iter()
+            .filter_map(|(range, mention)| Some((move_range(range)?, *mention))),
+    );
+}
+
+/// Numbers footnotes in the order they're first referenced, and moves their definitions
+/// to the end of the events, after a rule, so they are rendered below the rest of the text.
+/// Definitions that are never referenced are numbered after all of the referenced ones.
+fn move_footnote_definitions_to_end<'a>(
+    events: impl Iterator<Item = (pulldown_cmark::Event<'a>, Range<usize>)>,
+) -> (
+    Vec<(pulldown_cmark::Event<'a>, Range<usize>)>,
+    HashMap<String, usize>,
+) {
+    use pulldown_cmark::{Event, Tag};
+
+    let mut body = Vec::new();
+    let mut definitions: Vec<(String, Vec<_>)> = Vec::new();
+    let mut footnote_numbers = HashMap::default();
+    let mut in_definition = false;
+    for (event, range) in events {
+        match &event {
+            Event::FootnoteReference(label) => {
+                let next_number = footnote_numbers.len() + 1;
+                footnote_numbers
+                    .entry(label.to_string())
+                    .or_insert(next_number);
+            }
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                definitions.push((label.to_string(), Vec::new()));
+                in_definition = true;
+            }
+            _ => {}
+        }
+
+        if in_definition {
+            if let Event::End(Tag::FootnoteDefinition(_)) = &event {
+                in_definition = false;
+            }
+            if let Some((_, definition)) = definitions.last_mut() {
+                definition.push((event, range));
+            }
+        } else {
+            body.push((event, range));
+        }
+    }
+
+    if !definitions.is_empty() {
+        for (label, _) in &definitions {
+            let next_number = footnote_numbers.len() + 1;
+            footnote_numbers.entry(label.clone()).or_insert(next_number);
+        }
+        definitions.sort_by_key(|(label, _)| footnote_numbers[label]);
+
+        body.push((Event::Rule, 0..0));
+        body.extend(
+            definitions
+                .into_iter()
+                .flat_map(|(_, definition)| definition),
+        );
+    }
+
+    (body, footnote_numbers)
+}
+
+/// Pushes the indentation and the bullet or number for a new list item, returning the
+/// range of the bullet if the list is unordered.
+fn push_list_item_prefix(
+    text: &mut String,
+    list_stack: &mut Vec<(Option<u64>, bool)>,
+    bullets: &[String],
+) -> Option<Range<usize>> {
+    let depth = list_stack.len();
+    let (list_number, has_content) = list_stack.last_mut()?;
+    *has_content = false;
+    for _ in 0..depth - 1 {
+        text.push_str("  ");
+    }
+    if let Some(number) = list_number {
+        text.push_str(&format!("{}. ", number));
+        *number += 1;
+        None
+    } else {
+        let start = text.len();
+        let bullet = bullets
+            .get((depth - 1) % bullets.len().max(1))
+            .map_or("-", String::as_str);
+        text.push_str(bullet);
+        text.push(' ');
+        Some(start..text.len())
+    }
+}
+
+/// Parses the `[!KIND]` marker that makes the given quote a GitHub-style alert, which
+/// must be the only text on the quote's first line. Returns the alert's kind, along with
+/// the range of its marker within the quote.
+fn parse_alert_marker(quote: &str) -> Option<(AlertKind, Range<usize>)> {
+    let first_line = quote.lines().next()?;
+    let content = first_line.trim_start().strip_prefix('>')?;
+    let marker = content.trim();
+    let kind = AlertKind::from_marker(marker)?;
+    let marker_start = first_line.len() - content.trim_start().len();
+    Some((kind, marker_start..marker_start + marker.len()))
+}
+
+/// Pushes the prefix used to mark quoted lines, if the current line has no content yet.
+fn push_quote_prefix(
+    text: &mut String,
+    highlights: &mut Vec<(Range<usize>, Highlight)>,
+    quote_depth: usize,
+) {
+    let line_start = text.rfind('\n').map_or(0, |ix| ix + 1);
+    if quote_depth == 0 || !text[line_start..].trim_start().is_empty() {
+        return;
+    }
+
+    let prefix = "▎ ".repeat(quote_depth);
+    text.insert_str(line_start, &prefix);
+    push_text_highlight(
+        highlights,
+        line_start..line_start + prefix.len(),
+        HighlightStyle::default(),
+        quote_depth,
+    );
+}
+
+pub fn new_paragraph(text: &mut String, list_stack: &mut Vec<(Option<u64>, bool)>) {
+    let mut is_subsequent_paragraph_of_list = false;
+    if let Some((_, has_content)) = list_stack.last_mut() {
+        if *has_content {
+            is_subsequent_paragraph_of_list = true;
+        } else {
+            *has_content = true;
+            return;
+        }
+    }
+
+    if !text.is_empty() {
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push('\n');
+    }
+    for _ in 0..list_stack.len().saturating_sub(1) {
+        text.push_str("  ");
+    }
+    if is_subsequent_paragraph_of_list {
+        text.push_str("  ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpui::TestAppContext;
+    use language::{LanguageConfig, LanguageQueries};
+    use pretty_assertions::assert_eq;
+    use std::{cell::RefCell, rc::Rc};
+    use theme::{SyntaxTheme, ThemeRegistry};
+    use util::test::marked_text_ranges;
+
+    #[gpui::test]
+    fn test_render_task_list() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "- [ ] todo\n- [x] done\n  - [x] nested done\n  - [ ] nested todo\n- plain".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) = marked_text_ranges(
+            "«☐» todo\n«☑» done\n  «☑» nested done\n  «☐» nested todo\n- plain",
+            false,
+        );
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.checkbox_ranges,
+            vec![
+                (ranges[0].clone(), false),
+                (ranges[1].clone(), true),
+                (ranges[2].clone(), true),
+                (ranges[3].clone(), false),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_render_nested_block_quotes() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "> outer\n>\n> > inner".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) = marked_text_ranges("«▎ outer»\n\n«▎ ▎ inner»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), Highlight::Quote),
+                (ranges[1].clone(), Highlight::Quote),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_render_alerts() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "> [!NOTE]\n> Read **this**.\n\n> [!warning]\n> Careful\n\n> [!FOO]\n> Plain".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        // Unrecognized kinds are rendered as normal quotes.
+        let (text, ranges) = marked_text_ranges(
+            "«▎ »«ⓘ Note»«\n▎ Read »«this»«.»\n\n«▎ »«⚠ Warning»«\n▎ Careful»\n\n«▎ [!FOO]\n▎ Plain»",
+            false,
+        );
+        assert_eq!(rich_text.text, text);
+        let bold = HighlightStyle {
+            font_weight: Some(FontWeight::BOLD),
+            ..Default::default()
+        };
+        let note = |style| Highlight::Alert(AlertKind::Note, style);
+        let warning = Highlight::Alert(AlertKind::Warning, HighlightStyle::default());
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), note(HighlightStyle::default())),
+                (ranges[1].clone(), Highlight::AlertTitle(AlertKind::Note)),
+                (ranges[2].clone(), note(HighlightStyle::default())),
+                (ranges[3].clone(), note(bold)),
+                (ranges[4].clone(), note(HighlightStyle::default())),
+                (ranges[5].clone(), warning.clone()),
+                (ranges[6].clone(), Highlight::AlertTitle(AlertKind::Warning)),
+                (ranges[7].clone(), warning),
+                (ranges[8].clone(), Highlight::Quote),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_render_table() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "| a | bb |\n|:--|--:|\n| ccc | d |".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) = marked_text_ranges("«a»   │ «bb»\n────┼───\nccc │  d", false);
+        assert_eq!(rich_text.text, text);
+        let bold = Highlight::Highlight(HighlightStyle {
+            font_weight: Some(FontWeight::BOLD),
+            ..Default::default()
+        });
+        assert_eq!(
+            rich_text.highlights,
+            vec![(ranges[0].clone(), bold.clone()), (ranges[1].clone(), bold)]
+        );
+    }
+
+    #[gpui::test]
+    fn test_render_horizontal_rule() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "---\n\nabove\n\n---\n\nbelow\n\n---\n".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let rule = "─".repeat(RULE_WIDTH);
+        assert_eq!(
+            rich_text.text,
+            format!("{rule}\n\nabove\n\n{rule}\n\nbelow\n\n{rule}")
+        );
+    }
+
+    #[gpui::test]
+    fn test_code_block_ranges() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "before\n\n```\nfn main() {}\nlet x = 1;\n```\n\nafter\n\n    indented".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) = marked_text_ranges(
+            "before\n\n«fn main() {}\nlet x = 1;»\n\nafter\n\n«indented»",
+            false,
+        );
+        assert_eq!(rich_text.text, text);
+        assert_eq!(rich_text.code_block_ranges, ranges);
+    }
+
+    #[gpui::test]
+    fn test_indented_code_blocks() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let language = rust_lang();
+        let rich_text = render_markdown(
+            "text\n\n    fn main() {\n        let x = 1;\n\n      y\n    }\n\ntext\n\n- item\n\n      a\n        b\n\n> ```\n> c\n>   d\n> ```".into(),
+            &[],
+            &language_registry,
+            Some(&language),
+        );
+
+        let (text, ranges) = marked_text_ranges(
+            "text\n\n«fn main() {\n    let x = 1;\n\n  y\n}»\n\ntext\n- item\n\n  «a\n    b»\n\n«▎ c\n▎   d»",
+            false,
+        );
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.code_block_ranges,
+            vec![
+                ranges[0].clone(),
+                ranges[1].clone(),
+                ranges[2].start + "▎ ".len()..ranges[2].end
+            ]
+        );
+
+        // Indented code blocks are highlighted with the default language.
+        let let_start = text.find("let").unwrap();
+        assert!(rich_text.highlights.iter().any(|(range, highlight)| {
+            *range == (let_start..let_start + 3) && matches!(highlight, Highlight::Id(_))
+        }));
+    }
+
+    #[gpui::test]
+    fn test_render_heading_levels() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "# one **bold**\n\n###### six **bold**".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) = marked_text_ranges("«one bold»\n\n«six »«bold»", false);
+        assert_eq!(rich_text.text, text);
+        let weight = |weight| {
+            Highlight::Highlight(HighlightStyle {
+                font_weight: Some(weight),
+                ..Default::default()
+            })
+        };
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), weight(FontWeight::BLACK)),
+                (ranges[1].clone(), weight(FontWeight::NORMAL)),
+                (ranges[2].clone(), weight(FontWeight::BOLD)),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_heading_ranges() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "# Intro\n\ntext\n\n## Usage\n\n### Usage\n\n> ## Quoted\n\n## Usage".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) = marked_text_ranges(
+            "«Intro»\n\ntext\n\n«Usage»\n\n«Usage»\n\n▎ «Quoted»\n\n«Usage»",
+            false,
+        );
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.headings,
+            vec![
+                (HeadingLevel::H1, ranges[0].clone()),
+                (HeadingLevel::H2, ranges[1].clone()),
+                (HeadingLevel::H3, ranges[2].clone()),
+                (HeadingLevel::H2, ranges[3].clone()),
+                (HeadingLevel::H2, ranges[4].clone()),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_render_setext_headings() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let setext = render_markdown(
+            "Title **bold**\n=====\n\nSub\n---\n\ntext".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        let atx = render_markdown(
+            "# Title **bold**\n\n## Sub\n\ntext".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        assert_eq!(setext.text, "Title bold\n\nSub\n\ntext");
+        assert_eq!(setext.text, atx.text);
+        assert_eq!(setext.highlights, atx.highlights);
+    }
+
+    #[gpui::test]
+    fn test_markdown_to_plain_text() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let markdown = "**hi** _@abc_, see:\n\n- `one`\n- [two](https://zed.dev)\n  1. three";
+        let plain_text = markdown_to_plain_text(markdown);
+        assert_eq!(plain_text, "hi @abc, see:\n- one\n- two\n  1. three");
+
+        let rich_text = render_markdown(markdown.into(), &[], &language_registry, None);
+        assert_eq!(rich_text.plain_text(), plain_text);
+    }
+
+    #[gpui::test]
+    fn test_autolink_bare_urls() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "see https://zed.dev for details, or **http://x.com**.\n\n`https://not.linked`".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) = marked_text_ranges(
+            "see «https://zed.dev» for details, or «http://x.com».\n\nhttps://not.linked",
+            false,
+        );
+        assert_eq!(rich_text.text, text);
+        assert_eq!(rich_text.link_ranges, ranges);
+        assert_eq!(
+            rich_text.link_urls.as_ref(),
+            &["https://zed.dev".to_string(), "http://x.com".to_string()]
+        );
+        let underline = Some(UnderlineStyle {
+            thickness: 1.0.into(),
+            ..Default::default()
+        });
+        assert_eq!(
+            rich_text.highlights[..2],
+            [
+                (
+                    ranges[0].clone(),
+                    Highlight::Highlight(HighlightStyle {
+                        underline,
+                        ..Default::default()
+                    })
+                ),
+                (
+                    ranges[1].clone(),
+                    Highlight::Highlight(HighlightStyle {
+                        font_weight: Some(FontWeight::BOLD),
+                        underline,
+                        ..Default::default()
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_render_html() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = "a <b>bold</b> <span>b</span> <code>c</code>\n\n<div>\nd\n</div>\n\ne";
+        let render = |html| {
+            render_markdown_with_options(
+                block.into(),
+                &[],
+                &language_registry,
+                None,
+                &RenderMarkdownOptions {
+                    html,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let rich_text = render(HtmlRendering::Strip);
+        assert_eq!(rich_text.text, "a bold b c\n\ne");
+
+        let rich_text = render(HtmlRendering::Literal);
+        assert_eq!(
+            rich_text.text,
+            "a <b>bold</b> <span>b</span> <code>c</code>\n\n<div>\nd\n</div>\n\ne"
+        );
+
+        // Safe tags render the same way as their markdown equivalents.
+        let rich_text = render(HtmlRendering::SafeTags);
+        let expected = render_markdown(
+            "a **bold** b `c`\n\ne".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        assert_eq!(rich_text.text, expected.text);
+        assert_eq!(rich_text.highlights, expected.highlights);
+    }
+
+    #[gpui::test]
+    fn test_render_kbd_tags() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = "press <kbd>Cmd</kbd>-<KBD>K</KBD>";
+        let render = |block: &str, render_kbd_tags, html| {
+            render_markdown_with_options(
+                block.to_string(),
+                &[],
+                &language_registry,
+                None,
+                &RenderMarkdownOptions {
+                    render_kbd_tags,
+                    html,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let key = Highlight::Key {
+            style: HighlightStyle::default(),
+            in_quote: false,
+        };
+        let rich_text = render(block, true, HtmlRendering::Strip);
+        assert_eq!(rich_text.text, "press Cmd-K");
+        assert_eq!(
+            rich_text.highlights,
+            vec![(6..9, key.clone()), (10..11, key.clone())]
+        );
+        let rich_text = render(block, true, HtmlRendering::SafeTags);
+        assert_eq!(
+            rich_text.highlights,
+            vec![(6..9, key.clone()), (10..11, key.clone())]
+        );
+
+        // Keys keep the style of the text around them.
+        let rich_text = render("> **<kbd>K</kbd>**", true, HtmlRendering::Strip);
+        assert_eq!(rich_text.text, "▎ K");
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (0..4, Highlight::Quote),
+                (
+                    4..5,
+                    Highlight::Key {
+                        style: HighlightStyle {
+                            font_weight: Some(FontWeight::BOLD),
+                            ..Default::default()
+                        },
+                        in_quote: true,
+                    }
+                ),
+            ]
+        );
+
+        // A tag that isn't closed ends with its paragraph.
+        for html in [HtmlRendering::Strip, HtmlRendering::SafeTags] {
+            let rich_text = render("<kbd>K\n\ntext\n\n```\ncode\n```", true, html);
+            assert_eq!(rich_text.text, "K\n\ntext\n\ncode");
+            assert_eq!(rich_text.highlights, vec![(0..1, key.clone())]);
+        }
+        let rich_text = render_markdown_with_options(
+            "<code>let\n\nlet".into(),
+            &[],
+            &language_registry,
+            Some(&rust_lang()),
+            &RenderMarkdownOptions {
+                html: HtmlRendering::SafeTags,
+                highlight_inline_code: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(rich_text.text, "let\n\nlet");
+        assert_eq!(
+            rich_text
+                .highlights
+                .iter()
+                .map(|(range, _)| range.clone())
+                .collect::<Vec<_>>(),
+            vec![0..3]
+        );
+
+        let rich_text = render(block, false, HtmlRendering::Strip);
+        assert_eq!(rich_text.text, "press Cmd-K");
+        assert_eq!(rich_text.highlights, vec![]);
+
+        let rich_text = render(block, true, HtmlRendering::Literal);
+        assert_eq!(rich_text.text, block);
+        assert_eq!(rich_text.highlights, vec![]);
+    }
+
+    #[gpui::test]
+    fn test_reference_links() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "[docs][d], [zed][] and [blog]\n\n[d]: https://zed.dev/docs\n[zed]: https://zed.dev\n[blog]: https://zed.dev/blog".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) = marked_text_ranges("«docs», «zed» and «blog»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(rich_text.link_ranges, ranges);
+        assert_eq!(
+            &*rich_text.link_urls,
+            &[
+                "https://zed.dev/docs".to_string(),
+                "https://zed.dev".to_string(),
+                "https://zed.dev/blog".to_string(),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_render_images() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "![a *diagram*](https://zed.dev/a.png) ![](https://zed.dev/b.png \"title\") ![](https://zed.dev/c.png)".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) =
+            marked_text_ranges("«a diagram» «title» «https://zed.dev/c.png»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(rich_text.link_ranges, ranges);
+        assert_eq!(
+            &*rich_text.link_urls,
+            &[
+                "https://zed.dev/a.png".to_string(),
+                "https://zed.dev/b.png".to_string(),
+                "https://zed.dev/c.png".to_string(),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_render_footnotes() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "a[^b] c[^a] d[^b]\n\n[^a]: first *note*\n\n[^b]: second note".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let rule = "─".repeat(RULE_WIDTH);
+        assert_eq!(
+            rich_text.text,
+            format!("a[1] c[2] d[1]\n\n{rule}\n\n[1] second note\n\n[2] first note")
+        );
+    }
+
+    #[gpui::test]
+    fn test_list_options() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = "- a\n  - b\n    - c\n      - d\n- [ ] e\n\n3. f\n4. g";
+
+        let rich_text = render_markdown(block.into(), &[], &language_registry, None);
+        assert_eq!(
+            rich_text.text,
+            "- a\n  - b\n    - c\n      - d\n☐ e\n3. f\n4. g"
+        );
+
+        let rich_text = render_markdown_with_options(
+            block.into(),
+            &[],
+            &language_registry,
+            None,
+            &RenderMarkdownOptions {
+                list_bullets: vec!["•".into(), "◦".into(), "▪".into()],
+                preserve_list_start: false,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            rich_text.text,
+            "• a\n  ◦ b\n    ▪ c\n      • d\n☐ e\n1. f\n2. g"
+        );
+    }
+
+    #[gpui::test]
+    fn test_ordered_list_start() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+
+        let rich_text = render_markdown("5. a\n6. b\n7. c".into(), &[], &language_registry, None);
+        assert_eq!(rich_text.text, "5. a\n6. b\n7. c");
+
+        // Items with several paragraphs keep counting from the list's start.
+        let rich_text = render_markdown(
+            "5. a\n\n   more a\n\n6. b\n   - x\n\n   more b\n7. c".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        assert_eq!(
+            rich_text.text,
+            "5. a\n\n  more a\n6. b\n  - x\n\n  more b\n7. c"
+        );
+
+        // Ordered lists nested inside unordered ones keep their own start.
+        let rich_text = render_markdown(
+            "- a\n  3. b\n  4. c\n- d\n  10. e\n  11. f".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        assert_eq!(rich_text.text, "- a\n  3. b\n  4. c\n- d\n  10. e\n  11. f");
+
+        let rich_text = render_markdown(
+            "2. a\n   5. b\n   6. c\n3. d".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        assert_eq!(rich_text.text, "2. a\n   5. b\n   6. c\n3. d");
+    }
+
+    #[gpui::test]
+    fn test_list_item_continuation_lines() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "- first line\n  second line\n- a  \n  b\n  1. x\n     y".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        assert_eq!(
+            rich_text.text,
+            "- first line\n  second line\n- a\n  b\n  1. x\n     y"
+        );
+
+        let rich_text =
+            render_markdown("> - quoted\n>   item".into(), &[], &language_registry, None);
+        assert_eq!(rich_text.text, "▎ - quoted\n▎   item");
+    }
+
+    #[gpui::test]
+    fn test_links_without_underlines() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown_with_options(
+            "[a](https://a.com) **[b](https://b.com)** `c` https://c.com".into(),
+            &[],
+            &language_registry,
+            None,
+            &RenderMarkdownOptions {
+                underline_links: false,
+                ..Default::default()
+            },
+        );
+
+        let (text, ranges) = marked_text_ranges("«a» «b» c «https://c.com»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(rich_text.link_ranges, ranges);
+        assert_eq!(
+            rich_text.highlights,
+            vec![(
+                ranges[1].clone(),
+                Highlight::Highlight(HighlightStyle {
+                    font_weight: Some(FontWeight::BOLD),
+                    ..Default::default()
+                })
+            )]
+        );
+    }
+
+    #[gpui::test]
+    fn test_links_with_inline_code() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "see [run `cargo test` now](https://a.com)".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let (text, ranges) = marked_text_ranges("see «run cargo test now»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(rich_text.link_ranges, ranges);
+        assert_eq!(rich_text.link_urls.as_ref(), ["https://a.com"]);
+        assert_eq!(
+            rich_text.highlights,
+            vec![(
+                ranges[0].clone(),
+                Highlight::Highlight(HighlightStyle {
+                    underline: Some(UnderlineStyle {
+                        thickness: 1.0.into(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            )]
+        );
+    }
+
+    #[gpui::test]
+    fn test_link_click_handler(cx: &mut TestAppContext) {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let mut rich_text = render_markdown(
+            "[link](https://zed.dev)".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        let clicked_urls = Rc::new(RefCell::new(Vec::new()));
+        rich_text.set_link_click_handler({
+            let clicked_urls = clicked_urls.clone();
+            move |url, _| clicked_urls.borrow_mut().push(url.to_string())
+        });
+
+        // The test platform panics if the URL is opened instead.
+        let on_click = rich_text.click_listener();
+        cx.add_empty_window().update(|cx| on_click(0, cx));
+        assert_eq!(*clicked_urls.borrow(), ["https://zed.dev"]);
+    }
+
+    #[test]
+    fn test_color_links() {
+        let bold = HighlightStyle {
+            font_weight: Some(FontWeight::BOLD),
+            ..Default::default()
+        };
+        let link_color = Hsla::default();
+        let link = HighlightStyle {
+            color: Some(link_color),
+            ..Default::default()
+        };
+        let bold_link = HighlightStyle {
+            color: Some(link_color),
+            ..bold
+        };
+
+        assert_eq!(
+            color_links([(2..8, bold)], &[0..4, 6..7, 10..12], link_color),
+            vec![
+                (0..2, link),
+                (2..4, bold_link),
+                (4..6, bold),
+                (6..7, bold_link),
+                (7..8, bold),
+                (10..12, link),
+            ]
+        );
+        assert_eq!(
+            color_links([(2..8, bold)], &[], link_color),
+            vec![(2..8, bold)]
+        );
+    }
+
+    #[gpui::test]
+    fn test_render_strikethrough() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "~~strike~~ and ~~**bold** strike~~".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let strikethrough = HighlightStyle {
+            strikethrough: Some(StrikethroughStyle {
+                thickness: 1.0.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let (text, ranges) = marked_text_ranges("«strike» and «bold»« strike»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), Highlight::Highlight(strikethrough)),
+                (
+                    ranges[1].clone(),
+                    Highlight::Highlight(HighlightStyle {
+                        font_weight: Some(FontWeight::BOLD),
+                        ..strikethrough
+                    })
+                ),
+                (ranges[2].clone(), Highlight::Highlight(strikethrough)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mention_styles() {
+        let status = StatusColors::dark();
+        assert_eq!(
+            mention_style(false, &status),
+            HighlightStyle {
+                font_weight: Some(FontWeight::BOLD),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            mention_style(true, &status),
+            HighlightStyle {
+                font_weight: Some(FontWeight::BOLD),
+                background_color: Some(status.info_background),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[gpui::test]
+    fn test_highlight_inline_code() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let language = rust_lang();
+        let block = "run `let x = 1` now";
+        let options = RenderMarkdownOptions {
+            highlight_inline_code: true,
+            ..Default::default()
+        };
+
+        let rich_text = render_markdown(block.into(), &[], &language_registry, Some(&language));
+        assert_eq!(rich_text.text, "run let x = 1 now");
+        assert_eq!(rich_text.highlights, vec![]);
+
+        let rich_text = render_markdown_with_options(
+            block.into(),
+            &[],
+            &language_registry,
+            Some(&language),
+            &options,
+        );
+        assert_eq!(rich_text.text, "run let x = 1 now");
+        assert!(rich_text
+            .highlights
+            .iter()
+            .any(|(range, highlight)| *range == (4..7) && matches!(highlight, Highlight::Id(_))));
+
+        // Without a default language, inline code is rendered as usual.
+        let rich_text =
+            render_markdown_with_options(block.into(), &[], &language_registry, None, &options);
+        assert_eq!(rich_text.highlights, vec![]);
+    }
+
+    #[gpui::test]
+    fn test_render_marks() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let render = |block: &str, mentions: &[Mention], options: &RenderMarkdownOptions| {
+            render_markdown_with_options(block.into(), mentions, &language_registry, None, options)
+        };
+        let options = RenderMarkdownOptions {
+            render_marks: true,
+            ..Default::default()
+        };
+        let bold = HighlightStyle {
+            font_weight: Some(FontWeight::BOLD),
+            ..Default::default()
+        };
+
+        let rich_text = render("a ==b== c", &[], &options);
+        assert_eq!(rich_text.text, "a b c");
+        assert_eq!(
+            rich_text.highlights,
+            vec![(2..3, Highlight::Mark(HighlightStyle::default()))]
+        );
+
+        // Marked text keeps its style.
+        let rich_text = render("**x ==y==** z", &[], &options);
+        assert_eq!(rich_text.text, "x y z");
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (0..2, Highlight::Highlight(bold)),
+                (2..3, Highlight::Mark(bold))
+            ]
+        );
+
+        // Mentions after marks are shifted to match.
+        let rich_text = render(
+            "==hi== @abc",
+            &[Mention {
+                range: 7..11,
+                is_self_mention: false,
+                user_id: None,
+            }],
+            &options,
+        );
+        assert_eq!(rich_text.text, "hi @abc");
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (0..2, Highlight::Mark(HighlightStyle::default())),
+                (3..7, Highlight::Mention)
+            ]
+        );
+
+        // Code, unpaired markers, and markers around whitespace are rendered literally.
+        let rich_text = render("`==a==`, a == b, ==c\n```\n==d==\n```", &[], &options);
+        assert_eq!(rich_text.text, "==a==, a == b, ==c\n\n==d==");
+        assert!(!rich_text
+            .highlights
+            .iter()
+            .any(|(_, highlight)| matches!(highlight, Highlight::Mark(_))));
+
+        // Marks aren't rendered by default.
+        let rich_text = render("a ==b== c", &[], &RenderMarkdownOptions::default());
+        assert_eq!(rich_text.text, "a ==b== c");
+        assert_eq!(rich_text.highlights, vec![]);
+    }
+
+    #[gpui::test]
+    fn test_render_math_as_code() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = "let $a_i = b_i$ cost \\$5 or `$x$`";
+
+        let rich_text = render_markdown(block.into(), &[], &language_registry, None);
+        assert_eq!(rich_text.text, "let $a_i = b_i$ cost $5 or $x$");
+
+        let rich_text = render_markdown_with_options(
+            block.into(),
+            &[],
+            &language_registry,
+            None,
+            &RenderMarkdownOptions {
+                render_math_as_code: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(rich_text.text, "let a_i = b_i cost $5 or $x$");
+        assert_eq!(rich_text.highlights, vec![(4..13, Highlight::Code)]);
+
+        // Math is left untouched by the other passes, even when they change the text
+        // before it.
+        let rich_text = render_markdown_with_options(
+            "Term :tada:\n: x^2^ is $x^2^$ and $:x:$".into(),
+            &[],
+            &language_registry,
+            None,
+            &RenderMarkdownOptions {
+                render_math_as_code: true,
+                render_sub_superscripts: true,
+                expand_emoji_shortcodes: true,
+                render_definition_lists: true,
+                ..Default::default()
+            },
+        );
+        let (text, ranges) = marked_text_ranges(
+            "«Term 🎉»\n\u{a0}\u{a0}\u{a0}\u{a0}x² is «x^2^» and «:x:»",
+            false,
+        );
+        let bold = Highlight::Highlight(HighlightStyle {
+            font_weight: Some(FontWeight::BOLD),
+            ..Default::default()
+        });
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), bold),
+                (ranges[1].clone(), Highlight::Code),
+                (ranges[2].clone(), Highlight::Code),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_compute_highlights() {
+        let language = rust_lang();
+        let language_registry = Arc::new(LanguageRegistry::test());
+        language_registry.add(language.clone());
+        let highlights = compute_highlights("let x = 1", &language_registry, "Rust");
+        assert!(highlights
+            .iter()
+            .any(|(range, highlight)| *range == (0..3) && matches!(highlight, Highlight::Id(_))));
+
+        // The highlights match those of rendering the text as code after other text.
+        let mut text = "x".to_string();
+        let mut rendered_highlights = Vec::new();
+        render_code(
+            &mut text,
+            &mut rendered_highlights,
+            "let x = 1",
+            &language,
+            usize::MAX,
+            false,
+        );
+        assert_eq!(
+            rendered_highlights,
+            highlights
+                .iter()
+                .map(|(range, highlight)| (range.start + 1..range.end + 1, highlight.clone()))
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            compute_highlights("let x = 1", &language_registry, "Unknown"),
+            vec![(0..9, Highlight::Code)]
+        );
+    }
+
+    #[gpui::test]
+    fn test_strip_front_matter() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let render = |block: &str, mentions: &[Mention], strip_front_matter| {
+            render_markdown_with_options(
+                block.into(),
+                mentions,
+                &language_registry,
+                None,
+                &RenderMarkdownOptions {
+                    strip_front_matter,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let (block, ranges) = marked_text_ranges(
+            "---\ntitle: Intro\nauthor: \"«@abc»\"\n---\n# Intro\n\nby «@abc»",
+            false,
+        );
+        let mentions = ranges
+            .into_iter()
+            .map(|range| Mention {
+                range,
+                is_self_mention: false,
+                user_id: Some(1),
+            })
+            .collect::<Vec<_>>();
+        let rich_text = render(&block, &mentions, true);
+        let (text, ranges) = marked_text_ranges("Intro\n\nby «@abc»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.front_matter,
+            Some(HashMap::from_iter([
+                ("title".to_string(), "Intro".to_string()),
+                ("author".to_string(), "@abc".to_string()),
+            ]))
+        );
+        assert_eq!(
+            rich_text
+                .mention_ranges
+                .iter()
+                .map(|(range, _)| range.clone())
+                .collect::<Vec<_>>(),
+            ranges
+        );
+
+        // Front matter is rendered as is unless it's stripped, and only stripped from the
+        // very start of the text.
+        let rich_text = render(&block, &mentions, false);
+        assert!(rich_text.text.contains("title: Intro"));
+        assert_eq!(rich_text.front_matter, None);
+        for block in [
+            "# Intro\n\n---\ntitle: Intro\n---",
+            "\n---\ntitle: Intro\n---",
+        ] {
+            let rich_text = render(block, &[], true);
+            assert!(rich_text.text.contains("title: Intro"));
+            assert_eq!(rich_text.front_matter, None);
+        }
+    }
+
+    #[gpui::test]
+    fn test_render_sub_superscripts() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = "H~2~O, x^2^, and ~~struck~~";
+        let options = RenderMarkdownOptions {
+            render_sub_superscripts: true,
+            ..Default::default()
+        };
+        let rich_text =
+            render_markdown_with_options(block.into(), &[], &language_registry, None, &options);
+        let (text, ranges) = marked_text_ranges("H₂O, x², and «struck»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![(
+                ranges[0].clone(),
+                Highlight::Highlight(HighlightStyle {
+                    strikethrough: Some(StrikethroughStyle {
+                        thickness: 1.0.into(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            )]
+        );
+
+        // Without the option, the tildes and carets are rendered as markdown.
+        let rich_text = render_markdown_with_options(
+            block.into(),
+            &[],
+            &language_registry,
+            None,
+            &Default::default(),
+        );
+        assert!(rich_text.text.contains("x^2^"));
+    }
+
+    #[gpui::test]
+    fn test_render_definition_lists() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = "Apple\n: A fruit\n: A company\n\nratio 3:1\n\nOrange\n: Another fruit\n- item";
+        let options = RenderMarkdownOptions {
+            render_definition_lists: true,
+            ..Default::default()
+        };
+        let rich_text =
+            render_markdown_with_options(block.into(), &[], &language_registry, None, &options);
+
+        let indent = "\u{a0}\u{a0}\u{a0}\u{a0}";
+        let (text, ranges) = marked_text_ranges(
+            &format!(
+                "«Apple»\n{indent}A fruit\n{indent}A company\n\nratio 3:1\n\n\
+                «Orange»\n{indent}Another fruit\n- item"
+            ),
+            false,
+        );
+        assert_eq!(rich_text.text, text);
+        let bold = Highlight::Highlight(HighlightStyle {
+            font_weight: Some(FontWeight::BOLD),
+            ..Default::default()
+        });
+        assert_eq!(
+            rich_text.highlights,
+            ranges
+                .into_iter()
+                .map(|range| (range, bold.clone()))
+                .collect::<Vec<_>>()
+        );
+
+        // Mentions within definitions are kept.
+        let block = "Owner\n: @nathan";
+        let rich_text = render_markdown_with_options(
+            block.into(),
+            &[Mention {
+                range: 8..15,
+                is_self_mention: false,
+                user_id: Some(1),
+            }],
+            &language_registry,
+            None,
+            &options,
+        );
+        assert_eq!(rich_text.text, format!("Owner\n{indent}@nathan"));
+        assert_eq!(
+            rich_text
+                .mention_ranges
+                .iter()
+                .map(|(range, _)| &rich_text.text[range.clone()])
+                .collect::<Vec<_>>(),
+            ["@nathan"]
+        );
+    }
+
+    #[gpui::test]
+    fn test_code_line_numbers() {
+        let language = rust_lang();
+        let language_registry = Arc::new(LanguageRegistry::test());
+        language_registry.add(language.clone());
+        let code = "fn a() {}\nlet x = 1;\nb();";
+        let mut text = String::new();
+        let mut highlights = Vec::new();
+        render_code(
+            &mut text,
+            &mut highlights,
+            code,
+            &language,
+            usize::MAX,
+            true,
+        );
+        assert_eq!(text, "1 fn a() {}\n2 let x = 1;\n3 b();");
+        assert_eq!(
+            highlights
+                .iter()
+                .filter(|(_, highlight)| *highlight == Highlight::LineNumber)
+                .map(|(range, _)| &text[range.clone()])
+                .collect::<Vec<_>>(),
+            ["1 ", "2 ", "3 "]
+        );
+
+        // The code's highlights are the same as without line numbers, shifted past the
+        // line number of each line.
+        let unnumbered_highlights = compute_highlights(code, &language_registry, "Rust");
+        let mut code_highlights = highlights
+            .iter()
+            .filter(|(_, highlight)| *highlight != Highlight::LineNumber)
+            .map(|(range, highlight)| (&text[range.clone()], highlight.clone()));
+        for (range, highlight) in &unnumbered_highlights {
+            for segment in code[range.clone()].split_inclusive('\n') {
+                assert_eq!(code_highlights.next(), Some((segment, highlight.clone())));
+            }
+        }
+        assert_eq!(code_highlights.next(), None);
+        assert!(unnumbered_highlights
+            .iter()
+            .any(|(range, highlight)| code[range.clone()] == *"let"
+                && matches!(highlight, Highlight::Id(_))));
+
+        // Line numbers are padded to the same width, and aren't copied.
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = format!("a\n\n```\n{}```", "x\n".repeat(10));
+        let rich_text = render_markdown_with_options(
+            block,
+            &[],
+            &language_registry,
+            None,
+            &RenderMarkdownOptions {
+                code_block_line_numbers: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            rich_text.text,
+            "a\n\n 1 x\n 2 x\n 3 x\n 4 x\n 5 x\n 6 x\n 7 x\n 8 x\n 9 x\n10 x"
+        );
+        let line_number_ranges = rich_text
+            .highlights
+            .iter()
+            .filter(|(_, highlight)| *highlight == Highlight::LineNumber)
+            .map(|(range, _)| range.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(line_number_ranges.len(), 10);
+        assert_eq!(
+            copied_code(
+                &rich_text.text,
+                rich_text.code_block_ranges[0].clone(),
+                &line_number_ranges
+            ),
+            "x\n".repeat(10).trim_end()
+        );
+    }
+
+    #[gpui::test]
+    fn test_rich_text_builder() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let options = RenderMarkdownOptions::default();
+        let mut builder = RichTextBuilder::new();
+        builder.push_markdown(
+            "hi @abc, see [this](https://zed.dev)",
+            &[Mention {
+                range: 3..7,
+                is_self_mention: false,
+                user_id: Some(1),
+            }],
+            &language_registry,
+            None,
+            &options,
+        );
+        builder.push_plain("\n---\n");
+        builder.push_markdown(
+            "**and** @fgh",
+            &[Mention {
+                range: 8..12,
+                is_self_mention: true,
+                user_id: Some(2),
+            }],
+            &language_registry,
+            None,
+            &options,
+        );
+        let rich_text = builder.build();
+
+        let (text, ranges) =
+            marked_text_ranges("hi «@abc», see «this»\n---\n\n«and» «@fgh»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(rich_text.link_ranges, vec![ranges[1].clone()]);
+        assert_eq!(
+            rich_text
+                .mention_ranges
+                .iter()
+                .map(|(range, _)| range.clone())
+                .collect::<Vec<_>>(),
+            vec![ranges[0].clone(), ranges[3].clone()]
+        );
+        assert_eq!(
+            rich_text
+                .highlights
+                .iter()
+                .map(|(range, _)| range.clone())
+                .collect::<Vec<_>>(),
+            ranges
+        );
+    }
+
+    #[gpui::test]
+    fn test_trim_start() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let mut rich_text = render_markdown(
+            "&#32;\n\n```\n  code\n```\n[link](https://zed.dev)".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        assert_eq!(rich_text.text, " \n\n  code\n\nlink");
+        let link_highlight = rich_text.highlights[0].1.clone();
+        // Highlights that start within the removed whitespace are clipped to the rest of
+        // the text, unless they're entirely within it.
+        rich_text
+            .highlights
+            .splice(0..0, [(0..1, Highlight::Code), (2..9, Highlight::Code)]);
+
+        rich_text.trim_start();
+        let (text, ranges) = marked_text_ranges("«  code»\n\n«link»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), Highlight::Code),
+                (ranges[1].clone(), link_highlight),
+            ]
+        );
+        assert_eq!(rich_text.code_block_ranges, vec![ranges[0].clone()]);
+        assert_eq!(rich_text.link_ranges, vec![ranges[1].clone()]);
+        assert_eq!(&*rich_text.link_urls, &["https://zed.dev".to_string()]);
+    }
+
+    #[gpui::test]
+    fn test_render_unbalanced_emphasis() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let markers = [
+            "*", "**", "_", "__", "~~", "***", "`", "> ", "<b>", "</b>", "</i>",
+        ];
+        for (ix, marker) in markers.iter().enumerate() {
+            for other in &markers[ix..] {
+                for block in [
+                    format!("{marker}a {other}b{marker} c"),
+                    format!("a{marker}{other} b{other}\n\nplain"),
+                    format!("{other}{marker}a{marker}\n\n{marker}plain"),
+                ] {
+                    for html in [HtmlRendering::Strip, HtmlRendering::SafeTags] {
+                        let rich_text = render_markdown_with_options(
+                            block.clone(),
+                            &[],
+                            &language_registry,
+                            None,
+                            &RenderMarkdownOptions {
+                                html,
+                                ..Default::default()
+                            },
+                        );
+                        for window in rich_text.highlights.windows(2) {
+                            assert!(
+                                window[0].0.end <= window[1].0.start,
+                                "overlapping highlights for {block:?}"
+                            );
+                            assert!(
+                                window[0].0.end < window[1].0.start || window[0].1 != window[1].1,
+                                "fragmented highlights for {block:?}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        // Unbalanced closing tags don't affect the text after them.
+        let rich_text = render_markdown_with_options(
+            "</b></i> plain **bold** plain".into(),
+            &[],
+            &language_registry,
+            None,
+            &RenderMarkdownOptions {
+                html: HtmlRendering::SafeTags,
+                ..Default::default()
+            },
+        );
+        let (text, ranges) = marked_text_ranges(" plain «bold» plain", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![(
+                ranges[0].clone(),
+                Highlight::Highlight(HighlightStyle {
+                    font_weight: Some(FontWeight::BOLD),
+                    ..Default::default()
+                })
+            )]
+        );
+    }
+
+    #[gpui::test]
+    fn test_minimal_highlights() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let render = |block: &str, soft_break_as_space| {
+            render_markdown_with_options(
+                block.into(),
+                &[],
+                &language_registry,
+                None,
+                &RenderMarkdownOptions {
+                    soft_break_as_space,
+                    ..Default::default()
+                },
+            )
+        };
+        let bold = HighlightStyle {
+            font_weight: Some(FontWeight::BOLD),
+            ..Default::default()
+        };
+        let bold_italic = HighlightStyle {
+            font_style: Some(FontStyle::Italic),
+            ..bold
+        };
+        let strikethrough = HighlightStyle {
+            strikethrough: Some(StrikethroughStyle {
+                thickness: 1.0.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let rich_text = render("**_bold italic_** and ***more***", false);
+        let (text, ranges) = marked_text_ranges("«bold italic» and «more»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), Highlight::Highlight(bold_italic)),
+                (ranges[1].clone(), Highlight::Highlight(bold_italic)),
+            ]
+        );
+
+        // Nested spans only split a run where its style changes.
+        let rich_text = render("**a _b_ c**", false);
+        let (text, ranges) = marked_text_ranges("«a »«b»« c»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), Highlight::Highlight(bold)),
+                (ranges[1].clone(), Highlight::Highlight(bold_italic)),
+                (ranges[2].clone(), Highlight::Highlight(bold)),
+            ]
+        );
+
+        // Runs that span several lines, or lines of a quote, aren't split at the breaks.
+        for (soft_break_as_space, expected) in [(false, "«one\ntwo»"), (true, "«one two»")] {
+            let rich_text = render("~~one\ntwo~~", soft_break_as_space);
+            let (text, ranges) = marked_text_ranges(expected, false);
+            assert_eq!(rich_text.text, text);
+            assert_eq!(
+                rich_text.highlights,
+                vec![(ranges[0].clone(), Highlight::Highlight(strikethrough))]
+            );
+        }
+        let rich_text = render("> one\n> two", false);
+        let (text, ranges) = marked_text_ranges("«▎ one\n▎ two»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![(ranges[0].clone(), Highlight::Quote)]
+        );
+    }
+
+    #[gpui::test]
+    fn test_soft_break_as_space() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = "one\ntwo  \nthree\\\nfour \n five\n\n> six\n> seven";
+        let render = |soft_break_as_space| {
+            render_markdown_with_options(
+                block.into(),
+                &[],
+                &language_registry,
+                None,
+                &RenderMarkdownOptions {
+                    soft_break_as_space,
+                    ..Default::default()
+                },
+            )
+            .text
+        };
+
+        assert_eq!(
+            render(false),
+            "one\ntwo\nthree\nfour \nfive\n\n▎ six\n▎ seven"
+        );
+        assert_eq!(render(true), "one two\nthree\nfour five\n\n▎ six seven");
+    }
+
+    #[gpui::test]
+    fn test_skip_highlighting_large_code_blocks() {
+        let language = rust_lang();
+        let small_block = "let x = 1;\n";
+        let large_block = small_block.repeat(20_000);
+        assert!(large_block.len() > RenderMarkdownOptions::default().max_highlighted_code_len);
+
+        let mut text = String::new();
+        let mut highlights = Vec::new();
+        render_code(
+            &mut text,
+            &mut highlights,
+            small_block,
+            &language,
+            1024,
+            false,
+        );
+        assert!(highlights
+            .iter()
+            .any(|(_, highlight)| matches!(highlight, Highlight::Id(_))));
+
+        // A large block is rendered as a single code span, without being highlighted.
+        let mut text = String::new();
+        let mut highlights = Vec::new();
+        render_code(
+            &mut text,
+            &mut highlights,
+            &large_block,
+            &language,
+            1024,
+            false,
+        );
+        assert_eq!(text, large_block);
+        assert_eq!(highlights, vec![(0..large_block.len(), Highlight::Code)]);
+    }
+
+    #[test]
+    fn test_code_wrap_width() {
+        let options = RenderMarkdownOptions {
+            code_wrap_width: Some(4),
+            ..Default::default()
+        };
+        let mut builder = RichTextBuilder::new();
+        builder.push_markdown_internal(
+            "`abcdefghij`\n```\nabcdefghij k\n```",
+            &[],
+            None,
+            None,
+            &options,
+            &HashMap::default(),
+        );
+        let rich_text = builder.build();
+
+        // Only code blocks are wrapped, and their highlights cover the inserted spaces.
+        assert_eq!(rich_text.text, "abcdefghij\n\nabcd\u{200B}efgh\u{200B}ij k");
+        assert_eq!(rich_text.code_block_ranges, vec![12..30]);
+        assert_eq!(rich_text.highlights, vec![(12..30, Highlight::Code)]);
+        assert_eq!(
+            rich_text.text[rich_text.code_block_ranges[0].clone()].replace(ZERO_WIDTH_SPACE, ""),
+            "abcdefghij k"
+        );
+
+        // Mentions after the inserted spaces are shifted too.
+        let mut builder = RichTextBuilder::new();
+        builder.push_markdown_internal(
+            "```\nabcdefghij @bob\n```",
+            &[Mention {
+                range: 15..19,
+                is_self_mention: false,
+                user_id: Some(1),
+            }],
+            Some(&Arc::new(LanguageRegistry::test())),
+            None,
+            &options,
+            &HashMap::default(),
+        );
+        let rich_text = builder.build();
+        assert_eq!(rich_text.text, "abcd\u{200B}efgh\u{200B}ij @bob");
+        assert_eq!(
+            rich_text.mention_ranges,
+            vec![(
+                17..21,
+                MentionMeta {
+                    is_self_mention: false,
+                    user_id: Some(1),
+                }
+            )]
+        );
+        assert_eq!(&rich_text.text[17..21], "@bob");
+    }
+
+    #[test]
+    fn test_render_markdown_plain() {
+        let rich_text = render_markdown_plain(
+            "run `cargo test`:\n```rust\nfn a() {}\nfn b() {}\n```".into(),
+            &[],
+        );
+        assert_eq!(rich_text.text, "run cargo test:\n\nfn a() {}\nfn b() {}");
+        assert_eq!(rich_text.code_block_ranges, vec![17..36]);
+        assert_eq!(rich_text.highlights, vec![(17..36, Highlight::Code)]);
+    }
+
+    #[gpui::test]
+    fn test_render_diff_blocks() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "```diff\n--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n-old\n+new\n same\n```".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        assert_eq!(
+            rich_text.text,
+            "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n-old\n+new\n same"
+        );
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (0..7, Highlight::Code),
+                (8..15, Highlight::Code),
+                (16..31, Highlight::DiffHunk),
+                (32..36, Highlight::DiffRemoved),
+                (37..41, Highlight::DiffAdded),
+                (42..47, Highlight::Code),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_collapse_blank_lines() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+
+        // An empty code block used to leave two blank lines between its neighbors. Blank
+        // lines within code blocks are kept.
+        let rich_text = render_markdown(
+            "a\n\n```\n```\n\n[b](https://zed.dev)\n\n```\nc\n\n\nd\n```".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+        assert_eq!(rich_text.text, "a\n\nb\n\nc\n\n\nd");
+        assert_eq!(rich_text.link_ranges, vec![3..4]);
+        assert_eq!(rich_text.code_block_ranges, vec![3..3, 6..11]);
+    }
+
+    #[gpui::test]
+    fn test_is_interactive() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let render = |block: &str, mentions: &[Mention]| {
+            render_markdown(block.into(), mentions, &language_registry, None)
+        };
+
+        let rich_text = render("**plain** text", &[]);
+        assert!(!rich_text.has_links());
+        assert!(!rich_text.has_mentions());
+        assert!(!rich_text.is_interactive());
+
+        let rich_text = render("see [docs](https://zed.dev)", &[]);
+        assert!(rich_text.has_links());
+        assert!(!rich_text.has_mentions());
+        assert!(rich_text.is_interactive());
+
+        let rich_text = render(
+            "hi @nathan",
+            &[Mention {
+                range: 3..10,
+                is_self_mention: true,
+                user_id: None,
+            }],
+        );
+        assert!(!rich_text.has_links());
+        assert!(rich_text.has_mentions());
+        assert!(rich_text.is_interactive());
+
+        // Code blocks can be clicked to copy them.
+        let rich_text = render("```\ncode\n```", &[]);
+        assert!(!rich_text.has_links());
+        assert!(rich_text.is_interactive());
+    }
+
+    #[gpui::test]
+    fn test_coalesce_highlights() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let bold = Highlight::Highlight(HighlightStyle {
+            font_weight: Some(FontWeight::BOLD),
+            ..Default::default()
+        });
+        let italic = Highlight::Highlight(HighlightStyle {
+            font_style: Some(FontStyle::Italic),
+            ..Default::default()
+        });
+
+        let mut rich_text = render_markdown("abcdefgh".into(), &[], &language_registry, None);
+        rich_text.highlights = vec![
+            (2..4, bold.clone()),
+            (0..2, bold.clone()),
+            (4..5, bold.clone()),
+            (3..5, bold.clone()),
+            (5..6, italic.clone()),
+            (6..8, bold.clone()),
+        ];
+        rich_text.coalesce_highlights();
+        assert_eq!(
+            rich_text.highlights,
+            vec![(0..5, bold.clone()), (5..6, italic), (6..8, bold)]
+        );
+    }
+
+    #[gpui::test]
+    fn test_truncate() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let rich_text = render_markdown(
+            "**héllo** [wörld](https://zed.dev) and [more](https://zed.dev/more)".into(),
+            &[],
+            &language_registry,
+            None,
+        );
+
+        let truncated = rich_text.truncate(100);
+        assert_eq!(truncated.text, rich_text.text);
+
+        let truncated = rich_text.truncate(9);
+        let (text, ranges) = marked_text_ranges("«héllo» «wör»…", false);
+        assert_eq!(truncated.text, text);
+        assert_eq!(
+            truncated
+                .highlights
+                .iter()
+                .map(|(range, _)| range.clone())
+                .collect::<Vec<_>>(),
+            ranges
+        );
+        assert_eq!(truncated.link_ranges, vec![ranges[1].clone()]);
+        assert_eq!(&*truncated.link_urls, &["https://zed.dev".to_string()]);
+    }
+
+    #[gpui::test]
+    fn test_mention_ranges() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let (block, ranges) =
+            marked_text_ranges("hi «@abc»\n\n| a | b |\n|---|---|\n| x | «@fgh» |", false);
+        let mentions = [
+            Mention {
+                range: ranges[0].clone(),
+                is_self_mention: false,
+                user_id: Some(1),
+            },
+            Mention {
+                range: ranges[1].clone(),
+                is_self_mention: true,
+                user_id: Some(2),
+            },
+        ];
+        let rich_text = render_markdown(block, &mentions, &language_registry, None);
+
+        let (text, ranges) = marked_text_ranges("hi «@abc»\n\na │ b\n──┼─────\nx │ «@fgh»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.mention_ranges,
+            vec![
+                (
+                    ranges[0].clone(),
+                    MentionMeta {
+                        is_self_mention: false,
+                        user_id: Some(1),
+                    }
+                ),
+                (
+                    ranges[1].clone(),
+                    MentionMeta {
+                        is_self_mention: true,
+                        user_id: Some(2),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_mentions_within_styled_text() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let render = |block: &str| {
+            let (block, ranges) = marked_text_ranges(block, false);
+            let mentions = ranges
+                .into_iter()
+                .map(|range| Mention {
+                    range,
+                    is_self_mention: false,
+                    user_id: None,
+                })
+                .collect::<Vec<_>>();
+            render_markdown(block, &mentions, &language_registry, None)
+        };
+
+        // The quote's highlight is split around the mention rather than overlapping it.
+        let rich_text = render("> hi «@user» there");
+        let (text, ranges) = marked_text_ranges("«▎ hi »«@user»« there»", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), Highlight::Quote),
+                (ranges[1].clone(), Highlight::Mention),
+                (ranges[2].clone(), Highlight::Quote),
+            ]
+        );
+
+        let rich_text = render("**«@user»** and **a «@user» b**");
+        let (text, ranges) = marked_text_ranges("«@user» and «a »«@user»« b»", false);
+        let bold = Highlight::Highlight(HighlightStyle {
+            font_weight: Some(FontWeight::BOLD),
+            ..Default::default()
+        });
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), Highlight::Mention),
+                (ranges[1].clone(), bold.clone()),
+                (ranges[2].clone(), Highlight::Mention),
+                (ranges[3].clone(), bold),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_file_location_matcher() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = "panicked at src/main.rs:42:5, see (lib.rs:7). \
+            Meet at 10:30am or 10:30, not in `src/lib.rs:1` or https://zed.dev/a.rs:3";
+        let rich_text = render_markdown_with_options(
+            block.into(),
+            &[],
+            &language_registry,
+            None,
+            &RenderMarkdownOptions {
+                file_location_matcher: Some(FileLocationMatcher::new(|location| {
+                    Some(format!(
+                        "file://{}#{}:{}",
+                        location.path,
+                        location.line,
+                        location.column.unwrap_or(1)
+                    ))
+                })),
+                ..Default::default()
+            },
+        );
+
+        let (text, ranges) = marked_text_ranges(
+            "panicked at «src/main.rs:42:5», see («lib.rs:7»). \
+            Meet at 10:30am or 10:30, not in src/lib.rs:1 or «https://zed.dev/a.rs:3»",
+            false,
+        );
+        assert_eq!(rich_text.text, text);
+        assert_eq!(rich_text.link_ranges, ranges);
+        assert_eq!(
+            rich_text.link_urls.as_ref(),
+            [
+                "file://src/main.rs#42:5",
+                "file://lib.rs#7:1",
+                "https://zed.dev/a.rs:3"
+            ]
+        );
+
+        assert_eq!(
+            find_file_locations("C:\\zed\\main.rs:3 and foo:1"),
+            vec![(
+                0..16,
+                FileLocation {
+                    path: "C:\\zed\\main.rs".into(),
+                    line: 3,
+                    column: None,
+                }
+            )]
+        );
+    }
+
+    #[gpui::test]
+    fn test_mention_matcher() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let (block, ranges) = marked_text_ranges(
+            "see #zed, «#ünïcode» and `#code` in https://zed.dev/#docs",
+            false,
+        );
+        let mentions = [Mention {
+            range: ranges[0].clone(),
+            is_self_mention: false,
+            user_id: Some(1),
+        }];
+        let options = RenderMarkdownOptions {
+            mention_matcher: Some(MentionMatcher::new(|text| {
+                let mut matches = Vec::new();
+                for (start, _) in text.match_indices('#') {
+                    let len = text[start + 1..]
+                        .find(|c: char| !c.is_alphanumeric())
+                        .unwrap_or(text.len() - start - 1);
+                    let name = &text[start + 1..start + 1 + len];
+                    matches.push((start..start + 1 + len, format!("zed://channel/{name}")));
+                }
+                matches
+            })),
+            ..Default::default()
+        };
+        let rich_text =
+            render_markdown_with_options(block, &mentions, &language_registry, None, &options);
+
+        let (text, ranges) = marked_text_ranges(
+            "see «#zed», «#ünïcode» and #code in «https://zed.dev/#docs»",
+            false,
+        );
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.link_ranges,
+            vec![ranges[0].clone(), ranges[2].clone()]
+        );
+        assert_eq!(
+            rich_text.link_urls.as_ref(),
+            ["zed://channel/zed", "https://zed.dev/#docs"]
+        );
+        assert_eq!(
+            rich_text.mention_ranges,
+            vec![(
+                ranges[1].clone(),
+                MentionMeta {
+                    is_self_mention: false,
+                    user_id: Some(1),
+                }
+            )]
+        );
+        assert!(rich_text
+            .highlights
+            .contains(&(ranges[0].clone(), Highlight::Mention)));
+
+        // Mentions within styled text keep its style, and aren't made lighter.
+        let rich_text = render_markdown_with_options(
+            "_see #zed_\n\n# in #zed".into(),
+            &[],
+            &language_registry,
+            None,
+            &options,
+        );
+        let (text, ranges) = marked_text_ranges("«see »«#zed»\n\n«in #zed»", false);
+        assert_eq!(rich_text.text, text);
+        let italic = HighlightStyle {
+            font_style: Some(FontStyle::Italic),
+            ..Default::default()
+        };
+        let heading = HighlightStyle {
+            font_weight: Some(FontWeight::BLACK),
+            ..Default::default()
+        };
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), Highlight::Highlight(italic)),
+                (
+                    ranges[1].clone(),
+                    Highlight::Highlight(HighlightStyle {
+                        font_weight: Some(FontWeight::BOLD),
+                        ..italic
+                    })
+                ),
+                (ranges[2].clone(), Highlight::Highlight(heading)),
+            ]
+        );
+    }
+
+    #[gpui::test]
+    fn test_expand_emoji_shortcodes() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let block = ":thumbsup: @alice :unknown: `:tada:` @bob :tada:";
+        let mentions = [
+            Mention {
+                range: 11..17,
+                is_self_mention: false,
+                user_id: None,
+            },
+            Mention {
+                range: 37..41,
+                is_self_mention: true,
+                user_id: None,
+            },
+        ];
+        let rich_text = render_markdown_with_options(
+            block.into(),
+            &mentions,
+            &language_registry,
+            None,
+            &RenderMarkdownOptions {
+                expand_emoji_shortcodes: true,
+                ..Default::default()
+            },
+        );
+
+        let (text, ranges) = marked_text_ranges("👍 «@alice» :unknown: :tada: «@bob» 🎉", false);
+        assert_eq!(rich_text.text, text);
+        assert_eq!(
+            rich_text.highlights,
+            vec![
+                (ranges[0].clone(), Highlight::Mention),
+                (ranges[1].clone(), Highlight::SelfMention),
+            ]
+        );
+
+        let rich_text = render_markdown(block.into(), &mentions, &language_registry, None);
+        assert_eq!(
+            rich_text.text,
+            ":thumbsup: @alice :unknown: :tada: @bob :tada:"
+        );
+    }
+
+    #[gpui::test]
+    async fn test_render_markdown_async(cx: &mut TestAppContext) {
+        let mut language_registry = LanguageRegistry::test();
+        language_registry.set_executor(cx.executor());
+        let language_registry = Arc::new(language_registry);
+        language_registry.set_theme(ThemeRegistry::default().get("One Dark").unwrap());
+        language_registry.register(
+            "/rust",
+            LanguageConfig {
+                name: "Rust".into(),
+                path_suffixes: vec!["rs".into()],
+                ..Default::default()
+            },
+            tree_sitter_rust::language(),
+            vec![],
+            |_| LanguageQueries {
+                highlights: Some("\"fn\" @keyword".into()),
+                ..Default::default()
+            },
+        );
+
+        let block = "```rust\nfn main() {}\n```".to_string();
+        let has_syntax_highlights = |rich_text: &RichText| {
+            rich_text
+                .highlights
+                .iter()
+                .any(|(_, highlight)| matches!(highlight, Highlight::Id(_)))
+        };
+
+        // The Rust grammar hasn't been loaded yet, so the synchronous path can't highlight it.
+        let rich_text = render_markdown(block.clone(), &[], &language_registry, None);
+        assert!(!has_syntax_highlights(&rich_text));
+
+        let rich_text = render_markdown_async(
+            block,
+            &[],
+            &language_registry,
+            None,
+            &RenderMarkdownOptions::default(),
+        )
+        .await;
+        assert_eq!(rich_text.text, "fn main() {}");
+        assert!(has_syntax_highlights(&rich_text));
+    }
+
+    fn rust_lang() -> Arc<Language> {
+        let language = Arc::new(
+            Language::new(
+                LanguageConfig {
+                    name: "Rust".into(),
+                    ..Default::default()
+                },
+                Some(tree_sitter_rust::language()),
+            )
+            .with_highlights_query("\"let\" @keyword")
+            .unwrap(),
+        );
+        language.set_theme(&SyntaxTheme::new_test([("keyword", gpui::red())]));
+        language
     }
 }
@@ -0,0 +1,151 @@
+use crate::replacements::{apply_replacements, is_protected, protected_ranges, Replacement};
+
+/// Rewrites each `^superscript^` and `~subscript~` in the given markdown with Unicode's
+/// superscript and subscript characters, as in `x^2^` and `H~2~O`. This is done because
+/// text within a block can't be rendered at different sizes or baselines. Returns the
+/// rewritten markdown, along with the replacements that were made.
+///
+/// As in Pandoc, the text between the delimiters can't contain whitespace, and `~~` is
+/// left for strikethrough. Text with a character that has no superscript or subscript
+/// form, such as a capital letter, is left untouched, as is text within code, HTML, or
+/// links.
+pub fn expand_scripts(block: &str) -> (String, Vec<Replacement>) {
+    let protected_ranges = protected_ranges(block);
+
+    let mut replacements = Vec::new();
+    let mut search_start = 0;
+    while let Some(open) = block[search_start..]
+        .find(['^', '~'])
+        .map(|ix| search_start + ix)
+    {
+        let delimiter = block.as_bytes()[open] as char;
+        let content_start = open + 1;
+        search_start = content_start;
+        if block[..open].ends_with([delimiter, '\\'])
+            || block[content_start..].starts_with(delimiter)
+        {
+            continue;
+        }
+        let Some(close) = block[content_start..]
+            .find(delimiter)
+            .map(|ix| content_start + ix)
+        else {
+            continue;
+        };
+
+        let to_script = if delimiter == '^' {
+            superscript
+        } else {
+            subscript
+        };
+        let content = &block[content_start..close];
+        let Some(scripts) = content
+            .char_indices()
+            .map(|(ix, c)| Some((content_start + ix, c.len_utf8(), to_script(c)?)))
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+        if scripts.is_empty()
+            || block[close + 1..].starts_with(delimiter)
+            || is_protected(&(open..close + 1), &protected_ranges)
+        {
+            continue;
+        }
+
+        replacements.push(Replacement {
+            range: open..content_start,
+            text: "",
+        });
+        for (ix, len, script) in scripts {
+            replacements.push(Replacement {
+                range: ix..ix + len,
+                text: script,
+            });
+        }
+        replacements.push(Replacement {
+            range: close..close + 1,
+            text: "",
+        });
+        search_start = close + 1;
+    }
+
+    (apply_replacements(block, &replacements), replacements)
+}
+
+fn superscript(c: char) -> Option<&'static str> {
+    Some(match c {
+        '0' => "⁰",
+        '1' => "¹",
+        '2' => "²",
+        '3' => "³",
+        '4' => "⁴",
+        '5' => "⁵",
+        '6' => "⁶",
+        '7' => "⁷",
+        '8' => "⁸",
+        '9' => "⁹",
+        '+' => "⁺",
+        '-' => "⁻",
+        '=' => "⁼",
+        '(' => "⁽",
+        ')' => "⁾",
+        'i' => "ⁱ",
+        'n' => "ⁿ",
+        _ => return None,
+    })
+}
+
+fn subscript(c: char) -> Option<&'static str> {
+    Some(match c {
+        '0' => "₀",
+        '1' => "₁",
+        '2' => "₂",
+        '3' => "₃",
+        '4' => "₄",
+        '5' => "₅",
+        '6' => "₆",
+        '7' => "₇",
+        '8' => "₈",
+        '9' => "₉",
+        '+' => "₊",
+        '-' => "₋",
+        '=' => "₌",
+        '(' => "₍",
+        ')' => "₎",
+        'a' => "ₐ",
+        'e' => "ₑ",
+        'h' => "ₕ",
+        'k' => "ₖ",
+        'l' => "ₗ",
+        'm' => "ₘ",
+        'n' => "ₙ",
+        'o' => "ₒ",
+        'p' => "ₚ",
+        's' => "ₛ",
+        't' => "ₜ",
+        'x' => "ₓ",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_scripts() {
+        let (text, _) = expand_scripts("H~2~O, x^2^ + y^-1^, and C~n~H~2n+2~");
+        assert_eq!(text, "H₂O, x² + y⁻¹, and CₙH₂ₙ₊₂");
+
+        for block in [
+            "~~struck~~",
+            "a ~b c~ d",
+            "x^Y^",
+            "\\^2^",
+            "`x^2^` and [H~2~O](https://example.com)",
+        ] {
+            assert_eq!(expand_scripts(block), (block.to_string(), vec![]));
+        }
+    }
+}
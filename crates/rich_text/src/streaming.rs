@@ -0,0 +1,225 @@
+use crate::{front_matter, RenderMarkdownOptions, RichText, RichTextBuilder};
+use language::{Language, LanguageRegistry};
+use std::{ops::Range, sync::Arc};
+
+/// Renders markdown that arrives incrementally, such as a streamed assistant response.
+///
+/// Once a top-level block is followed by another one, it can no longer change, so its
+/// rendering is kept and only the text from the start of the last block onwards is
+/// re-parsed and appended to it when more text arrives. Link reference definitions and footnotes are only
+/// resolved within the stable blocks they appear in and the trailing unstable text.
+pub struct StreamingRichText {
+    source: String,
+    /// The length of the prefix of `source` whose blocks have been rendered into `stable`.
+    stable_len: usize,
+    stable: RichText,
+    rich_text: RichText,
+    language_registry: Arc<LanguageRegistry>,
+    language: Option<Arc<Language>>,
+    options: RenderMarkdownOptions,
+}
+
+impl StreamingRichText {
+    pub fn new(
+        language_registry: Arc<LanguageRegistry>,
+        language: Option<Arc<Language>>,
+        options: RenderMarkdownOptions,
+    ) -> Self {
+        Self {
+            source: String::new(),
+            stable_len: 0,
+            stable: RichTextBuilder::new().build(),
+            rich_text: RichTextBuilder::new().build(),
+            language_registry,
+            language,
+            options,
+        }
+    }
+
+    /// The markdown that has been pushed so far.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The rendering of all of the markdown that has been pushed so far.
+    pub fn rich_text(&self) -> &RichText {
+        &self.rich_text
+    }
+
+    pub fn push_str(&mut self, chunk: &str) {
+        self.source.push_str(chunk);
+
+        let mut unstable_start =
+            self.stable_len + last_block_start(&self.source[self.stable_len..]);
+        // Front matter is parsed as ordinary blocks, such as a rule for its opening `---`,
+        // so it's kept unstable until it's closed or ruled out, and then committed whole.
+        if self.stable_len == 0 && self.options.strip_front_matter {
+            let front_matter_len = match front_matter::parse_front_matter(&self.source) {
+                Some((_, len)) => len,
+                None if front_matter::is_unclosed_front_matter(&self.source) => usize::MAX,
+                None => 0,
+            };
+            if unstable_start < front_matter_len {
+                unstable_start = 0;
+            }
+        }
+        if unstable_start > self.stable_len {
+            let (blocks, placeholder_len) = self.render(self.stable_len..unstable_start);
+            self.stable.append(blocks, placeholder_len);
+            self.stable_len = unstable_start;
+        }
+
+        let mut rich_text = self.stable.clone();
+        let (unstable, placeholder_len) = self.render(self.stable_len..self.source.len());
+        rich_text.append(unstable, placeholder_len);
+        self.rich_text = rich_text;
+    }
+
+    /// Renders the given range of the source, to be appended to the stable text. Blocks are
+    /// separated from the text before them based on their kind, such as by a blank line
+    /// before a paragraph but only a newline before a list, so a placeholder stands in for
+    /// any stable text. Returns the rendering along with the length of its placeholder.
+    fn render(&self, range: Range<usize>) -> (RichText, usize) {
+        let mut builder = RichTextBuilder::new();
+        // The stable text never ends with whitespace, since it's trimmed when built.
+        let placeholder = if self.stable.text.is_empty() { "" } else { "." };
+        builder.push_plain(placeholder);
+        // Front matter can only appear before the first stable block.
+        let options = RenderMarkdownOptions {
+            strip_front_matter: self.options.strip_front_matter && range.start == 0,
+            ..self.options.clone()
+        };
+        builder.push_markdown(
+            &self.source[range],
+            &[],
+            &self.language_registry,
+            self.language.as_ref(),
+            &options,
+        );
+        (builder.build(), placeholder.len())
+    }
+}
+
+/// Returns the offset of the last top-level block in the given markdown, which may still
+/// be incomplete. Everything before it consists of complete blocks.
+fn last_block_start(source: &str) -> usize {
+    use pulldown_cmark::{Event, Options, Parser};
+
+    let mut depth = 0;
+    let mut last_start = 0;
+    let mut previous_was_html = false;
+    for (event, range) in Parser::new_ext(source, Options::all()).into_offset_iter() {
+        let is_top_level = depth == 0;
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+        if !is_top_level || matches!(event, Event::End(_)) {
+            continue;
+        }
+
+        // Block-level HTML is emitted line by line, and the lines of a single HTML block
+        // must be parsed together.
+        let is_html = matches!(event, Event::Html(_));
+        if !(is_html && previous_was_html) {
+            // Include the block's indentation, which determines how it's parsed.
+            last_start = source[..range.start].rfind('\n').map_or(0, |ix| ix + 1);
+        }
+        previous_was_html = is_html;
+    }
+    last_start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_markdown_with_options;
+    use collections::HashMap;
+    use pretty_assertions::assert_eq;
+
+    #[gpui::test]
+    fn test_streaming_rich_text() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let source = "# Title\n\nSome **bold** text with a [link](https://zed.dev).\n\n- one\n- two\n\n  more\n\n```rust\nfn main() {\n\n    let x = 1;\n}\n```\n\n| a | b |\n|---|---|\n| c | d |\n\n<div>\n\nhtml\n</div>\n\n    indented\n\ndone";
+
+        let mut streaming = StreamingRichText::new(
+            language_registry.clone(),
+            None,
+            RenderMarkdownOptions::default(),
+        );
+        for (ix, ch) in source.char_indices() {
+            streaming.push_str(&ch.to_string());
+
+            let prefix = &source[..ix + ch.len_utf8()];
+            let expected = render_markdown_with_options(
+                prefix.to_string(),
+                &[],
+                &language_registry,
+                None,
+                &RenderMarkdownOptions::default(),
+            );
+            let actual = streaming.rich_text();
+            assert_eq!(actual.text, expected.text, "text for {prefix:?}");
+            assert_eq!(
+                actual.highlights, expected.highlights,
+                "highlights for {prefix:?}"
+            );
+            assert_eq!(
+                actual.link_ranges, expected.link_ranges,
+                "links for {prefix:?}"
+            );
+        }
+        assert_eq!(streaming.source(), source);
+        assert!(streaming.stable_len > 0);
+    }
+
+    #[gpui::test]
+    fn test_streaming_front_matter() {
+        let language_registry = Arc::new(LanguageRegistry::test());
+        let options = RenderMarkdownOptions {
+            strip_front_matter: true,
+            ..Default::default()
+        };
+        let render = |chunks: &[&str]| {
+            let mut streaming =
+                StreamingRichText::new(language_registry.clone(), None, options.clone());
+            for chunk in chunks {
+                streaming.push_str(chunk);
+            }
+            streaming.rich_text
+        };
+
+        let rich_text = render(&["---\n", "title: x\n---\n"]);
+        assert_eq!(rich_text.text, "");
+        assert_eq!(
+            rich_text.front_matter,
+            Some(HashMap::from_iter([("title".to_string(), "x".to_string())]))
+        );
+
+        for chunks in [
+            &["---\n", "title: x\n---\n", "\n# Intro\n\ntext"][..],
+            &["---\n", "A heading\n---\n\n", "text"],
+            &["---\n\n", "text\n\nmore"],
+        ] {
+            let source = chunks.concat();
+            let expected = render_markdown_with_options(
+                source.clone(),
+                &[],
+                &language_registry,
+                None,
+                &options,
+            );
+            let actual = render(chunks);
+            assert_eq!(actual.text, expected.text, "text for {source:?}");
+            assert_eq!(
+                actual.highlights, expected.highlights,
+                "highlights for {source:?}"
+            );
+            assert_eq!(
+                actual.front_matter, expected.front_matter,
+                "front matter for {source:?}"
+            );
+        }
+    }
+}
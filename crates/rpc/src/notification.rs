@@ -71,6 +71,16 @@ impl Notification {
         serde_json::from_value(value).ok()
     }
 
+    /// The user whose action caused this notification.
+    pub fn actor_id(&self) -> u64 {
+        match self {
+            Self::ContactRequest { sender_id } => *sender_id,
+            Self::ContactRequestAccepted { responder_id } => *responder_id,
+            Self::ChannelInvitation { inviter_id, .. } => *inviter_id,
+            Self::ChannelMessageMention { sender_id, .. } => *sender_id,
+        }
+    }
+
     pub fn all_variant_names() -> &'static [&'static str] {
         Self::VARIANTS
     }